@@ -0,0 +1,1823 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::{AsmError, Expansion, Location};
+use crate::expr::{self, Expr, Reloc, Value};
+use crate::instructions::{AddressMode, Instruction, Opcode, MNEMONICS};
+use crate::lexer::{tokenize, Spanned, Token, Tokens};
+use crate::macros::{split_args, substitute, Block, BlockKind, Condition, Line, Macro};
+use crate::object::{Deferred, Definition, Fixup, FixupKind, Object, Section, Site};
+
+/// How deep `.include`s and macro expansions may nest before they're assumed
+/// to be recursive.
+const MAX_DEPTH: usize = 16;
+
+/// Most times a `.repeat` may assemble its body, enough to fill the address
+/// space with one byte lines.
+const MAX_REPEAT: i64 = 0x10000;
+
+/// Operand syntax, before it's matched against the modes the mnemonic has.
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    /// No operand at all
+    None,
+    /// `A`
+    Accumulator,
+    /// `#value`
+    Immediate(Expr),
+    /// `value`, zero page or absolute depending on its size
+    Direct(Expr),
+    /// `value,X`
+    DirectX(Expr),
+    /// `value,Y`
+    DirectY(Expr),
+    /// `(value)`
+    Indirect(Expr),
+    /// `(value,X)`
+    IndirectX(Expr),
+    /// `(value),Y`
+    IndirectY(Expr),
+}
+
+fn parse_operand(tokens: &mut Tokens) -> Result<Operand, AsmError> {
+    if tokens.is_empty() {
+        return Ok(Operand::None);
+    }
+    if tokens.peek_second().is_none() && tokens.eat_register("A") {
+        return Ok(Operand::Accumulator);
+    }
+    if tokens.eat_punct("#") {
+        return expr::parse(tokens).map(Operand::Immediate);
+    }
+
+    let start = tokens.position();
+    if tokens.eat_punct("(") {
+        let value = expr::parse(tokens)?;
+        if tokens.eat_punct(",") {
+            if !tokens.eat_register("X") {
+                return Err(AsmError::new(tokens.location(), "expected 'X'"));
+            }
+            tokens.expect_punct(")")?;
+            return Ok(Operand::IndirectX(value));
+        }
+        tokens.expect_punct(")")?;
+        if tokens.is_empty() {
+            return Ok(Operand::Indirect(value));
+        }
+        if tokens.eat_punct(",") {
+            if !tokens.eat_register("Y") {
+                return Err(AsmError::new(tokens.location(), "expected 'Y'"));
+            }
+            return Ok(Operand::IndirectY(value));
+        }
+        // Something like `(base + 1) * 2`, an expression rather than
+        // indirection
+        tokens.rewind(start);
+    }
+
+    let value = expr::parse(tokens)?;
+    if tokens.eat_punct(",") {
+        if tokens.eat_register("X") {
+            return Ok(Operand::DirectX(value));
+        }
+        if tokens.eat_register("Y") {
+            return Ok(Operand::DirectY(value));
+        }
+        return Err(AsmError::new(tokens.location(), "expected 'X' or 'Y'"));
+    }
+    Ok(Operand::Direct(value))
+}
+
+impl Operand {
+    fn expr(&self) -> Option<&Expr> {
+        match self {
+            Operand::None | Operand::Accumulator => None,
+            Operand::Immediate(expr)
+            | Operand::Direct(expr)
+            | Operand::DirectX(expr)
+            | Operand::DirectY(expr)
+            | Operand::Indirect(expr)
+            | Operand::IndirectX(expr)
+            | Operand::IndirectY(expr) => Some(expr),
+        }
+    }
+}
+
+pub(crate) fn byte(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => Err(format!("value {} doesn't fit in a byte", value)),
+    }
+}
+
+pub(crate) fn word(value: i64) -> Result<u16, String> {
+    match value {
+        -32768..=65535 => Ok(value as u16),
+        _ => Err(format!("value {} doesn't fit in a word", value)),
+    }
+}
+
+fn supports(mnemonic: &str, mode: &AddressMode) -> bool {
+    Opcode::opcode_for(mnemonic, mode).is_some()
+}
+
+/// Parses `name [param, ...]` after `.macro`.
+fn macro_signature(tokens: &mut Tokens) -> Result<(String, Vec<String>), AsmError> {
+    let name = tokens.expect_ident()?.to_string();
+    let mut params = Vec::new();
+    if !tokens.is_empty() {
+        loop {
+            params.push(tokens.expect_ident()?.to_string());
+            if !tokens.eat_punct(",") {
+                break;
+            }
+        }
+    }
+    Ok((name, params))
+}
+
+/// Output of [`Assembler::assemble`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// Address of the first byte, set by the first `.org`
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    /// Labels and constants. Local labels are keyed `global@local`.
+    pub symbols: BTreeMap<String, i64>,
+    /// Which of the symbols are labels rather than constants
+    pub labels: BTreeSet<String>,
+    pub listing: Vec<ListingLine>,
+}
+
+/// One source line and what it assembled to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    /// Address at the start of the line
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// The instruction as [`Opcode`] displays it, if the line is one
+    pub instruction: Option<String>,
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub source: String,
+}
+
+/// Two-pass 6502 assembler.
+///
+/// Source is one statement per line: an optional `label:` (or `@local:`,
+/// scoped to the label before it), then an instruction or directive.
+/// `name = expr` defines a constant. Operands are expressions over numbers,
+/// symbols and `*` (the address of the line), with C operators plus `<x` and
+/// `>x` for the low and high byte.
+///
+/// | Directive | |
+/// |---|---|
+/// | `.org address` | Sets the address, padding with zeroes if code came before |
+/// | `.byte value\|"text", ...` | Emits bytes |
+/// | `.word value, ...` | Emits little-endian words |
+/// | `.res count [, fill]` | Reserves `count` bytes of `fill`, default zero |
+/// | `.incbin "file" [, offset [, length]]` | Emits a file's contents |
+/// | `.include "file"` | Assembles another source file in place |
+/// | `.define NAME tokens...` | Replaces `NAME` with `tokens` from here on |
+/// | `.if value` / `.else` / `.endif` | Assembles lines if `value` isn't zero |
+/// | `.ifdef name` / `.ifndef name` | Tests for a symbol, define or macro |
+/// | `.repeat count [, var]` / `.endrepeat` | Assembles lines `count` times, with `var` counting from zero |
+/// | `.macro name [param, ...]` / `.endmacro` | Defines a macro, invoked as `name arg, ...` |
+/// | `.segment "name" [: zeropage]` | Switches segment in an object module |
+/// | `.export name, ...` | Makes symbols visible to other object modules |
+/// | `.import name, ...` / `.importzp name, ...` | Uses symbols another module exports, in zero page for `.importzp` |
+///
+/// Files are looked up next to the including file, then in each
+/// [`Assembler::include_dir`]. `@labels` inside a macro are private to each
+/// expansion, and errors in one point into the definition, listing the
+/// invocations in [`AsmError::expansions`].
+///
+/// [`Assembler::assemble_object`] makes a relocatable [`Object`] for the
+/// [`Linker`](crate::Linker) instead. Code goes in the `CODE` segment until a
+/// `.segment` picks another, and there's no `.org` since the link config
+/// places segments. Labels in `ZEROPAGE` or a segment declared `: zeropage`
+/// are zero page addresses.
+///
+/// ```
+/// use asm6502::Assembler;
+///
+/// let program = Assembler::new()
+///     .assemble(
+///         "
+///         .org $8000
+/// reset:  LDX #0
+/// @loop:  LDA message,X
+///         BEQ @done
+///         INX
+///         BNE @loop
+/// @done:  RTS
+/// message: .byte \"hi\", 0
+///         .word reset
+///         ",
+///     )
+///     .unwrap();
+/// assert_eq!(program.origin, 0x8000);
+/// assert_eq!(program.symbols["reset@done"], 0x800A);
+/// assert_eq!(&program.bytes[..5], &[0xA2, 0x00, 0xBD, 0x0B, 0x80]);
+/// assert_eq!(&program.bytes[11..], b"hi\0\x00\x80");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Assembler {
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory to search for `.include` and `.incbin` files.
+    pub fn include_dir(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// Assembles `source`, returning every error found. Relative `.include`
+    /// paths are resolved against the working directory.
+    pub fn assemble(&self, source: &str) -> Result<Program, Vec<AsmError>> {
+        self.run(source, None, false, |pass| pass.program())
+    }
+
+    pub fn assemble_file(&self, path: impl AsRef<Path>) -> Result<Program, Vec<AsmError>> {
+        let path = path.as_ref();
+        self.run(&read(path)?, Some(path), false, |pass| pass.program())
+    }
+
+    /// Assembles `source` into an object module for the
+    /// [`Linker`](crate::Linker).
+    pub fn assemble_object(&self, source: &str) -> Result<Object, Vec<AsmError>> {
+        self.run(source, None, true, |pass| pass.object())
+    }
+
+    pub fn assemble_object_file(&self, path: impl AsRef<Path>) -> Result<Object, Vec<AsmError>> {
+        let path = path.as_ref();
+        self.run(&read(path)?, Some(path), true, |pass| pass.object())
+    }
+
+    fn run<T>(
+        &self,
+        source: &str,
+        file: Option<&Path>,
+        object: bool,
+        output: impl FnOnce(Pass) -> T,
+    ) -> Result<T, Vec<AsmError>> {
+        let frame = Frame {
+            file: file.map(Path::to_path_buf),
+            ..Frame::default()
+        };
+
+        // A pass can resolve symbols defined in terms of ones the pass before
+        // only found further down, so repeat until nothing new turns up.
+        // Sizes never depend on forward references, so addresses come out the
+        // same in every pass.
+        let mut symbols = BTreeMap::new();
+        loop {
+            let mut pass = Pass::new(self, &symbols, false, object);
+            pass.source(source, &frame);
+            let done = pass.symbols.len() == symbols.len();
+            symbols = pass.symbols;
+            if done {
+                break;
+            }
+        }
+
+        let mut pass = Pass::new(self, &symbols, true, object);
+        pass.source(source, &frame);
+        pass.finish();
+        if !pass.errors.is_empty() {
+            return Err(pass.errors);
+        }
+        Ok(output(pass))
+    }
+}
+
+fn read(path: &Path) -> Result<String, Vec<AsmError>> {
+    fs::read_to_string(path).map_err(|err| {
+        let location = Location { line: 1, column: 1 };
+        vec![AsmError::new(location, format!("can't read: {}", err)).in_file(Some(path))]
+    })
+}
+
+/// Assembles `source` from address 0, see [`Assembler`] for the syntax.
+///
+/// ```
+/// let bytes = asm6502::assemble("LDX #$08\nloop: DEX\nBNE loop").unwrap();
+/// assert_eq!(bytes, [0xA2, 0x08, 0xCA, 0xD0, 0xFD]);
+/// ```
+pub fn assemble(source: &str) -> Result<Vec<u8>, Vec<AsmError>> {
+    Assembler::new()
+        .assemble(source)
+        .map(|program| program.bytes)
+}
+
+/// A symbol's value in one pass.
+#[derive(Debug, Clone, Copy)]
+struct Symbol {
+    value: i64,
+    /// Computed from forward references, see [`Value::forward`]
+    forward: bool,
+    reloc: Reloc,
+    label: bool,
+    /// Section of a label in an object module
+    section: Option<usize>,
+}
+
+/// Where the lines being assembled come from.
+#[derive(Debug, Clone, Default)]
+struct Frame {
+    file: Option<PathBuf>,
+    /// Macro invocations being expanded, innermost first
+    expansions: Vec<Expansion>,
+    /// Includes and expansions nested so far
+    depth: usize,
+}
+
+/// One pass over the source.
+struct Pass<'a> {
+    assembler: &'a Assembler,
+    /// Symbols the previous pass defined, for forward references
+    previous: &'a BTreeMap<String, Symbol>,
+    /// Whether to report errors and undefined symbols, which earlier passes
+    /// ignore
+    last: bool,
+    symbols: BTreeMap<String, Symbol>,
+    /// Global label `@local` labels belong to
+    scope: String,
+    origin: Option<u16>,
+    pc: i64,
+    /// Address of the current line, for `*`
+    line_start: i64,
+    bytes: Vec<u8>,
+    errors: Vec<AsmError>,
+    defines: BTreeMap<String, Vec<Token>>,
+    macros: BTreeMap<String, Rc<Macro>>,
+    /// Expansions so far, numbering their `@labels`
+    expansion_count: usize,
+    /// `.macro` or `.repeat` being collected
+    block: Option<Block>,
+    conditions: Vec<Condition>,
+    /// Kept on the last pass only
+    listing: Vec<ListingLine>,
+    /// Entry in `listing` for the line being assembled
+    listing_line: Option<usize>,
+    /// Assembling an object module
+    object: bool,
+    /// Segments of an object module. The current one's bytes and fixups are
+    /// in `bytes` and `fixups` while it's being assembled.
+    sections: Vec<Section>,
+    section: usize,
+    fixups: Vec<Fixup>,
+    /// How to work out relocatable constants
+    deferred: BTreeMap<String, Deferred>,
+    imports: BTreeMap<String, Site>,
+    exports: Vec<(String, Site)>,
+}
+
+impl<'a> Pass<'a> {
+    fn new(
+        assembler: &'a Assembler,
+        previous: &'a BTreeMap<String, Symbol>,
+        last: bool,
+        object: bool,
+    ) -> Self {
+        Pass {
+            assembler,
+            previous,
+            last,
+            symbols: BTreeMap::new(),
+            scope: String::new(),
+            origin: None,
+            pc: 0,
+            line_start: 0,
+            bytes: Vec::new(),
+            errors: Vec::new(),
+            defines: BTreeMap::new(),
+            macros: BTreeMap::new(),
+            expansion_count: 0,
+            block: None,
+            conditions: Vec::new(),
+            listing: Vec::new(),
+            listing_line: None,
+            object,
+            sections: vec![Section::new("CODE", false)],
+            section: 0,
+            fixups: Vec::new(),
+            deferred: BTreeMap::new(),
+            imports: BTreeMap::new(),
+            exports: Vec::new(),
+        }
+    }
+
+    fn program(self) -> Program {
+        Program {
+            origin: self.origin.unwrap_or(0),
+            bytes: self.bytes,
+            labels: self
+                .symbols
+                .iter()
+                .filter(|(_, symbol)| symbol.label)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            symbols: self
+                .symbols
+                .into_iter()
+                .map(|(name, symbol)| (name, symbol.value))
+                .collect(),
+            listing: self.listing,
+        }
+    }
+
+    fn object(mut self) -> Object {
+        self.park_section();
+        let mut deferred = self.deferred;
+        let imports = self.imports;
+        let symbols = self
+            .symbols
+            .into_iter()
+            .map(|(key, symbol)| {
+                let definition = if imports.contains_key(&key) {
+                    Definition::Import
+                } else if let Some(section) = symbol.section {
+                    Definition::Label {
+                        section,
+                        offset: symbol.value,
+                    }
+                } else if let Some(value) = deferred.remove(&key) {
+                    Definition::Expr(value)
+                } else {
+                    Definition::Constant(symbol.value)
+                };
+                (key, definition)
+            })
+            .collect();
+        Object {
+            sections: self.sections,
+            symbols,
+            exports: self.exports,
+            imports,
+        }
+    }
+
+    fn report(&mut self, error: AsmError, frame: &Frame) {
+        if self.last {
+            self.errors.push(
+                error
+                    .in_file(frame.file.as_deref())
+                    .expanded_from(&frame.expansions),
+            );
+        }
+    }
+
+    fn source(&mut self, source: &str, frame: &Frame) {
+        for (index, text) in source.lines().enumerate() {
+            let line_no = index + 1;
+            match tokenize(text, line_no) {
+                Ok(tokens) => {
+                    let end = Location {
+                        line: line_no,
+                        column: text.chars().count() + 1,
+                    };
+                    let line = Line {
+                        text: text.to_string(),
+                        tokens,
+                        end,
+                    };
+                    self.line(&line, frame);
+                }
+                Err(error) => self.report(error, frame),
+            }
+        }
+    }
+
+    /// Reports blocks left open at the end of the source.
+    fn finish(&mut self) {
+        if let Some(block) = self.block.take() {
+            let frame = Frame {
+                file: block.file.clone(),
+                ..Frame::default()
+            };
+            let message = format!(
+                "'{}' without '{}'",
+                block.directive(),
+                block.end_directive()
+            );
+            self.report(AsmError::new(block.location, message), &frame);
+        }
+        for condition in std::mem::take(&mut self.conditions) {
+            let frame = Frame {
+                file: condition.file,
+                ..Frame::default()
+            };
+            let error = AsmError::new(condition.location, "'.if' without '.endif'");
+            self.report(error, &frame);
+        }
+        if self.last {
+            for (name, site) in &self.exports {
+                if !self.symbols.contains_key(name) {
+                    let error = site.error(format!("exported symbol '{}' isn't defined", name));
+                    self.errors.push(error);
+                } else if self.imports.contains_key(name) {
+                    let error = site.error(format!("'{}' is imported, so can't be exported", name));
+                    self.errors.push(error);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, line: &Line, frame: &Frame) {
+        // Lines a macro or `.include` on this one expands to get their own
+        // entries
+        let enclosing = self.listing_line;
+        if self.last {
+            self.listing.push(ListingLine {
+                address: self.pc as u16,
+                bytes: Vec::new(),
+                instruction: None,
+                file: frame.file.clone(),
+                line: line.end.line,
+                source: line.text.clone(),
+            });
+            self.listing_line = Some(self.listing.len() - 1);
+        }
+        if let Err(error) = self.statement(line, frame) {
+            self.report(error, frame);
+        }
+        self.listing_line = enclosing;
+    }
+
+    fn active(&self) -> bool {
+        self.conditions
+            .last()
+            .is_none_or(|condition| condition.active)
+    }
+
+    fn substitute_defines(&self, tokens: &[Spanned]) -> Vec<Spanned> {
+        if self.defines.is_empty() {
+            return tokens.to_vec();
+        }
+        substitute(tokens, &|name| self.defines.get(name).cloned())
+    }
+
+    fn symbol_key(&self, name: &str) -> String {
+        if name.starts_with('@') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn resolve(&self, name: &str, location: Location) -> Result<Option<Value>, AsmError> {
+        let key = self.symbol_key(name);
+        if let Some(symbol) = self.symbols.get(&key) {
+            return Ok(Some(Value {
+                value: symbol.value,
+                forward: symbol.forward,
+                reloc: symbol.reloc,
+            }));
+        }
+        if let Some(symbol) = self.previous.get(&key) {
+            return Ok(Some(Value {
+                value: symbol.value,
+                forward: true,
+                reloc: symbol.reloc,
+            }));
+        }
+        if self.last {
+            Err(AsmError::new(
+                location,
+                format!("undefined symbol '{}'", name),
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// How addresses in the current section are relocated.
+    fn pc_reloc(&self) -> Reloc {
+        if !self.object {
+            Reloc::None
+        } else if self.sections[self.section].zeropage {
+            Reloc::ZeroPage
+        } else {
+            Reloc::Absolute
+        }
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<Option<Value>, AsmError> {
+        let pc = Value {
+            value: self.line_start,
+            forward: false,
+            reloc: self.pc_reloc(),
+        };
+        expr.eval(pc, &|name, location| self.resolve(name, location))
+    }
+
+    /// Value of `expr`, or `None` if it can't be known until a later pass.
+    /// Relocatable values come out as zero, for the linker to fill in.
+    fn value(&self, expr: &Expr) -> Result<Option<i64>, AsmError> {
+        Ok(self.eval(expr)?.map(|value| match value.reloc {
+            Reloc::None => value.value,
+            _ => 0,
+        }))
+    }
+
+    /// Parses an expression which has to only depend on what's defined
+    /// above it, so every pass sees the same, and not on where the linker
+    /// puts things. Anything that affects sizes has to be.
+    fn expect_settled(&self, tokens: &mut Tokens) -> Result<i64, AsmError> {
+        let location = tokens.location();
+        let expr = expr::parse(tokens)?;
+        match self.eval(&expr)? {
+            Some(value) if value.reloc != Reloc::None => Err(AsmError::new(
+                location,
+                "value isn't known until the program is linked",
+            )),
+            Some(value) if !value.forward => Ok(value.value),
+            _ => Err(AsmError::new(
+                location,
+                "value must be defined before it's used here",
+            )),
+        }
+    }
+
+    fn site(&self, location: Location, frame: &Frame) -> Site {
+        Site {
+            file: frame.file.clone(),
+            location,
+            expansions: frame.expansions.clone(),
+        }
+    }
+
+    /// `expr` for the linker to evaluate, with the symbols that were known
+    /// when assembling replaced by their values.
+    fn defer(&self, expr: &Expr, location: Location, frame: &Frame) -> Deferred {
+        let expr = expr.map_symbols(&|name, location| match self.resolve(name, location) {
+            Ok(Some(value)) if value.reloc == Reloc::None => Expr::Number(value.value),
+            _ => Expr::Symbol {
+                name: self.symbol_key(name),
+                location,
+            },
+        });
+        Deferred {
+            expr,
+            section: self.section,
+            pc: self.line_start,
+            site: self.site(location, frame),
+        }
+    }
+
+    /// Leaves the bytes at `offset` in the current section to the linker if
+    /// they depend on where it places things. Branches always do, since the
+    /// offset to their target depends on their own address.
+    fn relocate(
+        &mut self,
+        expr: &Expr,
+        kind: FixupKind,
+        offset: i64,
+        location: Location,
+        frame: &Frame,
+    ) -> Result<(), AsmError> {
+        if !self.object {
+            return Ok(());
+        }
+        let relocatable = self
+            .eval(expr)?
+            .is_some_and(|value| value.reloc != Reloc::None);
+        if relocatable || kind == FixupKind::Branch {
+            let value = self.defer(expr, location, frame);
+            self.fixups.push(Fixup {
+                offset,
+                kind,
+                value,
+            });
+        }
+        Ok(())
+    }
+
+    /// Moves the current section's bytes and fixups back into `sections`.
+    fn park_section(&mut self) {
+        let section = &mut self.sections[self.section];
+        section.bytes = mem::take(&mut self.bytes);
+        section.fixups = mem::take(&mut self.fixups);
+    }
+
+    fn switch_section(
+        &mut self,
+        name: &str,
+        zeropage: bool,
+        location: Location,
+    ) -> Result<(), AsmError> {
+        let index = match self
+            .sections
+            .iter()
+            .position(|section| section.name == name)
+        {
+            Some(index) if zeropage && !self.sections[index].zeropage => {
+                return Err(AsmError::new(
+                    location,
+                    format!("segment '{}' already holds absolute addresses", name),
+                ))
+            }
+            Some(index) => index,
+            None => {
+                self.sections.push(Section::new(name, zeropage));
+                self.sections.len() - 1
+            }
+        };
+        self.park_section();
+        self.section = index;
+        let section = &mut self.sections[index];
+        self.bytes = mem::take(&mut section.bytes);
+        self.fixups = mem::take(&mut section.fixups);
+        self.pc = self.bytes.len() as i64;
+        Ok(())
+    }
+
+    /// Parses an expression and checks it fits in a byte.
+    fn byte_operand(&self, tokens: &mut Tokens) -> Result<u8, AsmError> {
+        let location = tokens.location();
+        let expr = expr::parse(tokens)?;
+        let value = self.value(&expr)?.unwrap_or(0);
+        byte(value).map_err(|message| AsmError::new(location, message))
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        value: Value,
+        label: bool,
+        location: Location,
+    ) -> Result<(), AsmError> {
+        let key = self.symbol_key(name);
+        if self.symbols.contains_key(&key) {
+            return Err(AsmError::new(
+                location,
+                format!("'{}' is already defined", name),
+            ));
+        }
+        self.symbols.insert(
+            key,
+            Symbol {
+                value: value.value,
+                forward: value.forward,
+                reloc: value.reloc,
+                label,
+                section: (label && self.object).then_some(self.section),
+            },
+        );
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8], location: Location) -> Result<(), AsmError> {
+        if self.pc + bytes.len() as i64 > 0x10000 {
+            return Err(AsmError::new(location, "code runs past $FFFF"));
+        }
+        self.origin.get_or_insert(self.pc as u16);
+        self.bytes.extend_from_slice(bytes);
+        if let Some(index) = self.listing_line {
+            self.listing[index].bytes.extend_from_slice(bytes);
+        }
+        self.pc += bytes.len() as i64;
+        Ok(())
+    }
+
+    fn statement(&mut self, line: &Line, frame: &Frame) -> Result<(), AsmError> {
+        self.line_start = self.pc;
+        let keyword = line
+            .tokens
+            .first()
+            .and_then(Spanned::ident)
+            .map(str::to_ascii_lowercase);
+        let keyword = keyword.as_deref();
+
+        if self.block.is_some() {
+            return self.record(line, keyword, frame);
+        }
+        if let Some(keyword @ (".if" | ".ifdef" | ".ifndef" | ".else" | ".endif")) = keyword {
+            return self.conditional(keyword, line, frame);
+        }
+        if !self.active() {
+            return Ok(());
+        }
+
+        let spanned = if keyword == Some(".define") {
+            line.tokens.clone()
+        } else {
+            self.substitute_defines(&line.tokens)
+        };
+        let mut tokens = Tokens::new(&spanned, line.end);
+
+        if let Some(first) = tokens.peek() {
+            let second = tokens.peek_second();
+            if let Some(name) = first.ident() {
+                if second.is_some_and(|token| token.is_punct(":")) {
+                    tokens.next();
+                    tokens.next();
+                    let pc = Value {
+                        value: self.pc,
+                        forward: false,
+                        reloc: self.pc_reloc(),
+                    };
+                    self.define(name, pc, true, first.location)?;
+                    if !name.starts_with('@') {
+                        self.scope = name.to_string();
+                    }
+                } else if second.is_some_and(|token| token.is_punct("=")) {
+                    tokens.next();
+                    tokens.next();
+                    let expr = expr::parse(&mut tokens)?;
+                    tokens.expect_end()?;
+                    let value = match self.eval(&expr)? {
+                        Some(value) => value,
+                        None => return Ok(()),
+                    };
+                    if value.reloc != Reloc::None {
+                        let deferred = self.defer(&expr, first.location, frame);
+                        self.deferred.insert(self.symbol_key(name), deferred);
+                    }
+                    return self.define(name, value, false, first.location);
+                }
+            }
+        }
+
+        let first = match tokens.next() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        match first.ident() {
+            Some(name) if name.starts_with('.') => self.directive(first, &mut tokens, frame)?,
+            Some(name) => match self.macros.get(name) {
+                Some(mac) => self.expand(mac.clone(), first, &mut tokens, frame)?,
+                None => self.instruction(first, &mut tokens, frame)?,
+            },
+            None => return Err(AsmError::new(first.location, "expected a mnemonic")),
+        }
+        tokens.expect_end()
+    }
+
+    /// Adds a line to the block being collected, or closes it.
+    fn record(
+        &mut self,
+        line: &Line,
+        keyword: Option<&str>,
+        frame: &Frame,
+    ) -> Result<(), AsmError> {
+        let block = self.block.as_mut().expect("no block to record into");
+        match keyword {
+            Some(".macro") | Some(".repeat") => block.nesting += 1,
+            Some(".endmacro") | Some(".endrepeat") if block.nesting > 0 => block.nesting -= 1,
+            Some(end @ ".endmacro") | Some(end @ ".endrepeat") => {
+                let block = self.block.take().expect("no block to close");
+                let first = &line.tokens[0];
+                if end != block.end_directive() {
+                    return Err(AsmError::new(
+                        first.location,
+                        format!("expected '{}'", block.end_directive()),
+                    ));
+                }
+                Tokens::new(&line.tokens[1..], line.end).expect_end()?;
+                return self.close(block, first.location, frame);
+            }
+            _ => {}
+        }
+        block.lines.push(line.clone());
+        Ok(())
+    }
+
+    fn close(&mut self, block: Block, location: Location, frame: &Frame) -> Result<(), AsmError> {
+        match block.kind {
+            // A `.macro` with a bad signature, already reported
+            BlockKind::Macro { name, .. } if name.is_empty() => {}
+            BlockKind::Macro { name, params } => {
+                if self.macros.contains_key(&name) {
+                    return Err(AsmError::new(
+                        location,
+                        format!("macro '{}' is already defined", name),
+                    ));
+                }
+                let mac = Macro {
+                    name: name.clone(),
+                    params,
+                    body: block.lines,
+                    file: block.file,
+                };
+                self.macros.insert(name, Rc::new(mac));
+            }
+            BlockKind::Repeat { count, var } => {
+                for index in 0..count {
+                    for line in &block.lines {
+                        let tokens = match &var {
+                            Some(var) => substitute(&line.tokens, &|name| {
+                                (name == var).then(|| vec![Token::Number(index)])
+                            }),
+                            None => line.tokens.clone(),
+                        };
+                        self.line(
+                            &Line {
+                                text: line.text.clone(),
+                                tokens,
+                                end: line.end,
+                            },
+                            frame,
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn conditional(&mut self, keyword: &str, line: &Line, frame: &Frame) -> Result<(), AsmError> {
+        let location = line.tokens[0].location;
+        let rest = self.substitute_defines(&line.tokens[1..]);
+        let mut tokens = Tokens::new(&rest, line.end);
+
+        match keyword {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let enclosing = self.active();
+                let taken = if enclosing {
+                    self.test(keyword, &line.tokens[1..], &mut tokens)
+                } else {
+                    Ok(false)
+                };
+                // Pushed even if the test failed, so `.endif` still matches
+                let active = *taken.as_ref().unwrap_or(&false);
+                self.conditions.push(Condition {
+                    active,
+                    done: active || !enclosing,
+                    seen_else: false,
+                    file: frame.file.clone(),
+                    location,
+                });
+                taken.map(|_| ())
+            }
+            ".else" => {
+                tokens.expect_end()?;
+                let condition = self
+                    .conditions
+                    .last_mut()
+                    .ok_or_else(|| AsmError::new(location, "'.else' without '.if'"))?;
+                if condition.seen_else {
+                    return Err(AsmError::new(location, "second '.else' for one '.if'"));
+                }
+                condition.seen_else = true;
+                condition.active = !condition.done;
+                condition.done = true;
+                Ok(())
+            }
+            _ => {
+                tokens.expect_end()?;
+                self.conditions
+                    .pop()
+                    .ok_or_else(|| AsmError::new(location, "'.endif' without '.if'"))?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether an `.if`, `.ifdef` or `.ifndef` holds. `raw` is the operand
+    /// before defines were substituted, for testing the names of defines.
+    fn test(&self, keyword: &str, raw: &[Spanned], tokens: &mut Tokens) -> Result<bool, AsmError> {
+        if keyword == ".if" {
+            let value = self.expect_settled(tokens)?;
+            tokens.expect_end()?;
+            return Ok(value != 0);
+        }
+
+        let end = tokens.location();
+        let mut tokens = Tokens::new(raw, end);
+        let name = tokens.expect_ident()?;
+        tokens.expect_end()?;
+        let defined = self.symbols.contains_key(&self.symbol_key(name))
+            || self.defines.contains_key(name)
+            || self.macros.contains_key(name);
+        Ok(defined == (keyword == ".ifdef"))
+    }
+
+    fn expand(
+        &mut self,
+        mac: Rc<Macro>,
+        first: &Spanned,
+        tokens: &mut Tokens,
+        frame: &Frame,
+    ) -> Result<(), AsmError> {
+        if frame.depth == MAX_DEPTH {
+            return Err(AsmError::new(first.location, "macros nested too deeply"));
+        }
+        self.expansion_count += 1;
+        let body = mac.expand(
+            split_args(tokens.rest()),
+            self.expansion_count,
+            first.location,
+        )?;
+
+        let invocation = Expansion {
+            name: mac.name.clone(),
+            file: frame.file.clone(),
+            location: first.location,
+        };
+        let inner = Frame {
+            file: mac.file.clone(),
+            expansions: std::iter::once(invocation)
+                .chain(frame.expansions.iter().cloned())
+                .collect(),
+            depth: frame.depth + 1,
+        };
+        for line in &body {
+            self.line(line, &inner);
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        first: &Spanned,
+        tokens: &mut Tokens,
+        frame: &Frame,
+    ) -> Result<(), AsmError> {
+        let name = first.ident().unwrap_or_default();
+        let mnemonic = name.to_ascii_uppercase();
+        if !MNEMONICS.contains(&mnemonic.as_str()) {
+            return Err(AsmError::new(
+                first.location,
+                format!("unknown mnemonic '{}'", name),
+            ));
+        }
+
+        let location = tokens.location();
+        let operand = parse_operand(tokens)?;
+        tokens.expect_end()?;
+
+        let mode = self.select_mode(&mnemonic, &operand, location)?;
+        let opcode =
+            Opcode::new(&mnemonic, mode).map_err(|err| AsmError::new(location, err.to_string()))?;
+        if let Some(index) = self.listing_line {
+            self.listing[index].instruction = Some(opcode.to_string());
+        }
+        if let Some(expr) = operand.expr() {
+            let kind = match mode {
+                AddressMode::Relative(_) => FixupKind::Branch,
+                _ if mode.size() == 2 => FixupKind::Byte,
+                _ => FixupKind::Word,
+            };
+            self.relocate(expr, kind, self.pc + 1, location, frame)?;
+        }
+        self.emit(&opcode.encode(), first.location)
+    }
+
+    /// Picks the addressing mode for an operand at `location`, preferring
+    /// zero page over absolute when the value isn't a forward reference, is
+    /// known to fit, and the instruction has a zero page form. Values only a
+    /// later pass or the linker can fill in count as zero.
+    fn select_mode(
+        &self,
+        mnemonic: &str,
+        operand: &Operand,
+        location: Location,
+    ) -> Result<AddressMode, AsmError> {
+        use AddressMode::*;
+
+        let error = |message: String| AsmError::new(location, message);
+        let value = |expr: &Expr| -> Result<i64, AsmError> { Ok(self.value(expr)?.unwrap_or(0)) };
+        let byte = |expr: &Expr| byte(value(expr)?).map_err(error);
+        let word = |expr: &Expr| word(value(expr)?).map_err(error);
+        let zero_or_absolute =
+            |expr: &Expr, zero: fn(u8) -> AddressMode, absolute: fn(u16) -> AddressMode| {
+                let zero_page = match self.eval(expr)? {
+                    None | Some(Value { forward: true, .. }) => false,
+                    Some(Value {
+                        reloc: Reloc::ZeroPage,
+                        ..
+                    }) => true,
+                    Some(Value { value, reloc, .. }) => {
+                        reloc == Reloc::None && (0..=0xFF).contains(&value)
+                    }
+                };
+                if zero_page && supports(mnemonic, &zero(0)) {
+                    byte(expr).map(zero)
+                } else {
+                    word(expr).map(absolute)
+                }
+            };
+
+        let mode = match operand {
+            Operand::None if supports(mnemonic, &Implicit) => Implicit,
+            // `ASL` on its own means `ASL A`
+            Operand::None | Operand::Accumulator => Accumulator,
+            Operand::Immediate(expr) => Immediate(byte(expr)?),
+            Operand::Direct(target) if supports(mnemonic, &Relative(0)) => {
+                match self.value(target)? {
+                    // The linker works out the offset once it knows where
+                    // the branch is
+                    Some(_) if self.object => Relative(0),
+                    Some(target) => {
+                        let offset = target - (self.pc + 2);
+                        match offset {
+                            -128..=127 => Relative(offset as i8),
+                            _ => {
+                                return Err(error(format!(
+                                    "branch target ${:04X} is {} bytes away, out of range",
+                                    target, offset
+                                )))
+                            }
+                        }
+                    }
+                    None => Relative(0),
+                }
+            }
+            Operand::Direct(expr) => zero_or_absolute(expr, Zero, Absolute)?,
+            Operand::DirectX(expr) => zero_or_absolute(expr, ZeroX, AbsoluteX)?,
+            Operand::DirectY(expr) => zero_or_absolute(expr, ZeroY, AbsoluteY)?,
+            Operand::Indirect(expr) => Indirect(word(expr)?),
+            Operand::IndirectX(expr) => IndirectX(byte(expr)?),
+            Operand::IndirectY(expr) => IndirectY(byte(expr)?),
+        };
+        Ok(mode)
+    }
+
+    /// Finds an `.include` or `.incbin` file, next to `file` or in an include
+    /// directory.
+    fn find_file(
+        &self,
+        name: &str,
+        file: Option<&Path>,
+        location: Location,
+    ) -> Result<PathBuf, AsmError> {
+        let beside = match file.and_then(Path::parent) {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        std::iter::once(beside)
+            .chain(self.assembler.include_dirs.iter().map(|dir| dir.join(name)))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| AsmError::new(location, format!("can't find '{}'", name)))
+    }
+
+    fn directive(
+        &mut self,
+        first: &Spanned,
+        tokens: &mut Tokens,
+        frame: &Frame,
+    ) -> Result<(), AsmError> {
+        let name = first.ident().unwrap_or_default();
+        let location = first.location;
+
+        match name.to_ascii_lowercase().as_str() {
+            ".org" if self.object => {
+                return Err(AsmError::new(
+                    location,
+                    "'.org' can't be used in object modules, the link config places segments",
+                ))
+            }
+            ".org" => {
+                let address = self.expect_settled(tokens)?;
+                if !(0..=0xFFFF).contains(&address) {
+                    return Err(AsmError::new(location, format!("bad address {}", address)));
+                }
+                if self.origin.is_none() {
+                    self.pc = address;
+                } else if address >= self.pc {
+                    let padding = vec![0; (address - self.pc) as usize];
+                    self.emit(&padding, location)?;
+                } else {
+                    return Err(AsmError::new(
+                        location,
+                        format!(
+                            ".org ${:04X} is behind the current address ${:04X}",
+                            address, self.pc
+                        ),
+                    ));
+                }
+            }
+            ".byte" => loop {
+                let location = tokens.location();
+                if let Some(Token::Str(text)) = tokens.peek().map(|token| &token.token) {
+                    tokens.next();
+                    self.emit(text.as_bytes(), location)?;
+                } else {
+                    let expr = expr::parse(tokens)?;
+                    let value = self.value(&expr)?.unwrap_or(0);
+                    let value = byte(value).map_err(|message| AsmError::new(location, message))?;
+                    self.relocate(&expr, FixupKind::Byte, self.pc, location, frame)?;
+                    self.emit(&[value], location)?;
+                }
+                if !tokens.eat_punct(",") {
+                    break;
+                }
+            },
+            ".word" => loop {
+                let location = tokens.location();
+                let expr = expr::parse(tokens)?;
+                let value = self.value(&expr)?.unwrap_or(0);
+                let value = word(value).map_err(|message| AsmError::new(location, message))?;
+                self.relocate(&expr, FixupKind::Word, self.pc, location, frame)?;
+                self.emit(&value.to_le_bytes(), location)?;
+                if !tokens.eat_punct(",") {
+                    break;
+                }
+            },
+            ".res" => {
+                let count = self.expect_settled(tokens)?;
+                let fill = if tokens.eat_punct(",") {
+                    self.byte_operand(tokens)?
+                } else {
+                    0
+                };
+                if !(0..=0x10000).contains(&count) {
+                    return Err(AsmError::new(location, format!("bad size {}", count)));
+                }
+                self.emit(&vec![fill; count as usize], location)?;
+            }
+            ".incbin" => {
+                let path = tokens.expect_string()?;
+                let path = self.find_file(path, frame.file.as_deref(), location)?;
+                let data = fs::read(&path).map_err(|err| {
+                    AsmError::new(location, format!("can't read {}: {}", path.display(), err))
+                })?;
+
+                let offset = if tokens.eat_punct(",") {
+                    self.expect_settled(tokens)?
+                } else {
+                    0
+                };
+                let length = if tokens.eat_punct(",") {
+                    self.expect_settled(tokens)?
+                } else {
+                    data.len() as i64 - offset
+                };
+                let range = usize::try_from(offset)
+                    .ok()
+                    .zip(usize::try_from(length).ok())
+                    .and_then(|(offset, length)| data.get(offset..offset.checked_add(length)?))
+                    .ok_or_else(|| {
+                        AsmError::new(
+                            location,
+                            format!("{} is only {} bytes", path.display(), data.len()),
+                        )
+                    })?;
+                self.emit(range, location)?;
+            }
+            ".include" => {
+                let path = tokens.expect_string()?;
+                tokens.expect_end()?;
+                if frame.depth == MAX_DEPTH {
+                    return Err(AsmError::new(location, "includes nested too deeply"));
+                }
+                let path = self.find_file(path, frame.file.as_deref(), location)?;
+                let source = fs::read_to_string(&path).map_err(|err| {
+                    AsmError::new(location, format!("can't read {}: {}", path.display(), err))
+                })?;
+                let inner = Frame {
+                    file: Some(path),
+                    expansions: frame.expansions.clone(),
+                    depth: frame.depth + 1,
+                };
+                self.source(&source, &inner);
+            }
+            ".define" => {
+                let name = tokens.expect_ident()?;
+                if self.defines.contains_key(name) {
+                    return Err(AsmError::new(
+                        location,
+                        format!("'{}' is already defined", name),
+                    ));
+                }
+                let value = self.substitute_defines(tokens.rest());
+                let value = value.into_iter().map(|spanned| spanned.token).collect();
+                self.defines.insert(name.to_string(), value);
+            }
+            ".macro" => {
+                // The body is collected even if the signature is bad, so it
+                // doesn't get assembled
+                let signature = macro_signature(tokens);
+                let (name, params) = signature.clone().unwrap_or_default();
+                self.block = Some(Block {
+                    kind: BlockKind::Macro { name, params },
+                    lines: Vec::new(),
+                    nesting: 0,
+                    file: frame.file.clone(),
+                    location,
+                });
+                signature?;
+            }
+            ".repeat" => {
+                self.block = Some(Block {
+                    kind: BlockKind::Repeat {
+                        count: 0,
+                        var: None,
+                    },
+                    lines: Vec::new(),
+                    nesting: 0,
+                    file: frame.file.clone(),
+                    location,
+                });
+                let count_location = tokens.location();
+                let count = self.expect_settled(tokens)?;
+                if count > MAX_REPEAT {
+                    return Err(AsmError::new(count_location, "repeat count too large"));
+                }
+                let var = if tokens.eat_punct(",") {
+                    Some(tokens.expect_ident()?.to_string())
+                } else {
+                    None
+                };
+                if let Some(block) = &mut self.block {
+                    block.kind = BlockKind::Repeat {
+                        count: count.max(0),
+                        var,
+                    };
+                }
+            }
+            ".segment" | ".export" | ".import" | ".importzp" if !self.object => {
+                return Err(AsmError::new(
+                    location,
+                    format!("'{}' can only be used in object modules", name),
+                ))
+            }
+            ".segment" => {
+                let segment = tokens.expect_string()?;
+                let zeropage = if tokens.eat_punct(":") {
+                    let location = tokens.location();
+                    match tokens.expect_ident()?.to_ascii_lowercase().as_str() {
+                        "zeropage" => true,
+                        "absolute" => false,
+                        size => {
+                            return Err(AsmError::new(
+                                location,
+                                format!("unknown address size '{}'", size),
+                            ))
+                        }
+                    }
+                } else {
+                    segment == "ZEROPAGE"
+                };
+                self.switch_section(segment, zeropage, location)?;
+            }
+            ".export" => loop {
+                let location = tokens.location();
+                let symbol = tokens.expect_ident()?;
+                if symbol.starts_with('@') {
+                    return Err(AsmError::new(location, "local labels can't be exported"));
+                }
+                self.exports
+                    .push((symbol.to_string(), self.site(location, frame)));
+                if !tokens.eat_punct(",") {
+                    break;
+                }
+            },
+            ".import" | ".importzp" => loop {
+                let location = tokens.location();
+                let symbol = tokens.expect_ident()?;
+                if symbol.starts_with('@') {
+                    return Err(AsmError::new(location, "local labels can't be imported"));
+                }
+                let value = Value {
+                    value: 0,
+                    forward: false,
+                    reloc: if name.eq_ignore_ascii_case(".importzp") {
+                        Reloc::ZeroPage
+                    } else {
+                        Reloc::Absolute
+                    },
+                };
+                self.define(symbol, value, false, location)?;
+                self.imports
+                    .insert(symbol.to_string(), self.site(location, frame));
+                if !tokens.eat_punct(",") {
+                    break;
+                }
+            },
+            ".endmacro" | ".endrepeat" => {
+                return Err(AsmError::new(
+                    location,
+                    format!("'{}' without a block to end", name),
+                ))
+            }
+            _ => {
+                return Err(AsmError::new(
+                    location,
+                    format!("unknown directive '{}'", name),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble_one(line: &str) -> Vec<u8> {
+        assemble(line).unwrap()
+    }
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err().remove(0)
+    }
+
+    #[test]
+    fn test_assemble_modes() {
+        assert_eq!(assemble_one("NOP"), [0xEA]);
+        assert_eq!(assemble_one("asl a"), [0x0A]);
+        assert_eq!(assemble_one("LSR"), [0x4A]);
+        assert_eq!(assemble_one("LDA #$01"), [0xA9, 0x01]);
+        assert_eq!(assemble_one("LDA #-1"), [0xA9, 0xFF]);
+        assert_eq!(assemble_one("LDA $10"), [0xA5, 0x10]);
+        assert_eq!(assemble_one("LDA $10,X"), [0xB5, 0x10]);
+        assert_eq!(assemble_one("LDX $10,Y"), [0xB6, 0x10]);
+        assert_eq!(assemble_one("LDA $0010"), [0xA5, 0x10]);
+        assert_eq!(assemble_one("LDA $1234"), [0xAD, 0x34, 0x12]);
+        assert_eq!(assemble_one("LDA $1234,x"), [0xBD, 0x34, 0x12]);
+        assert_eq!(assemble_one("LDA $1234,Y"), [0xB9, 0x34, 0x12]);
+        // no zero page,Y form for LDA
+        assert_eq!(assemble_one("LDA $10,Y"), [0xB9, 0x10, 0x00]);
+        assert_eq!(assemble_one("JMP $10"), [0x4C, 0x10, 0x00]);
+        assert_eq!(assemble_one("JMP ($FFFC)"), [0x6C, 0xFC, 0xFF]);
+        assert_eq!(assemble_one("LDA ($10,X)"), [0xA1, 0x10]);
+        assert_eq!(assemble_one("LDA ($10),Y"), [0xB1, 0x10]);
+        assert_eq!(assemble_one("LDA ($10 + 1) * 2"), [0xA5, 0x22]);
+        assert_eq!(assemble_one("BEQ $0010"), [0xF0, 0x0E]);
+    }
+
+    #[test]
+    fn test_assemble_program() {
+        let source = "
+            LDX #$08   ; count down
+            DEX
+            STX $0200
+            CPX #3
+            BNE $0002
+            STX $0201
+            BRK
+        ";
+        let bytes = assemble(source).unwrap();
+        assert_eq!(
+            bytes,
+            b"\xa2\x08\xca\x8e\x00\x02\xe0\x03\xd0\xf8\x8e\x01\x02\x00"
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let err = error("STA #$01");
+        assert_eq!(err.location, Location { line: 1, column: 5 });
+        assert!(err.message.contains("STA"));
+
+        let err = error("NOP\n  FOO $10");
+        assert_eq!(err.location, Location { line: 2, column: 3 });
+
+        assert_eq!(error("LDA ($10").location, Location { line: 1, column: 9 });
+        assert!(error("LDA #256").message.contains("byte"));
+        assert!(error("BNE $1000").message.contains("out of range"));
+        assert!(error("LDA $10,Z").message.contains("'X' or 'Y'"));
+        assert!(error("NOP $10").message.contains("NOP"));
+
+        assert_eq!(assemble("FOO\nBAR").unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn test_labels() {
+        let program = Assembler::new()
+            .assemble(
+                "
+                zp = $10
+                    .org $C000
+                start:
+                    LDA zp
+                    STA later       ; forward, so absolute even though it's small
+                    JMP next
+                @skip: .byte 1
+                next:
+                    BNE @skip       ; not start's @skip
+                @skip:
+                    BEQ start
+                later = far - $BFF0
+                far:
+                ",
+            )
+            .unwrap();
+        assert_eq!(program.symbols["start"], 0xC000);
+        assert_eq!(program.symbols["start@skip"], 0xC008);
+        assert_eq!(program.symbols["next@skip"], 0xC00B);
+        assert_eq!(program.symbols["later"], 0x1D);
+        assert_eq!(
+            program.bytes,
+            [0xA5, 0x10, 0x8D, 0x1D, 0x00, 0x4C, 0x09, 0xC0, 0x01, 0xD0, 0x00, 0xF0, 0xF3]
+        );
+
+        let err = error("loop: NOP\nloop: NOP");
+        assert!(err.message.contains("already defined"));
+        assert_eq!(err.location, Location { line: 2, column: 1 });
+        assert!(error("JMP nowhere").message.contains("'nowhere'"));
+        assert!(error("BNE far\n.res 200\nfar:")
+            .message
+            .contains("out of range"));
+        assert!(error(".res size\nsize = 1")
+            .message
+            .contains("defined before"));
+    }
+
+    #[test]
+    fn test_directives() {
+        let program = Assembler::new()
+            .assemble(
+                "
+                .org $8000
+                .byte 1, \"AB\", <$1234, >$1234
+                .word $1234, *
+                .res 2, $EA
+                .org $800C
+                .byte 'z'
+                ",
+            )
+            .unwrap();
+        assert_eq!(program.origin, 0x8000);
+        assert_eq!(
+            program.bytes,
+            [1, b'A', b'B', 0x34, 0x12, 0x34, 0x12, 0x05, 0x80, 0xEA, 0xEA, 0, b'z']
+        );
+
+        assert!(error(".org $10\nNOP\n.org 0").message.contains("behind"));
+        assert!(error(".byte 300").message.contains("byte"));
+        assert!(error(".foo").message.contains("unknown directive"));
+        assert!(error(".org $FFFF\n.word 0").message.contains("past $FFFF"));
+        assert!(error(".incbin \"does-not-exist.bin\"")
+            .message
+            .contains("can't find"));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("asm6502_include_{}", std::process::id()));
+        let lib = dir.join("lib");
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(
+            dir.join("main.s"),
+            ".include \"defs.s\"\nLDA #value\n.incbin \"data.bin\", 1, 2\n",
+        )
+        .unwrap();
+        fs::write(lib.join("defs.s"), "value = 7\n.byte 0\n").unwrap();
+        fs::write(dir.join("data.bin"), [1, 2, 3, 4]).unwrap();
+        fs::write(dir.join("broken.s"), "NOP\n.include \"bad.s\"\n").unwrap();
+        fs::write(lib.join("bad.s"), "\nLDA #nope\n").unwrap();
+        fs::write(dir.join("loop.s"), ".include \"loop.s\"\n").unwrap();
+
+        let mut assembler = Assembler::new();
+        assembler.include_dir(&lib);
+        let program = assembler.assemble_file(dir.join("main.s")).unwrap();
+        assert_eq!(program.bytes, [0, 0xA9, 7, 2, 3]);
+
+        let errors = assembler.assemble_file(dir.join("broken.s")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, Some(lib.join("bad.s")));
+        assert_eq!(errors[0].location, Location { line: 2, column: 6 });
+
+        let errors = assembler.assemble_file(dir.join("loop.s")).unwrap_err();
+        assert!(errors[0].message.contains("nested too deeply"));
+        assert!(assembler.assemble_file(dir.join("missing.s")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_macros() {
+        let program = Assembler::new()
+            .assemble(
+                "
+                .macro load_store value, dest
+                    LDA #value
+                    STA dest
+                .endmacro
+                .macro wait
+                @spin: DEX
+                    BNE @spin
+                .endmacro
+                .macro twice op
+                    op
+                    op
+                .endmacro
+                start:
+                    load_store 1, $10
+                    wait
+                    wait
+                    load_store <$1234, $0200
+                    twice INX
+                ",
+            )
+            .unwrap();
+        assert_eq!(
+            program.bytes,
+            [
+                0xA9, 0x01, 0x85, 0x10, 0xCA, 0xD0, 0xFD, 0xCA, 0xD0, 0xFD, 0xA9, 0x34, 0x8D, 0x00,
+                0x02, 0xE8, 0xE8
+            ]
+        );
+        assert_eq!(program.symbols["start@spin#2"], 4);
+        assert_eq!(program.symbols["start@spin#3"], 7);
+
+        let source = "
+.macro bad
+    LDA #nope
+.endmacro
+.macro outer
+    bad
+.endmacro
+    bad
+    outer
+";
+        let errors = assemble(source).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].location,
+            Location {
+                line: 3,
+                column: 10
+            }
+        );
+        assert_eq!(
+            errors[0].expansions,
+            [Expansion {
+                name: "bad".into(),
+                file: None,
+                location: Location { line: 8, column: 5 },
+            }]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "3:10: undefined symbol 'nope'\n8:5: in expansion of 'bad'"
+        );
+        assert_eq!(errors[1].expansions.len(), 2);
+        assert_eq!(
+            errors[1].expansions[1].location,
+            Location { line: 9, column: 5 }
+        );
+
+        assert!(error(".macro forever\nforever\n.endmacro\nforever")
+            .message
+            .contains("nested too deeply"));
+        assert!(error(".macro one a\n.endmacro\none 1, 2")
+            .message
+            .contains("takes 1 arguments"));
+        assert!(error(".macro m\n.endmacro\n.macro m\n.endmacro")
+            .message
+            .contains("already defined"));
+        assert!(error(".macro open\nNOP")
+            .message
+            .contains("without '.endmacro'"));
+        assert!(error(".endmacro").message.contains("without a block"));
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let source = "
+            .define DEBUG 1
+            .define VALUE $42
+            x = 1
+            .if DEBUG && VALUE > 0
+                LDA #VALUE
+            .else
+                LDA #0
+            .endif
+            .ifdef NOPE
+                NOP
+            .endif
+            .ifndef NOPE
+              .if 0
+                BRK
+              .else
+                INX
+              .endif
+            .endif
+            .ifdef x
+                INY
+            .endif
+            .if 0
+              .if undefined_is_fine_here
+              .endif
+              not even an instruction
+            .endif
+        ";
+        assert_eq!(assemble(source).unwrap(), [0xA9, 0x42, 0xE8, 0xC8]);
+
+        assert!(error(".if later\n.endif\nlater = 1")
+            .message
+            .contains("defined before"));
+        assert!(error(".else").message.contains("without '.if'"));
+        assert!(error(".endif").message.contains("without '.if'"));
+        assert!(error(".if 1\n.else\n.else\n.endif")
+            .message
+            .contains("second '.else'"));
+        let err = error("NOP\n  .if 1");
+        assert!(err.message.contains("without '.endif'"));
+        assert_eq!(err.location, Location { line: 2, column: 3 });
+        assert!(error(".define A 1\n.define A 2")
+            .message
+            .contains("already defined"));
+    }
+
+    #[test]
+    fn test_repeat() {
+        let source = "
+            .repeat 3, i
+                .byte i * 2
+            .endrepeat
+            .repeat 2
+              .repeat 2, j
+                .byte j
+              .endrepeat
+            .endrepeat
+            .macro fill n, v
+              .repeat n
+                .byte v
+              .endrepeat
+            .endmacro
+            fill 2, 9
+        ";
+        assert_eq!(assemble(source).unwrap(), [0, 2, 4, 0, 1, 0, 1, 9, 9]);
+
+        assert!(error(".repeat 2\n.endmacro")
+            .message
+            .contains("expected '.endrepeat'"));
+        assert!(error(".repeat n\n.endrepeat\nn = 1")
+            .message
+            .contains("defined before"));
+        let too_large = error(".repeat 1000000000\nNOP\n.endrepeat");
+        assert_eq!(too_large.message, "repeat count too large");
+        assert_eq!(too_large.location, Location { line: 1, column: 9 });
+    }
+
+    #[test]
+    fn test_objects() {
+        let object = Assembler::new()
+            .assemble_object(
+                "
+                .import far
+                .importzp near
+                .segment \"ZEROPAGE\"
+                counter: .res 1
+                .segment \"CODE\"
+                    LDA counter     ; zero page, though the linker places it
+                    LDA near
+                    LDA later       ; forward, so absolute
+                    LDA far
+                    LDA #<far
+                    BNE *
+                .segment \"DATA\": zeropage
+                later: .byte 0
+                ",
+            )
+            .unwrap();
+        assert_eq!(
+            object.segments().collect::<Vec<_>>(),
+            [("CODE", 14), ("ZEROPAGE", 1), ("DATA", 1)]
+        );
+        assert_eq!(object.imports().collect::<Vec<_>>(), ["far", "near"]);
+        let code = &object.sections[0];
+        assert_eq!(
+            code.bytes,
+            [0xA5, 0x00, 0xA5, 0x00, 0xAD, 0x00, 0x00, 0xAD, 0x00, 0x00, 0xA9, 0x00, 0xD0, 0x00]
+        );
+        let fixups: Vec<_> = code
+            .fixups
+            .iter()
+            .map(|fixup| (fixup.offset, fixup.kind))
+            .collect();
+        assert_eq!(
+            fixups,
+            [
+                (1, FixupKind::Byte),
+                (3, FixupKind::Byte),
+                (5, FixupKind::Word),
+                (8, FixupKind::Word),
+                (11, FixupKind::Byte),
+                (13, FixupKind::Branch)
+            ]
+        );
+
+        let object_error = |source: &str| {
+            Assembler::new()
+                .assemble_object(source)
+                .unwrap_err()
+                .remove(0)
+                .message
+        };
+        assert!(error(".segment \"CODE\"")
+            .message
+            .contains("only be used in object modules"));
+        assert!(object_error(".org $8000").contains("can't be used in object modules"));
+        assert!(object_error(".export nope").contains("isn't defined"));
+        assert!(object_error(".import x\n.export x").contains("imported"));
+        assert!(object_error("start: .res start").contains("until the program is linked"));
+        assert!(object_error("NOP\n.segment \"CODE\": zeropage").contains("absolute"));
+    }
+}
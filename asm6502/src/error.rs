@@ -0,0 +1,138 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::instructions::AddressMode;
+
+/// Position in assembler source, both 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A macro invocation, for errors in the lines it expanded to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub file: Option<PathBuf>,
+    pub location: Location,
+}
+
+/// A problem in assembler source, reported against the offending token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Source file, or `None` for source passed in as a string
+    pub file: Option<PathBuf>,
+    pub location: Location,
+    pub message: String,
+    /// Macro invocations the error happened inside, innermost first.
+    /// `file` and `location` then point into the macro definition.
+    pub expansions: Vec<Expansion>,
+}
+
+impl AsmError {
+    pub(crate) fn new(location: Location, message: impl Into<String>) -> Self {
+        AsmError {
+            file: None,
+            location,
+            message: message.into(),
+            expansions: Vec::new(),
+        }
+    }
+
+    pub(crate) fn expanded_from(mut self, expansions: &[Expansion]) -> Self {
+        if self.expansions.is_empty() {
+            self.expansions = expansions.to_vec();
+        }
+        self
+    }
+
+    /// Attributes the error to `file`, unless an included file already
+    /// claimed it.
+    pub(crate) fn in_file(mut self, file: Option<&Path>) -> Self {
+        if self.file.is_none() {
+            self.file = file.map(Path::to_path_buf);
+        }
+        self
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}: {}", self.location, self.message)?;
+        for expansion in &self.expansions {
+            writeln!(f)?;
+            if let Some(file) = &expansion.file {
+                write!(f, "{}:", file.display())?;
+            }
+            write!(
+                f,
+                "{}: in expansion of '{}'",
+                expansion.location, expansion.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Why an instruction couldn't be built from a mnemonic and addressing mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodeError {
+    UnknownMnemonic(String),
+    UnsupportedMode {
+        mnemonic: &'static str,
+        mode: AddressMode,
+    },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::UnknownMnemonic(mnemonic) => {
+                write!(f, "unknown mnemonic '{}'", mnemonic)
+            }
+            EncodeError::UnsupportedMode { mnemonic, mode } => {
+                write!(f, "{} doesn't support {} addressing", mnemonic, mode.name())
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// Why modules couldn't be linked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// A problem with a line of a module's source or of the config
+    Source(AsmError),
+    /// Segments that don't fit the memory layout
+    Layout(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Source(error) => error.fmt(f),
+            LinkError::Layout(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl From<AsmError> for LinkError {
+    fn from(error: AsmError) -> Self {
+        LinkError::Source(error)
+    }
+}
@@ -0,0 +1,284 @@
+use crate::error::{AsmError, Location};
+use crate::lexer::{Token, Tokens};
+
+/// Binary operators and their precedence, loosest first.
+const BINARY: [(&str, u8); 18] = [
+    ("||", 1),
+    ("&&", 2),
+    ("|", 3),
+    ("^", 4),
+    ("&", 5),
+    ("==", 6),
+    ("!=", 6),
+    ("<>", 6),
+    ("<", 7),
+    (">", 7),
+    ("<=", 7),
+    (">=", 7),
+    ("<<", 8),
+    (">>", 8),
+    ("+", 9),
+    ("-", 9),
+    ("*", 10),
+    ("/", 10),
+];
+
+/// `-x`, `~x`, and `<x`/`>x` for the low and high byte.
+const UNARY: [&str; 4] = ["-", "~", "<", ">"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Number(i64),
+    Symbol {
+        name: String,
+        location: Location,
+    },
+    /// `*`, the address of the current line
+    Pc,
+    Unary(&'static str, Box<Expr>),
+    Binary {
+        op: &'static str,
+        location: Location,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+}
+
+/// How a value depends on where the linker places segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Reloc {
+    /// Known when assembling
+    None,
+    /// Somewhere in zero page
+    ZeroPage,
+    /// Anywhere
+    Absolute,
+}
+
+/// An evaluated expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Value {
+    /// Only a placeholder if `reloc` isn't [`Reloc::None`]
+    pub value: i64,
+    /// Depends on a symbol defined further down, which earlier passes
+    /// didn't know yet
+    pub forward: bool,
+    pub reloc: Reloc,
+}
+
+impl Value {
+    pub fn known(value: i64) -> Self {
+        Value {
+            value,
+            forward: false,
+            reloc: Reloc::None,
+        }
+    }
+}
+
+/// Looks up a symbol, returning `None` if it isn't known yet.
+pub(crate) type Resolve<'a> = dyn Fn(&str, Location) -> Result<Option<Value>, AsmError> + 'a;
+
+fn precedence(op: &str) -> Option<u8> {
+    BINARY
+        .iter()
+        .find(|(binary, _)| *binary == op)
+        .map(|&(_, precedence)| precedence)
+}
+
+/// Parses an expression, stopping at the first token that can't continue it.
+pub(crate) fn parse(tokens: &mut Tokens) -> Result<Expr, AsmError> {
+    parse_binary(tokens, 0)
+}
+
+fn parse_binary(tokens: &mut Tokens, min_precedence: u8) -> Result<Expr, AsmError> {
+    let mut lhs = parse_unary(tokens)?;
+    while let Some(spanned) = tokens.peek() {
+        let (op, location) = match spanned.token {
+            Token::Punct(op) => (op, spanned.location),
+            _ => break,
+        };
+        let precedence = match precedence(op) {
+            Some(precedence) if precedence > min_precedence => precedence,
+            _ => break,
+        };
+        tokens.next();
+        let rhs = parse_binary(tokens, precedence)?;
+        lhs = Expr::Binary {
+            op,
+            location,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        };
+    }
+    Ok(lhs)
+}
+
+fn parse_unary(tokens: &mut Tokens) -> Result<Expr, AsmError> {
+    if let Some(&op) = UNARY.iter().find(|op| tokens.eat_punct(op)) {
+        return Ok(Expr::Unary(op, Box::new(parse_unary(tokens)?)));
+    }
+
+    let location = tokens.location();
+    let spanned = match tokens.next() {
+        Some(spanned) => spanned,
+        None => return Err(AsmError::new(location, "expected an expression")),
+    };
+    match &spanned.token {
+        Token::Number(value) => Ok(Expr::Number(*value)),
+        Token::Ident(name) => Ok(Expr::Symbol {
+            name: name.clone(),
+            location,
+        }),
+        Token::Punct("*") => Ok(Expr::Pc),
+        Token::Punct("(") => {
+            let inner = parse(tokens)?;
+            tokens.expect_punct(")")?;
+            Ok(inner)
+        }
+        _ => Err(AsmError::new(location, "expected an expression")),
+    }
+}
+
+impl Expr {
+    /// The expression with each symbol replaced by what `replace` returns
+    /// for it.
+    pub fn map_symbols(&self, replace: &dyn Fn(&str, Location) -> Expr) -> Expr {
+        match self {
+            Expr::Symbol { name, location } => replace(name, *location),
+            Expr::Unary(op, operand) => Expr::Unary(op, Box::new(operand.map_symbols(replace))),
+            Expr::Binary {
+                op,
+                location,
+                lhs,
+                rhs,
+            } => Expr::Binary {
+                op,
+                location: *location,
+                lhs: Box::new(lhs.map_symbols(replace)),
+                rhs: Box::new(rhs.map_symbols(replace)),
+            },
+            Expr::Number(_) | Expr::Pc => self.clone(),
+        }
+    }
+
+    /// Evaluates the expression at address `pc`. A symbol `resolve` doesn't
+    /// know yet makes the whole expression unknown.
+    pub fn eval(&self, pc: Value, resolve: &Resolve) -> Result<Option<Value>, AsmError> {
+        match self {
+            Expr::Number(value) => Ok(Some(Value::known(*value))),
+            Expr::Pc => Ok(Some(pc)),
+            Expr::Symbol { name, location } => resolve(name, *location),
+            Expr::Unary(op, operand) => {
+                let operand = match operand.eval(pc, resolve)? {
+                    Some(operand) => operand,
+                    None => return Ok(None),
+                };
+                let value = match *op {
+                    "-" => operand.value.wrapping_neg(),
+                    "~" => !operand.value,
+                    "<" => operand.value & 0xFF,
+                    ">" => (operand.value >> 8) & 0xFF,
+                    _ => unreachable!("not a unary operator: {}", op),
+                };
+                let reloc = match *op {
+                    // A byte of an address is always a zero page value
+                    "<" | ">" => operand.reloc.min(Reloc::ZeroPage),
+                    _ => operand.reloc,
+                };
+                Ok(Some(Value {
+                    value,
+                    reloc,
+                    ..operand
+                }))
+            }
+            Expr::Binary {
+                op,
+                location,
+                lhs,
+                rhs,
+            } => {
+                // Both sides are evaluated so errors in either are reported
+                let (lhs, rhs) = match (lhs.eval(pc, resolve)?, rhs.eval(pc, resolve)?) {
+                    (Some(lhs), Some(rhs)) => (lhs, rhs),
+                    _ => return Ok(None),
+                };
+                let (a, b) = (lhs.value, rhs.value);
+                let value = match *op {
+                    "||" => i64::from(a != 0 || b != 0),
+                    "&&" => i64::from(a != 0 && b != 0),
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "&" => a & b,
+                    "==" => i64::from(a == b),
+                    "!=" | "<>" => i64::from(a != b),
+                    "<" => i64::from(a < b),
+                    ">" => i64::from(a > b),
+                    "<=" => i64::from(a <= b),
+                    ">=" => i64::from(a >= b),
+                    "<<" => a.wrapping_shl(b as u32),
+                    ">>" => a.wrapping_shr(b as u32),
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    // The divisor is a placeholder until the linker fills it in
+                    "/" if rhs.reloc != Reloc::None => 0,
+                    "/" if b == 0 => return Err(AsmError::new(*location, "division by zero")),
+                    "/" => a.wrapping_div(b),
+                    _ => unreachable!("not a binary operator: {}", op),
+                };
+                Ok(Some(Value {
+                    value,
+                    forward: lhs.forward || rhs.forward,
+                    reloc: lhs.reloc.max(rhs.reloc),
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::lexer::tokenize;
+
+    fn eval(source: &str) -> Result<Option<i64>, AsmError> {
+        let spanned = tokenize(source, 1).unwrap();
+        let end = Location {
+            line: 1,
+            column: source.len() + 1,
+        };
+        let mut tokens = Tokens::new(&spanned, end);
+        let expr = parse(&mut tokens)?;
+        tokens.expect_end()?;
+        let resolve = |name: &str, _| {
+            Ok(match name {
+                "label" => Some(Value::known(0x1234)),
+                _ => None,
+            })
+        };
+        Ok(expr
+            .eval(Value::known(0x8000), &resolve)?
+            .map(|value| value.value))
+    }
+
+    #[test]
+    fn test_expr() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), Some(7));
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), Some(9));
+        assert_eq!(eval("10 - 2 - 3").unwrap(), Some(5));
+        assert_eq!(eval("<label").unwrap(), Some(0x34));
+        assert_eq!(eval(">label + 1").unwrap(), Some(0x13));
+        assert_eq!(eval("-1").unwrap(), Some(-1));
+        assert_eq!(eval("~0 & $FF").unwrap(), Some(0xFF));
+        assert_eq!(eval("1 << 4 | 1").unwrap(), Some(0x11));
+        assert_eq!(eval("2 < 3 && label == $1234").unwrap(), Some(1));
+        assert_eq!(eval("* + 2").unwrap(), Some(0x8002));
+        assert_eq!(eval("unknown + 1").unwrap(), None);
+
+        assert!(eval("1 / 0").unwrap_err().message.contains("zero"));
+        assert_eq!(eval("1 +").unwrap_err().location.column, 4);
+        assert!(eval("(1").is_err());
+    }
+}
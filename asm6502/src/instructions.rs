@@ -1,7 +1,9 @@
 use asm6502_derive::Asm6502;
 use enum_dispatch::enum_dispatch;
 
-#[derive(Debug, PartialEq)]
+use crate::error::EncodeError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressMode {
     Implicit,
     Accumulator,
@@ -37,6 +39,37 @@ impl AddressMode {
             IndirectY(_) => 2,
         }
     }
+
+    /// Name of the mode as 6502 references spell it.
+    pub fn name(&self) -> &'static str {
+        use AddressMode::*;
+        match self {
+            Implicit => "implicit",
+            Accumulator => "accumulator",
+            Immediate(_) => "immediate",
+            Zero(_) => "zero page",
+            ZeroX(_) => "zero page,X",
+            ZeroY(_) => "zero page,Y",
+            Relative(_) => "relative",
+            Absolute(_) => "absolute",
+            AbsoluteX(_) => "absolute,X",
+            AbsoluteY(_) => "absolute,Y",
+            Indirect(_) => "indirect",
+            IndirectX(_) => "(indirect,X)",
+            IndirectY(_) => "(indirect),Y",
+        }
+    }
+
+    /// Operand bytes following the opcode, little-endian.
+    pub fn operand_bytes(&self) -> Vec<u8> {
+        use AddressMode::*;
+        match *self {
+            Implicit | Accumulator => vec![],
+            Immediate(a) | Zero(a) | ZeroX(a) | ZeroY(a) | IndirectX(a) | IndirectY(a) => vec![a],
+            Relative(a) => vec![a as u8],
+            Absolute(a) | AbsoluteX(a) | AbsoluteY(a) | Indirect(a) => a.to_le_bytes().to_vec(),
+        }
+    }
 }
 
 pub trait InstructionConstruct {
@@ -53,6 +86,18 @@ pub trait InstructionConstruct {
         Self::from_peekable(&mut bytes.iter().peekable())
     }
 
+    /// Opcode byte encoding the instruction in `mode`, or `None` if the
+    /// instruction doesn't support it. The operand value is ignored.
+    fn opcode_for(mode: &AddressMode) -> Option<u8>
+    where
+        Self: Sized;
+
+    /// Builds the instruction in `mode`, failing if it has no encoding for
+    /// it, like `STA` immediate.
+    fn new(mode: AddressMode) -> Result<Self, EncodeError>
+    where
+        Self: Sized;
+
     fn name(&self) -> &'static str;
 }
 
@@ -63,9 +108,28 @@ pub trait Instruction: std::fmt::Display {
     }
 
     fn size(&self) -> u8;
+
+    /// Machine code for the instruction, the inverse of decoding it.
+    ///
+    /// Panics if the instruction was built directly in a mode it doesn't
+    /// support; [`InstructionConstruct::new`] and [`Opcode::new`] rule that
+    /// out.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Whether the instruction is one of the undocumented ones, like `LAX`,
+    /// which the `unofficial` feature decodes.
+    fn unofficial(&self) -> bool {
+        false
+    }
+
+    /// Writes the machine code for the instruction to `writer`.
+    fn write_to<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.encode())
+    }
 }
 
 #[enum_dispatch(Instruction)]
+#[derive(Debug, PartialEq)]
 pub enum Opcode {
     ADC(ADC),
     AND(AND),
@@ -123,6 +187,52 @@ pub enum Opcode {
     TXA(TXA),
     TXS(TXS),
     TYA(TYA),
+    #[cfg(feature = "unofficial")]
+    AHX(AHX),
+    #[cfg(feature = "unofficial")]
+    ALR(ALR),
+    #[cfg(feature = "unofficial")]
+    ANC(ANC),
+    #[cfg(feature = "unofficial")]
+    ARR(ARR),
+    #[cfg(feature = "unofficial")]
+    AXS(AXS),
+    #[cfg(feature = "unofficial")]
+    DCP(DCP),
+    #[cfg(feature = "unofficial")]
+    ISC(ISC),
+    #[cfg(feature = "unofficial")]
+    KIL(KIL),
+    #[cfg(feature = "unofficial")]
+    LAS(LAS),
+    #[cfg(feature = "unofficial")]
+    LAX(LAX),
+    #[cfg(feature = "unofficial")]
+    RLA(RLA),
+    #[cfg(feature = "unofficial")]
+    RRA(RRA),
+    #[cfg(feature = "unofficial")]
+    SAX(SAX),
+    #[cfg(feature = "unofficial")]
+    SHX(SHX),
+    #[cfg(feature = "unofficial")]
+    SHY(SHY),
+    #[cfg(feature = "unofficial")]
+    SKB(SKB),
+    #[cfg(feature = "unofficial")]
+    SKW(SKW),
+    #[cfg(feature = "unofficial")]
+    SLO(SLO),
+    #[cfg(feature = "unofficial")]
+    SRE(SRE),
+    #[cfg(feature = "unofficial")]
+    TAS(TAS),
+    #[cfg(feature = "unofficial")]
+    UNOP(UNOP),
+    #[cfg(feature = "unofficial")]
+    USBC(USBC),
+    #[cfg(feature = "unofficial")]
+    XAA(XAA),
 }
 
 impl std::fmt::Display for Opcode {
@@ -139,6 +249,60 @@ macro_rules! try_from_peekable {
     };
 }
 
+macro_rules! mnemonics {
+    ($($id: tt),* $(,)?; unofficial: $($unofficial: tt),* $(,)?) => {
+        /// Every mnemonic `Opcode` knows, in upper case.
+        #[cfg(not(feature = "unofficial"))]
+        pub const MNEMONICS: &[&str] = &[$(stringify!($id)),*];
+
+        /// Every mnemonic `Opcode` knows, in upper case.
+        #[cfg(feature = "unofficial")]
+        pub const MNEMONICS: &[&str] = &[$(stringify!($id),)* $(stringify!($unofficial)),*];
+
+        impl Opcode {
+            /// Opcode byte for an upper case `mnemonic` in `mode`, or `None`
+            /// if the mnemonic doesn't exist or doesn't support the mode.
+            pub fn opcode_for(mnemonic: &str, mode: &AddressMode) -> Option<u8> {
+                match mnemonic {
+                    $(stringify!($id) => $id::opcode_for(mode),)*
+                    $(
+                        #[cfg(feature = "unofficial")]
+                        stringify!($unofficial) => $unofficial::opcode_for(mode),
+                    )*
+                    _ => None,
+                }
+            }
+
+            /// Builds the instruction for an upper case `mnemonic` in `mode`.
+            ///
+            /// ```
+            /// use asm6502::{AddressMode, Instruction, Opcode};
+            ///
+            /// let lda = Opcode::new("LDA", AddressMode::Immediate(0x01)).unwrap();
+            /// assert_eq!(lda.encode(), [0xA9, 0x01]);
+            /// assert!(Opcode::new("STA", AddressMode::Immediate(0x01)).is_err());
+            /// ```
+            pub fn new(mnemonic: &str, mode: AddressMode) -> Result<Self, EncodeError> {
+                match mnemonic {
+                    $(stringify!($id) => $id::new(mode).map(Opcode::$id),)*
+                    $(
+                        #[cfg(feature = "unofficial")]
+                        stringify!($unofficial) => $unofficial::new(mode).map(Opcode::$unofficial),
+                    )*
+                    _ => Err(EncodeError::UnknownMnemonic(mnemonic.to_string())),
+                }
+            }
+        }
+    };
+}
+
+mnemonics!(
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC, CLD, CLI, CLV, CMP, CPX,
+    CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP, JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA, PHP, PLA,
+    PLP, ROL, ROR, RTI, RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA, TXS, TYA;
+    unofficial: AHX, ALR, ANC, ARR, AXS, DCP, ISC, KIL, LAS, LAX, RLA, RRA, SAX, SHX, SHY, SKB, SKW, SLO, SRE, TAS, UNOP, USBC, XAA,
+);
+
 impl Opcode {
     pub fn from_peekable<'a, I: Iterator<Item = &'a u8> + 'a>(
         bytes: &mut std::iter::Peekable<I>,
@@ -199,6 +363,52 @@ impl Opcode {
         try_from_peekable!(bytes, TXA);
         try_from_peekable!(bytes, TXS);
         try_from_peekable!(bytes, TYA);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, AHX);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, ALR);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, ANC);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, ARR);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, AXS);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, DCP);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, ISC);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, KIL);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, LAS);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, LAX);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, RLA);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, RRA);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, SAX);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, SHX);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, SHY);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, SKB);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, SKW);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, SLO);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, SRE);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, TAS);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, UNOP);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, USBC);
+        #[cfg(feature = "unofficial")]
+        try_from_peekable!(bytes, XAA);
         None
     }
 }
@@ -532,6 +742,223 @@ pub struct TXS(AddressMode);
 #[asm6502(implicit = 0x98)]
 pub struct TYA(AddressMode);
 
+// Undocumented instructions. Several are unstable on real hardware, and
+// some have more than one opcode per mode; those keep the one they were
+// decoded from so they encode back to it.
+
+/// Unstable: stores A & X & (high byte of the address + 1)
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, absolute_y = 0x9F, indirect_y = 0x93)]
+pub struct AHX(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, immediate = 0x4B)]
+pub struct ALR(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, immediate(0x0B, 0x2B))]
+pub struct ANC(AddressMode, u8);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, immediate = 0x6B)]
+pub struct ARR(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, immediate = 0xCB)]
+pub struct AXS(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    zero = 0xC7,
+    zero_x = 0xD7,
+    absolute = 0xCF,
+    absolute_x = 0xDF,
+    absolute_y = 0xDB,
+    indirect_x = 0xC3,
+    indirect_y = 0xD3
+)]
+pub struct DCP(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    zero = 0xE7,
+    zero_x = 0xF7,
+    absolute = 0xEF,
+    absolute_x = 0xFF,
+    absolute_y = 0xFB,
+    indirect_x = 0xE3,
+    indirect_y = 0xF3
+)]
+pub struct ISC(AddressMode);
+
+/// Halts the CPU
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    implicit(0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2)
+)]
+pub struct KIL(AddressMode, u8);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, absolute_y = 0xBB)]
+pub struct LAS(AddressMode);
+
+/// Immediate is unstable, ORing A with a chip-dependent constant first
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    immediate = 0xAB,
+    zero = 0xA7,
+    zero_y = 0xB7,
+    absolute = 0xAF,
+    absolute_y = 0xBF,
+    indirect_x = 0xA3,
+    indirect_y = 0xB3
+)]
+pub struct LAX(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    zero = 0x27,
+    zero_x = 0x37,
+    absolute = 0x2F,
+    absolute_x = 0x3F,
+    absolute_y = 0x3B,
+    indirect_x = 0x23,
+    indirect_y = 0x33
+)]
+pub struct RLA(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    zero = 0x67,
+    zero_x = 0x77,
+    absolute = 0x6F,
+    absolute_x = 0x7F,
+    absolute_y = 0x7B,
+    indirect_x = 0x63,
+    indirect_y = 0x73
+)]
+pub struct RRA(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    zero = 0x87,
+    zero_y = 0x97,
+    absolute = 0x8F,
+    indirect_x = 0x83
+)]
+pub struct SAX(AddressMode);
+
+/// Unstable: stores X & (high byte of the address + 1)
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, absolute_y = 0x9E)]
+pub struct SHX(AddressMode);
+
+/// Unstable: stores Y & (high byte of the address + 1)
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, absolute_x = 0x9C)]
+pub struct SHY(AddressMode);
+
+/// Two byte `NOP`, which reads and ignores its operand
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    mnemonic = "NOP",
+    immediate(0x80, 0x82, 0x89, 0xC2, 0xE2),
+    zero(0x04, 0x44, 0x64),
+    zero_x(0x14, 0x34, 0x54, 0x74, 0xD4, 0xF4)
+)]
+pub struct SKB(AddressMode, u8);
+
+/// Three byte `NOP`, which reads and ignores its operand
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    mnemonic = "NOP",
+    absolute = 0x0C,
+    absolute_x(0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC)
+)]
+pub struct SKW(AddressMode, u8);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    zero = 0x07,
+    zero_x = 0x17,
+    absolute = 0x0F,
+    absolute_x = 0x1F,
+    absolute_y = 0x1B,
+    indirect_x = 0x03,
+    indirect_y = 0x13
+)]
+pub struct SLO(AddressMode);
+
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    zero = 0x47,
+    zero_x = 0x57,
+    absolute = 0x4F,
+    absolute_x = 0x5F,
+    absolute_y = 0x5B,
+    indirect_x = 0x43,
+    indirect_y = 0x53
+)]
+pub struct SRE(AddressMode);
+
+/// Unstable: sets S to A & X, then stores S & (high byte of the address + 1)
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, absolute_y = 0x9B)]
+pub struct TAS(AddressMode);
+
+/// The other one byte `NOP`s
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(
+    unofficial,
+    mnemonic = "NOP",
+    implicit(0x1A, 0x3A, 0x5A, 0x7A, 0xDA, 0xFA)
+)]
+pub struct UNOP(AddressMode, u8);
+
+/// `SBC` immediate's duplicate
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, mnemonic = "SBC", immediate = 0xEB)]
+pub struct USBC(AddressMode);
+
+/// Unstable: A = (A | a chip-dependent constant) & X & the operand
+#[cfg(feature = "unofficial")]
+#[derive(Asm6502, Debug, PartialEq)]
+#[asm6502(unofficial, immediate = 0x8B)]
+pub struct XAA(AddressMode);
+
 #[test]
 fn test_adc() {
     use AddressMode::*;
@@ -552,3 +979,95 @@ fn test_adc() {
         ADC(AbsoluteY(0x0201))
     );
 }
+
+#[test]
+fn test_opcode_for() {
+    use AddressMode::*;
+
+    assert_eq!(ADC::opcode_for(&Immediate(0)), Some(0x69));
+    assert_eq!(STA::opcode_for(&Immediate(0)), None);
+    assert_eq!(Opcode::opcode_for("JMP", &Indirect(0)), Some(0x6C));
+    assert_eq!(Opcode::opcode_for("LSR", &Accumulator), Some(0x4A));
+    assert_eq!(Opcode::opcode_for("XYZ", &Implicit), None);
+    let unofficial = if cfg!(feature = "unofficial") { 23 } else { 0 };
+    assert_eq!(MNEMONICS.len(), 56 + unofficial);
+}
+
+#[test]
+fn test_encode() {
+    use AddressMode::*;
+
+    assert_eq!(
+        STA::new(Immediate(1)),
+        Err(EncodeError::UnsupportedMode {
+            mnemonic: "STA",
+            mode: Immediate(1),
+        })
+    );
+    assert_eq!(STA::new(Zero(0x10)).unwrap().encode(), [0x85, 0x10]);
+    assert_eq!(BNE(Relative(-8)).encode(), [0xD0, 0xF8]);
+    assert_eq!(
+        Opcode::new("JMP", Indirect(0xFFFC)).unwrap().encode(),
+        [0x6C, 0xFC, 0xFF]
+    );
+    assert!(matches!(
+        Opcode::new("XYZ", Implicit),
+        Err(EncodeError::UnknownMnemonic(_))
+    ));
+
+    let mut out = Vec::new();
+    LDA(Immediate(1)).write_to(&mut out).unwrap();
+    Opcode::new("RTS", Implicit)
+        .unwrap()
+        .write_to(&mut out)
+        .unwrap();
+    assert_eq!(out, [0xA9, 0x01, 0x60]);
+}
+
+#[test]
+fn test_encode_round_trip() {
+    for opcode in 0..=0xFF {
+        let bytes = [opcode, 0x34, 0x12];
+        if let Some(decoded) = Opcode::from_peekable(&mut bytes.iter().peekable()) {
+            let size = usize::from(decoded.size());
+            assert_eq!(decoded.encode(), &bytes[..size], "{}", decoded);
+        }
+    }
+}
+
+#[cfg(feature = "unofficial")]
+#[test]
+fn test_unofficial() {
+    use AddressMode::*;
+
+    for opcode in 0..=0xFF {
+        let bytes = [opcode, 0x34, 0x12];
+        let decoded = Opcode::from_peekable(&mut bytes.iter().peekable());
+        assert!(decoded.is_some(), "${:02x}", opcode);
+    }
+
+    let lax = Opcode::from_peekable(&mut b"\xA7\x10".iter().peekable()).unwrap();
+    assert_eq!(lax, Opcode::LAX(LAX(Zero(0x10))));
+    assert!(lax.unofficial());
+    assert_eq!(lax.to_string(), "*LAX $10");
+
+    let nop = Opcode::from_peekable(&mut b"\x7C\x00\x02".iter().peekable()).unwrap();
+    assert_eq!(nop.to_string(), "*NOP $0200, X");
+    assert_eq!(nop.encode(), [0x7C, 0x00, 0x02]);
+    assert_eq!(
+        SKW::new(AbsoluteX(0x0200)).unwrap().encode(),
+        [0x1C, 0x00, 0x02]
+    );
+
+    assert_eq!(
+        Opcode::new("USBC", Immediate(1)).unwrap().to_string(),
+        "*SBC #$01"
+    );
+    assert_eq!(Opcode::opcode_for("DCP", &IndirectY(0)), Some(0xD3));
+    assert!(!Opcode::new("SBC", Immediate(1)).unwrap().unofficial());
+
+    assert_eq!(
+        crate::assemble("LAX $10\nSKB #1\nSAX $0300\nKIL").unwrap(),
+        [0xA7, 0x10, 0x80, 0x01, 0x8F, 0x00, 0x03, 0x02]
+    );
+}
@@ -0,0 +1,289 @@
+use crate::error::{AsmError, Location};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    /// Mnemonic, register, label or `.directive`
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Spanned {
+    pub token: Token,
+    pub location: Location,
+}
+
+impl Spanned {
+    pub fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.token, Token::Punct(p) if p == punct)
+    }
+
+    /// Identifier text, if the token is one.
+    pub fn ident(&self) -> Option<&str> {
+        match &self.token {
+            Token::Ident(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Cursor over the tokens of one line.
+pub(crate) struct Tokens<'a> {
+    tokens: &'a [Spanned],
+    pos: usize,
+    /// Where the line ends, for errors about missing tokens
+    end: Location,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(tokens: &'a [Spanned], end: Location) -> Self {
+        Tokens {
+            tokens,
+            pos: 0,
+            end,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&'a Spanned> {
+        self.tokens.get(self.pos)
+    }
+
+    /// The token after the next one.
+    pub fn peek_second(&self) -> Option<&'a Spanned> {
+        self.tokens.get(self.pos + 1)
+    }
+
+    pub fn next(&mut self) -> Option<&'a Spanned> {
+        let token = self.peek()?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Position to [`Tokens::rewind`] to when backtracking.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn rewind(&mut self, position: usize) {
+        self.pos = position;
+    }
+
+    pub fn location(&self) -> Location {
+        self.peek().map_or(self.end, |token| token.location)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.tokens.len()
+    }
+
+    pub fn eat_punct(&mut self, punct: &str) -> bool {
+        match self.peek() {
+            Some(token) if token.is_punct(punct) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn expect_punct(&mut self, punct: &str) -> Result<(), AsmError> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(AsmError::new(
+                self.location(),
+                format!("expected '{}'", punct),
+            ))
+        }
+    }
+
+    /// Consumes `register` (case-insensitively) if it's next.
+    pub fn eat_register(&mut self, register: &str) -> bool {
+        match self.peek().and_then(Spanned::ident) {
+            Some(name) if name.eq_ignore_ascii_case(register) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn expect_ident(&mut self) -> Result<&'a str, AsmError> {
+        let location = self.location();
+        match self.next().and_then(Spanned::ident) {
+            Some(name) => Ok(name),
+            None => Err(AsmError::new(location, "expected a name")),
+        }
+    }
+
+    /// Consumes every remaining token.
+    pub fn rest(&mut self) -> &'a [Spanned] {
+        let rest = &self.tokens[self.pos..];
+        self.pos = self.tokens.len();
+        rest
+    }
+
+    pub fn expect_string(&mut self) -> Result<&'a str, AsmError> {
+        let location = self.location();
+        match self.next().map(|token| &token.token) {
+            Some(Token::Str(text)) => Ok(text),
+            _ => Err(AsmError::new(location, "expected a string")),
+        }
+    }
+
+    pub fn expect_end(&self) -> Result<(), AsmError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(AsmError::new(token.location, "unexpected token")),
+        }
+    }
+}
+
+/// Longest first, so `<<` wins over `<`.
+const PUNCTS: [&str; 25] = [
+    "<<", ">>", "<=", ">=", "<>", "==", "!=", "&&", "||", "#", "(", ")", ",", ":", "+", "-", "*",
+    "/", "&", "|", "^", "~", "<", ">", "=",
+];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '@'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits one source line into tokens, dropping a trailing `;` comment.
+pub(crate) fn tokenize(line: &str, line_no: usize) -> Result<Vec<Spanned>, AsmError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let location = Location {
+            line: line_no,
+            column: pos + 1,
+        };
+        let error = |message: String| AsmError::new(location, message);
+
+        let take_while = |pos: &mut usize, pred: &dyn Fn(char) -> bool| {
+            let start = *pos;
+            while *pos < chars.len() && pred(chars[*pos]) {
+                *pos += 1;
+            }
+            chars[start..*pos].iter().collect::<String>()
+        };
+
+        let token = if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            pos += 1;
+            continue;
+        } else if is_ident_start(c) {
+            pos += 1;
+            let rest = take_while(&mut pos, &is_ident_char);
+            Token::Ident(format!("{}{}", c, rest))
+        } else if c.is_ascii_digit() {
+            let digits = take_while(&mut pos, &|c| c.is_ascii_alphanumeric());
+            Token::Number(
+                digits
+                    .parse()
+                    .map_err(|_| error(format!("invalid number '{}'", digits)))?,
+            )
+        } else if c == '$' || (c == '%' && matches!(chars.get(pos + 1), Some('0') | Some('1'))) {
+            let radix = if c == '$' { 16 } else { 2 };
+            pos += 1;
+            let digits = take_while(&mut pos, &|c| c.is_ascii_alphanumeric());
+            Token::Number(
+                i64::from_str_radix(&digits, radix)
+                    .map_err(|_| error(format!("invalid number '{}{}'", c, digits)))?,
+            )
+        } else if c == '\'' {
+            match (chars.get(pos + 1), chars.get(pos + 2)) {
+                (Some(&value), Some('\'')) if value.is_ascii() => {
+                    pos += 3;
+                    Token::Number(value as i64)
+                }
+                _ => return Err(error("invalid character literal".into())),
+            }
+        } else if c == '"' {
+            pos += 1;
+            let text = take_while(&mut pos, &|c| c != '"');
+            if pos == chars.len() {
+                return Err(error("unterminated string".into()));
+            }
+            pos += 1;
+            Token::Str(text)
+        } else {
+            let rest: String = chars[pos..].iter().take(2).collect();
+            match PUNCTS.iter().find(|p| rest.starts_with(*p)) {
+                Some(punct) => {
+                    pos += punct.len();
+                    Token::Punct(punct)
+                }
+                None => return Err(error(format!("unexpected character '{}'", c))),
+            }
+        };
+
+        tokens.push(Spanned { token, location });
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Vec<Token> {
+        tokenize(line, 1)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokens("loop: lda ($10),y ; comment"),
+            vec![
+                Token::Ident("loop".into()),
+                Token::Punct(":"),
+                Token::Ident("lda".into()),
+                Token::Punct("("),
+                Token::Number(0x10),
+                Token::Punct(")"),
+                Token::Punct(","),
+                Token::Ident("y".into()),
+            ]
+        );
+        assert_eq!(
+            tokens(".byte %0101, 'A', 10, \"hi\" << @x"),
+            vec![
+                Token::Ident(".byte".into()),
+                Token::Number(5),
+                Token::Punct(","),
+                Token::Number(65),
+                Token::Punct(","),
+                Token::Number(10),
+                Token::Punct(","),
+                Token::Str("hi".into()),
+                Token::Punct("<<"),
+                Token::Ident("@x".into()),
+            ]
+        );
+
+        let spanned = tokenize("  LDA #$1", 3).unwrap();
+        assert_eq!(spanned[1].location, Location { line: 3, column: 7 });
+        assert!(spanned[1].is_punct("#"));
+
+        let err = tokenize("LDA $xyz", 2).unwrap_err();
+        assert_eq!(err.location, Location { line: 2, column: 5 });
+        assert!(tokenize("\"open", 1).is_err());
+        assert!(tokenize("LDA `", 1).is_err());
+    }
+}
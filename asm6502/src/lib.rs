@@ -1,8 +1,24 @@
+#![allow(clippy::upper_case_acronyms)]
+
+mod assembler;
+mod error;
+mod expr;
 mod instructions;
 mod iter;
+mod lexer;
+mod link;
+mod macros;
+mod object;
+mod output;
 
-pub use crate::instructions::{Instruction, Opcode};
+pub use crate::assembler::{assemble, Assembler, ListingLine, Program};
+pub use crate::error::{AsmError, EncodeError, Expansion, LinkError, Location};
+pub use crate::instructions::{AddressMode, Instruction, InstructionConstruct, Opcode, MNEMONICS};
 pub use crate::iter::opcodes;
+pub use crate::link::{
+    AreaKind, LinkConfig, Linked, LinkedArea, Linker, MemoryArea, SegmentPlacement,
+};
+pub use crate::object::Object;
 
 pub fn dump(data: Vec<u8>) -> Vec<Opcode> {
     let opcodes = opcodes(data.iter().peekable());
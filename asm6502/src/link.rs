@@ -0,0 +1,849 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use nestle_ines::{CartridgeBuilder, Ines};
+
+use crate::assembler::{byte, word};
+use crate::error::{AsmError, LinkError, Location};
+use crate::expr::{self, Value};
+use crate::lexer::{tokenize, Spanned, Tokens};
+use crate::object::{Deferred, Definition, FixupKind, Object};
+
+/// How deep symbols may be defined in terms of each other before they're
+/// assumed to be circular.
+const MAX_DEPTH: usize = 64;
+
+/// Size of the CHR-ROM banks [`Linked::ines`] splits CHR areas into.
+const CHR_BANK: usize = 0x2000;
+
+/// What a memory area holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaKind {
+    /// PRG-ROM
+    Rom,
+    /// RAM, which segments reserve space in but isn't written out
+    Ram,
+    /// CHR-ROM
+    Chr,
+}
+
+/// A `memory` line of a [`LinkConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryArea {
+    pub name: String,
+    pub start: u16,
+    pub size: usize,
+    /// Byte to pad the area to its full size with. Without one only the
+    /// bytes up to the last one used are written.
+    pub fill: Option<u8>,
+    pub kind: AreaKind,
+    /// PRG bank of a ROM area in [`Linked::ines`], by default the next one
+    pub bank: Option<usize>,
+}
+
+/// A `segment` line of a [`LinkConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentPlacement {
+    pub name: String,
+    /// Memory area the segment goes in
+    pub load: String,
+    /// Fixed address, instead of straight after the segment before
+    pub start: Option<u16>,
+    pub align: Option<usize>,
+}
+
+/// Memory layout for the [`Linker`], one declaration per line:
+///
+/// ```text
+/// ; UxROM with two 16 KiB banks
+/// memory ZP:   start = $00,   size = $100,  type = ram
+/// memory RAM:  start = $300,  size = $500,  type = ram
+/// memory PRG0: start = $8000, size = $4000, fill = $FF, bank = 0
+/// memory PRG1: start = $C000, size = $4000, fill = $FF, bank = 1
+/// memory CHR:  start = $0000, size = $2000, type = chr
+///
+/// segment ZEROPAGE: load = ZP
+/// segment BSS:      load = RAM
+/// segment BANK_0:   load = PRG0
+/// segment CODE:     load = PRG1
+/// segment RODATA:   load = PRG1, align = $100
+/// segment VECTORS:  load = PRG1, start = $FFFA
+/// segment CHARS:    load = CHR
+/// ```
+///
+/// An area's `type` is `rom` (the default), `ram` or `chr`. Segments are
+/// placed in the order they're listed, each at its `start` or straight after
+/// the one before in the same area. Areas have to be declared before the
+/// segments that go in them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkConfig {
+    pub memory: Vec<MemoryArea>,
+    pub segments: Vec<SegmentPlacement>,
+}
+
+enum Attribute {
+    Number(i64),
+    Name(String),
+}
+
+/// `key = value, ...` after the name on a config line.
+struct Attributes {
+    values: BTreeMap<String, (Location, Attribute)>,
+    end: Location,
+}
+
+impl Attributes {
+    fn parse(tokens: &mut Tokens, end: Location) -> Result<Self, AsmError> {
+        let mut values = BTreeMap::new();
+        while !tokens.is_empty() {
+            let location = tokens.location();
+            let key = tokens.expect_ident()?.to_ascii_lowercase();
+            tokens.expect_punct("=")?;
+            let value = match tokens.peek().and_then(Spanned::ident) {
+                Some(name) => {
+                    tokens.next();
+                    Attribute::Name(name.to_string())
+                }
+                None => {
+                    let expr = expr::parse(tokens)?;
+                    let resolve = |name: &str, location| {
+                        Err(AsmError::new(
+                            location,
+                            format!("undefined symbol '{}'", name),
+                        ))
+                    };
+                    let value = expr.eval(Value::known(0), &resolve)?;
+                    Attribute::Number(value.map_or(0, |value| value.value))
+                }
+            };
+            if values.insert(key.clone(), (location, value)).is_some() {
+                return Err(AsmError::new(location, format!("'{}' is given twice", key)));
+            }
+            if !tokens.eat_punct(",") {
+                break;
+            }
+        }
+        tokens.expect_end()?;
+        Ok(Attributes { values, end })
+    }
+
+    fn number(&mut self, key: &str, range: RangeInclusive<i64>) -> Result<Option<i64>, AsmError> {
+        match self.values.remove(key) {
+            None => Ok(None),
+            Some((_, Attribute::Number(value))) if range.contains(&value) => Ok(Some(value)),
+            Some((location, _)) => Err(AsmError::new(
+                location,
+                format!(
+                    "'{}' must be a number from ${:X} to ${:X}",
+                    key,
+                    range.start(),
+                    range.end()
+                ),
+            )),
+        }
+    }
+
+    fn name(&mut self, key: &str) -> Result<Option<(Location, String)>, AsmError> {
+        match self.values.remove(key) {
+            None => Ok(None),
+            Some((location, Attribute::Name(name))) => Ok(Some((location, name))),
+            Some((location, _)) => {
+                Err(AsmError::new(location, format!("'{}' must be a name", key)))
+            }
+        }
+    }
+
+    fn required<T>(&self, value: Option<T>, key: &str) -> Result<T, AsmError> {
+        value.ok_or_else(|| AsmError::new(self.end, format!("missing '{}'", key)))
+    }
+
+    /// Checks every attribute was used.
+    fn finish(self) -> Result<(), AsmError> {
+        match self.values.into_iter().next() {
+            Some((key, (location, _))) => Err(AsmError::new(
+                location,
+                format!("unknown attribute '{}'", key),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl LinkConfig {
+    pub fn parse(text: &str) -> Result<Self, Vec<AsmError>> {
+        let mut config = LinkConfig::default();
+        let errors: Vec<AsmError> = text
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| config.line(line, index + 1).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Vec<AsmError>> {
+        let path = path.as_ref();
+        let in_file = |errors: Vec<AsmError>| {
+            errors
+                .into_iter()
+                .map(|error| error.in_file(Some(path)))
+                .collect()
+        };
+        let text = fs::read_to_string(path).map_err(|err| {
+            let location = Location { line: 1, column: 1 };
+            in_file(vec![AsmError::new(
+                location,
+                format!("can't read: {}", err),
+            )])
+        })?;
+        Self::parse(&text).map_err(in_file)
+    }
+
+    fn area(&self, name: &str) -> Option<&MemoryArea> {
+        self.memory.iter().find(|area| area.name == name)
+    }
+
+    fn line(&mut self, text: &str, line_no: usize) -> Result<(), AsmError> {
+        let spanned = tokenize(text, line_no)?;
+        let end = Location {
+            line: line_no,
+            column: text.chars().count() + 1,
+        };
+        let mut tokens = Tokens::new(&spanned, end);
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        let location = tokens.location();
+        let keyword = tokens.expect_ident()?.to_ascii_lowercase();
+        if keyword != "memory" && keyword != "segment" {
+            return Err(AsmError::new(
+                location,
+                format!("expected 'memory' or 'segment', not '{}'", keyword),
+            ));
+        }
+        let name_location = tokens.location();
+        let name = tokens.expect_ident()?.to_string();
+        tokens.expect_punct(":")?;
+        let mut attributes = Attributes::parse(&mut tokens, end)?;
+        let already_defined =
+            || AsmError::new(name_location, format!("'{}' is already defined", name));
+
+        if keyword == "memory" {
+            if self.area(&name).is_some() {
+                return Err(already_defined());
+            }
+            let start = attributes.number("start", 0..=0xFFFF)?;
+            let start = attributes.required(start, "start")?;
+            let size = attributes.number("size", 1..=0x10000)?;
+            let size = attributes.required(size, "size")?;
+            if start + size > 0x10000 {
+                return Err(AsmError::new(name_location, "area runs past $FFFF"));
+            }
+            let fill = attributes.number("fill", -128..=255)?;
+            let kind = match attributes.name("type")? {
+                None => AreaKind::Rom,
+                Some((location, kind)) => match kind.to_ascii_lowercase().as_str() {
+                    "rom" => AreaKind::Rom,
+                    "ram" => AreaKind::Ram,
+                    "chr" => AreaKind::Chr,
+                    _ => {
+                        return Err(AsmError::new(
+                            location,
+                            format!("unknown area type '{}'", kind),
+                        ))
+                    }
+                },
+            };
+            let bank = attributes.number("bank", 0..=0xFFFF)?;
+            attributes.finish()?;
+            self.memory.push(MemoryArea {
+                name,
+                start: start as u16,
+                size: size as usize,
+                fill: fill.map(|fill| fill as u8),
+                kind,
+                bank: bank.map(|bank| bank as usize),
+            });
+        } else {
+            if self.segments.iter().any(|segment| segment.name == name) {
+                return Err(already_defined());
+            }
+            let load = attributes.name("load")?;
+            let (load_location, load) = attributes.required(load, "load")?;
+            let area = self.area(&load).ok_or_else(|| {
+                AsmError::new(load_location, format!("no memory area '{}'", load))
+            })?;
+            let area_range = i64::from(area.start)..i64::from(area.start) + area.size as i64;
+            let start = attributes.number("start", 0..=0xFFFF)?;
+            if let Some(start) = start.filter(|start| !area_range.contains(start)) {
+                return Err(AsmError::new(
+                    name_location,
+                    format!("${:04X} is outside '{}'", start, load),
+                ));
+            }
+            let align = attributes.number("align", 1..=0x10000)?;
+            attributes.finish()?;
+            self.segments.push(SegmentPlacement {
+                name,
+                load,
+                start: start.map(|start| start as u16),
+                align: align.map(|align| align as usize),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A memory area after linking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkedArea {
+    pub name: String,
+    pub start: u16,
+    pub kind: AreaKind,
+    /// PRG bank of a ROM area, see [`MemoryArea::bank`]
+    pub bank: Option<usize>,
+    /// Contents up to the last byte used, or the whole area if it has a
+    /// [`MemoryArea::fill`]
+    pub bytes: Vec<u8>,
+}
+
+/// Output of [`Linker::link`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linked {
+    /// In config order
+    pub areas: Vec<LinkedArea>,
+    /// Exported symbols
+    pub symbols: BTreeMap<String, i64>,
+}
+
+impl Linked {
+    /// The ROM and CHR areas back to back, as a flat binary.
+    pub fn flat(&self) -> Vec<u8> {
+        self.areas
+            .iter()
+            .filter(|area| area.kind != AreaKind::Ram)
+            .flat_map(|area| area.bytes.iter().copied())
+            .collect()
+    }
+
+    /// Builds an iNES image with each ROM area placed in its PRG bank and
+    /// the CHR areas as CHR-ROM. Header settings like the mapper come from
+    /// `builder`, and areas bigger than a 16 KiB bank need its
+    /// [`CartridgeBuilder::prg_bank_size`] raised.
+    pub fn ines(&self, builder: &CartridgeBuilder) -> nestle_ines::Result<Ines> {
+        let mut builder = builder.clone();
+        for area in &self.areas {
+            match area.kind {
+                AreaKind::Rom => {
+                    builder.place(area.bank.unwrap_or(0), area.start, &area.bytes)?;
+                }
+                AreaKind::Chr => {
+                    for bank in area.bytes.chunks(CHR_BANK) {
+                        builder.push_chr_bank(bank)?;
+                    }
+                }
+                AreaKind::Ram => {}
+            }
+        }
+        builder.build()
+    }
+}
+
+/// Where a section was put: the index of its memory area and its address.
+type Placement = Option<(usize, i64)>;
+
+/// Looks up symbols across modules once the sections are placed.
+struct Resolver<'a> {
+    objects: &'a [Object],
+    placements: &'a [Vec<Placement>],
+    /// Module exporting each symbol
+    exports: &'a BTreeMap<&'a str, usize>,
+}
+
+impl Resolver<'_> {
+    fn address(&self, module: usize, section: usize) -> i64 {
+        self.placements[module][section].map_or(0, |(_, address)| address)
+    }
+
+    fn symbol(
+        &self,
+        module: usize,
+        key: &str,
+        location: Location,
+        depth: usize,
+    ) -> Result<i64, AsmError> {
+        if depth == MAX_DEPTH {
+            return Err(AsmError::new(
+                location,
+                format!("'{}' is defined in terms of itself", key),
+            ));
+        }
+        match self.objects[module].symbols.get(key) {
+            Some(Definition::Constant(value)) => Ok(*value),
+            Some(Definition::Label { section, offset }) => {
+                Ok(self.address(module, *section) + offset)
+            }
+            Some(Definition::Expr(deferred)) => self.eval(module, deferred, depth + 1),
+            Some(Definition::Import) => self.symbol(self.exports[key], key, location, depth + 1),
+            None => Err(AsmError::new(
+                location,
+                format!("undefined symbol '{}'", key),
+            )),
+        }
+    }
+
+    fn eval(&self, module: usize, deferred: &Deferred, depth: usize) -> Result<i64, AsmError> {
+        let pc = Value::known(self.address(module, deferred.section) + deferred.pc);
+        let resolve = |name: &str, location| {
+            self.symbol(module, name, location, depth)
+                .map(|value| Some(Value::known(value)))
+        };
+        deferred
+            .expr
+            .eval(pc, &resolve)
+            .map(|value| value.map_or(0, |value| value.value))
+            .map_err(|error| {
+                error
+                    .in_file(deferred.site.file.as_deref())
+                    .expanded_from(&deferred.site.expansions)
+            })
+    }
+}
+
+/// Places the segments of [`Object`]s in the memory areas of a
+/// [`LinkConfig`], then fills in the addresses the assembler left open.
+///
+/// ```
+/// use asm6502::{Assembler, LinkConfig, Linker};
+///
+/// let config = LinkConfig::parse(
+///     "
+///     memory ZP:  start = $00, size = $100, type = ram
+///     memory PRG: start = $C000, size = $4000, fill = $FF
+///     segment ZEROPAGE: load = ZP
+///     segment CODE:     load = PRG
+///     segment VECTORS:  load = PRG, start = $FFFA
+///     ",
+/// )
+/// .unwrap();
+/// let main = Assembler::new()
+///     .assemble_object(
+///         "
+///         .import clear
+///         .segment \"ZEROPAGE\"
+/// count:  .res 1
+///         .segment \"CODE\"
+/// reset:  JSR clear
+///         INC count
+///         JMP reset
+///         .segment \"VECTORS\"
+///         .word 0, reset, 0
+///         ",
+///     )
+///     .unwrap();
+/// let lib = Assembler::new()
+///     .assemble_object(".export clear\nclear: LDA #0\nRTS")
+///     .unwrap();
+///
+/// let linked = Linker::new(config).object(main).object(lib).link().unwrap();
+/// let prg = &linked.areas[1].bytes;
+/// assert_eq!(&prg[..8], &[0x20, 0x08, 0xC0, 0xE6, 0x00, 0x4C, 0x00, 0xC0]);
+/// assert_eq!(&prg[0x3FFA..], &[0, 0, 0x00, 0xC0, 0, 0]);
+/// assert_eq!(linked.symbols["clear"], 0xC008);
+/// ```
+///
+/// Each segment holds the sections of that name from every module, in the
+/// order the modules were added.
+#[derive(Debug, Clone)]
+pub struct Linker {
+    config: LinkConfig,
+    objects: Vec<Object>,
+}
+
+impl Linker {
+    pub fn new(config: LinkConfig) -> Self {
+        Linker {
+            config,
+            objects: Vec::new(),
+        }
+    }
+
+    pub fn object(&mut self, object: Object) -> &mut Self {
+        self.objects.push(object);
+        self
+    }
+
+    pub fn link(&self) -> Result<Linked, Vec<LinkError>> {
+        let objects = &self.objects;
+        let mut errors = Vec::new();
+
+        let mut placements: Vec<Vec<Placement>> = objects
+            .iter()
+            .map(|object| vec![None; object.sections.len()])
+            .collect();
+        let mut images = Vec::new();
+        for (area_index, area) in self.config.memory.iter().enumerate() {
+            let start = i64::from(area.start);
+            let end = start + area.size as i64;
+            let mut image = vec![area.fill.unwrap_or(0); area.size];
+            let mut pc = start;
+
+            let segments = self.config.segments.iter();
+            for segment in segments.filter(|segment| segment.load == area.name) {
+                if let Some(address) = segment.start.map(i64::from) {
+                    if address < pc {
+                        errors.push(LinkError::Layout(format!(
+                            "segment '{}' at ${:04X} overlaps what comes before it up to ${:04X}",
+                            segment.name,
+                            address,
+                            pc - 1
+                        )));
+                    }
+                    pc = pc.max(address);
+                }
+                if let Some(align) = segment.align.map(|align| align as i64) {
+                    pc = (pc + align - 1) / align * align;
+                }
+
+                let mut zeropage = false;
+                for (module, object) in objects.iter().enumerate() {
+                    for (index, section) in object.sections.iter().enumerate() {
+                        if section.name != segment.name {
+                            continue;
+                        }
+                        placements[module][index] = Some((area_index, pc));
+                        zeropage |= section.zeropage;
+                        let section_end = pc + section.bytes.len() as i64;
+                        if section_end <= end {
+                            image[(pc - start) as usize..(section_end - start) as usize]
+                                .copy_from_slice(&section.bytes);
+                        }
+                        pc = section_end;
+                    }
+                }
+                if zeropage && pc > 0x100 {
+                    errors.push(LinkError::Layout(format!(
+                        "zero page segment '{}' runs past $FF",
+                        segment.name
+                    )));
+                }
+            }
+
+            if pc > end {
+                errors.push(LinkError::Layout(format!(
+                    "memory area '{}' overflows by {} bytes",
+                    area.name,
+                    pc - end
+                )));
+            }
+            if area.fill.is_none() {
+                image.truncate(((pc - start) as usize).min(area.size));
+            }
+            images.push(image);
+        }
+
+        let mut unplaced = BTreeSet::new();
+        for (module, object) in objects.iter().enumerate() {
+            for (index, section) in object.sections.iter().enumerate() {
+                let labelled = object.symbols.values().any(|definition| {
+                    matches!(definition, Definition::Label { section, .. } if *section == index)
+                });
+                if placements[module][index].is_none() && (!section.bytes.is_empty() || labelled) {
+                    unplaced.insert(section.name.as_str());
+                }
+            }
+        }
+        for name in unplaced {
+            errors.push(LinkError::Layout(format!(
+                "segment '{}' isn't in the link config",
+                name
+            )));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut exports = BTreeMap::new();
+        for (module, object) in objects.iter().enumerate() {
+            for (name, site) in &object.exports {
+                if exports.insert(name.as_str(), module).is_some() {
+                    let message = format!("'{}' is exported more than once", name);
+                    errors.push(site.error(message).into());
+                }
+            }
+        }
+        for object in objects {
+            for (name, site) in &object.imports {
+                if !exports.contains_key(name.as_str()) {
+                    let message = format!("no module exports '{}'", name);
+                    errors.push(site.error(message).into());
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let resolver = Resolver {
+            objects,
+            placements: &placements,
+            exports: &exports,
+        };
+        for (module, object) in objects.iter().enumerate() {
+            for (index, section) in object.sections.iter().enumerate() {
+                let (area, address) = match placements[module][index] {
+                    Some(placement) => placement,
+                    None => continue,
+                };
+                let area_start = i64::from(self.config.memory[area].start);
+                for fixup in &section.fixups {
+                    let site = &fixup.value.site;
+                    let patch =
+                        resolver
+                            .eval(module, &fixup.value, 0)
+                            .and_then(|value| match fixup.kind {
+                                FixupKind::Byte => byte(value)
+                                    .map(|value| vec![value])
+                                    .map_err(|message| site.error(message)),
+                                FixupKind::Word => word(value)
+                                    .map(|value| value.to_le_bytes().to_vec())
+                                    .map_err(|message| site.error(message)),
+                                FixupKind::Branch => {
+                                    let offset = value - (address + fixup.value.pc + 2);
+                                    match offset {
+                                        -128..=127 => Ok(vec![offset as u8]),
+                                        _ => Err(site.error(format!(
+                                            "branch target ${:04X} is {} bytes away, out of range",
+                                            value, offset
+                                        ))),
+                                    }
+                                }
+                            });
+                    match patch {
+                        Ok(bytes) => {
+                            let at = (address + fixup.offset - area_start) as usize;
+                            images[area][at..at + bytes.len()].copy_from_slice(&bytes);
+                        }
+                        Err(error) => errors.push(error.into()),
+                    }
+                }
+            }
+        }
+
+        let mut symbols = BTreeMap::new();
+        for (module, object) in objects.iter().enumerate() {
+            for (name, site) in &object.exports {
+                match resolver.symbol(module, name, site.location, 0) {
+                    Ok(value) => {
+                        symbols.insert(name.clone(), value);
+                    }
+                    Err(error) => {
+                        let error = error
+                            .in_file(site.file.as_deref())
+                            .expanded_from(&site.expansions);
+                        errors.push(error.into());
+                    }
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut next_bank = 0;
+        let areas = self
+            .config
+            .memory
+            .iter()
+            .zip(images)
+            .map(|(area, bytes)| {
+                let bank = (area.kind == AreaKind::Rom).then(|| {
+                    let bank = area.bank.unwrap_or(next_bank);
+                    next_bank = bank + 1;
+                    bank
+                });
+                LinkedArea {
+                    name: area.name.clone(),
+                    start: area.start,
+                    kind: area.kind,
+                    bank,
+                    bytes,
+                }
+            })
+            .collect();
+        Ok(Linked { areas, symbols })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Assembler;
+
+    const CONFIG: &str = "
+        memory ZP:   start = $00,   size = $100,  type = ram
+        memory PRG0: start = $8000, size = $4000
+        memory PRG1: start = $C000, size = $4000, fill = $FF
+        memory CHR:  start = $0000, size = $2000, type = chr
+        segment ZEROPAGE: load = ZP
+        segment BANK_0:   load = PRG0
+        segment CODE:     load = PRG1
+        segment RODATA:   load = PRG1, align = $100
+        segment VECTORS:  load = PRG1, start = $FFFA
+        segment CHARS:    load = CHR
+    ";
+
+    fn link(config: &str, sources: &[&str]) -> Result<Linked, Vec<LinkError>> {
+        let mut linker = Linker::new(LinkConfig::parse(config).unwrap());
+        for source in sources {
+            linker.object(Assembler::new().assemble_object(source).unwrap());
+        }
+        linker.link()
+    }
+
+    fn error(config: &str, sources: &[&str]) -> String {
+        link(config, sources).unwrap_err().remove(0).to_string()
+    }
+
+    #[test]
+    fn test_config() {
+        let config = LinkConfig::parse(CONFIG).unwrap();
+        assert_eq!(
+            config.memory[2],
+            MemoryArea {
+                name: "PRG1".into(),
+                start: 0xC000,
+                size: 0x4000,
+                fill: Some(0xFF),
+                kind: AreaKind::Rom,
+                bank: None,
+            }
+        );
+        assert_eq!(config.memory[3].kind, AreaKind::Chr);
+        assert_eq!(config.segments[3].align, Some(0x100));
+        assert_eq!(config.segments[4].start, Some(0xFFFA));
+
+        let errors = LinkConfig::parse(
+            "memory A: start = 0
+memory B: start = $8000, size = $4000, colour = red
+segment S: load = C
+block X: start = 0
+memory D: start = $F000, size = $2000
+memory E: start = $8000, size = $100
+segment T: load = E, start = $4000",
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 6);
+        assert_eq!(errors[0].to_string(), "1:20: missing 'size'");
+        assert_eq!(errors[1].to_string(), "2:40: unknown attribute 'colour'");
+        assert_eq!(errors[2].to_string(), "3:12: no memory area 'C'");
+        assert!(errors[3].message.contains("expected 'memory'"));
+        assert!(errors[4].message.contains("past $FFFF"));
+        assert!(errors[5].message.contains("outside 'E'"));
+    }
+
+    #[test]
+    fn test_link() {
+        let main = "
+            .importzp ptr
+            .import table, bank_routine
+            .export reset
+            .segment \"CODE\"
+            reset:  LDA #<table
+                    STA ptr
+                    LDA #>table
+                    STA ptr+1
+            @loop:  DEX
+                    BNE @loop
+                    JSR bank_routine
+                    BEQ reset
+            .segment \"VECTORS\"
+                    .word 0, reset, 0
+            .segment \"CHARS\"
+                    .byte $FF
+        ";
+        let lib = "
+            .export ptr, table, bank_routine, entry
+            .segment \"ZEROPAGE\"
+                    .res 2
+            ptr:    .res 2
+            .segment \"RODATA\"
+            table:  .byte 1, 2, 3
+            entry = table + 1
+            .segment \"BANK_0\"
+            bank_routine: RTS
+            .segment \"CODE\"
+                    NOP
+        ";
+        let linked = link(CONFIG, &[main, lib]).unwrap();
+        assert_eq!(linked.symbols["ptr"], 2);
+        assert_eq!(linked.symbols["entry"], 0xC101);
+        assert_eq!(linked.symbols["reset"], 0xC000);
+
+        assert_eq!(linked.areas[1].bytes, [0x60]);
+        assert_eq!(linked.areas[1].bank, Some(0));
+        let prg = &linked.areas[2].bytes;
+        assert_eq!(linked.areas[2].bank, Some(1));
+        assert_eq!(prg.len(), 0x4000);
+        assert_eq!(
+            &prg[..0x12],
+            &[
+                0xA9, 0x00, 0x85, 0x02, 0xA9, 0xC1, 0x85, 0x03, 0xCA, 0xD0, 0xFD, 0x20, 0x00, 0x80,
+                0xF0, 0xF0, 0xEA, 0xFF
+            ]
+        );
+        assert_eq!(&prg[0x100..0x104], &[1, 2, 3, 0xFF]);
+        assert_eq!(&prg[0x3FFA..], &[0, 0, 0x00, 0xC0, 0, 0]);
+        assert_eq!(linked.flat().len(), 1 + 0x4000 + 1);
+
+        let ines = linked.ines(Ines::builder().mapper(2)).unwrap();
+        assert_eq!(ines.header.mapper_number(), 2);
+        assert_eq!(ines.prg.len(), 0x8000);
+        assert_eq!(&ines.prg[..2], &[0x60, 0xFF]);
+        assert_eq!(&ines.prg[0x4000..], &prg[..]);
+        assert_eq!(ines.chr.as_ref().map(|chr| chr[0]), Some(0xFF));
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let config = "
+            memory ZP:  start = $00, size = $200, type = ram
+            memory LOW: start = $8000, size = $100
+            memory PRG: start = $C000, size = $4000
+            segment ZEROPAGE: load = ZP
+            segment LOW:      load = LOW
+            segment CODE:     load = PRG
+            segment VECTORS:  load = PRG, start = $C010
+        ";
+        assert_eq!(
+            error(config, &[".import missing\nJMP missing"]),
+            "1:9: no module exports 'missing'"
+        );
+        assert!(
+            error(config, &[".export x\nx: NOP", ".export x\nx: NOP"]).contains("more than once")
+        );
+        assert!(error(config, &[".segment \"LOW\"\n.res $101"]).contains("overflows by 1"));
+        assert!(error(config, &[".segment \"NOPE\"\nNOP"]).contains("'NOPE' isn't in"));
+        assert!(error(config, &[".segment \"ZEROPAGE\"\n.res $101"]).contains("past $FF"));
+        assert!(error(config, &[".res 17\n.segment \"VECTORS\"\nNOP"]).contains("overlaps"));
+
+        let far = ".segment \"LOW\"\n.export far\nfar: RTS";
+        assert_eq!(
+            error(config, &[".import far\nBNE far", far]),
+            "2:5: branch target $8000 is -16386 bytes away, out of range"
+        );
+        assert!(error(config, &[".import far\nLDA #far", far]).contains("doesn't fit in a byte"));
+        assert!(error(
+            config,
+            &[".import b\n.export a\na = b", ".import a\n.export b\nb = a"]
+        )
+        .contains("in terms of itself"));
+    }
+}
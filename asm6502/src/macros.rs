@@ -0,0 +1,225 @@
+use std::path::PathBuf;
+
+use crate::error::{AsmError, Location};
+use crate::lexer::{Spanned, Token};
+
+/// A tokenized source line.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Line {
+    /// Source text, for the listing
+    pub text: String,
+    pub tokens: Vec<Spanned>,
+    /// Where the line ends, for errors about missing tokens
+    pub end: Location,
+}
+
+/// Replaces identifiers `replace` has tokens for. The replacements take the
+/// location of the identifier they stand in for.
+pub(crate) fn substitute(
+    tokens: &[Spanned],
+    replace: &dyn Fn(&str) -> Option<Vec<Token>>,
+) -> Vec<Spanned> {
+    let mut out = Vec::with_capacity(tokens.len());
+    for spanned in tokens {
+        match spanned.ident().and_then(replace) {
+            Some(replacement) => out.extend(replacement.into_iter().map(|token| Spanned {
+                token,
+                location: spanned.location,
+            })),
+            None => out.push(spanned.clone()),
+        }
+    }
+    out
+}
+
+/// Splits macro arguments at commas outside parentheses.
+pub(crate) fn split_args(tokens: &[Spanned]) -> Vec<Vec<Token>> {
+    let mut args = Vec::new();
+    if tokens.is_empty() {
+        return args;
+    }
+
+    let mut current = Vec::new();
+    let mut depth = 0usize;
+    for spanned in tokens {
+        match &spanned.token {
+            Token::Punct(",") if depth == 0 => {
+                args.push(current);
+                current = Vec::new();
+                continue;
+            }
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        current.push(spanned.token.clone());
+    }
+    args.push(current);
+    args
+}
+
+/// A `.macro` definition.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Line>,
+    /// File the macro was defined in
+    pub file: Option<PathBuf>,
+}
+
+impl Macro {
+    /// Body lines with `args` substituted for the parameters. `@labels` get
+    /// `id` appended, so every expansion has its own.
+    pub fn expand(
+        &self,
+        args: Vec<Vec<Token>>,
+        id: usize,
+        location: Location,
+    ) -> Result<Vec<Line>, AsmError> {
+        if args.len() > self.params.len() {
+            return Err(AsmError::new(
+                location,
+                format!(
+                    "'{}' takes {} arguments, got {}",
+                    self.name,
+                    self.params.len(),
+                    args.len()
+                ),
+            ));
+        }
+
+        let replace = |name: &str| {
+            if let Some(index) = self.params.iter().position(|param| param == name) {
+                // Missing trailing arguments expand to nothing
+                return Some(args.get(index).cloned().unwrap_or_default());
+            }
+            if name.starts_with('@') {
+                return Some(vec![Token::Ident(format!("{}#{}", name, id))]);
+            }
+            None
+        };
+        Ok(self
+            .body
+            .iter()
+            .map(|line| Line {
+                text: line.text.clone(),
+                tokens: substitute(&line.tokens, &replace),
+                end: line.end,
+            })
+            .collect())
+    }
+}
+
+/// Block directives whose lines are collected before being assembled.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum BlockKind {
+    Macro { name: String, params: Vec<String> },
+    Repeat { count: i64, var: Option<String> },
+}
+
+/// A `.macro` or `.repeat` block being collected.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Block {
+    pub kind: BlockKind,
+    pub lines: Vec<Line>,
+    /// Blocks of either kind opened inside this one, which the next closing
+    /// directive belongs to
+    pub nesting: usize,
+    pub file: Option<PathBuf>,
+    pub location: Location,
+}
+
+impl Block {
+    pub fn directive(&self) -> &'static str {
+        match self.kind {
+            BlockKind::Macro { .. } => ".macro",
+            BlockKind::Repeat { .. } => ".repeat",
+        }
+    }
+
+    pub fn end_directive(&self) -> &'static str {
+        match self.kind {
+            BlockKind::Macro { .. } => ".endmacro",
+            BlockKind::Repeat { .. } => ".endrepeat",
+        }
+    }
+}
+
+/// State of one `.if` block.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Condition {
+    /// Lines are being assembled
+    pub active: bool,
+    /// A branch was taken, or the enclosing block is inactive, so later
+    /// branches can't be
+    pub done: bool,
+    pub seen_else: bool,
+    pub file: Option<PathBuf>,
+    pub location: Location,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::lexer::tokenize;
+
+    fn line(text: &str) -> Line {
+        Line {
+            text: text.to_string(),
+            tokens: tokenize(text, 1).unwrap(),
+            end: Location {
+                line: 1,
+                column: text.len() + 1,
+            },
+        }
+    }
+
+    fn tokens(line: &Line) -> Vec<Token> {
+        line.tokens
+            .iter()
+            .map(|spanned| spanned.token.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        let args = split_args(&tokenize("($10), 2 + 1", 1).unwrap());
+        assert_eq!(args.len(), 2);
+        assert_eq!(
+            args[1],
+            [Token::Number(2), Token::Punct("+"), Token::Number(1)]
+        );
+
+        let mac = Macro {
+            name: "add".into(),
+            params: vec!["a".into(), "b".into()],
+            body: vec![line("@skip: LDA #a+b"), line("BNE @skip")],
+            file: None,
+        };
+        let location = Location { line: 9, column: 1 };
+        let body = mac.expand(args, 3, location).unwrap();
+        let mut expected = vec![Token::Ident("@skip#3".into())];
+        expected.extend(tokens(&line(": LDA #($10)+2 + 1")));
+        assert_eq!(tokens(&body[0]), expected);
+        assert_eq!(body[1].tokens[1].token, Token::Ident("@skip#3".into()));
+        // substituted tokens point into the definition
+        assert_eq!(
+            body[0].tokens[4].location,
+            Location {
+                line: 1,
+                column: 13
+            }
+        );
+
+        let body = mac
+            .expand(vec![vec![Token::Number(1)]], 4, location)
+            .unwrap();
+        assert_eq!(tokens(&body[0])[4..], [Token::Number(1), Token::Punct("+")]);
+        let err = mac
+            .expand(vec![vec![], vec![], vec![]], 5, location)
+            .unwrap_err();
+        assert!(err.message.contains("takes 2 arguments"));
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::error::{AsmError, Expansion, Location};
+use crate::expr::Expr;
+
+/// Where a line of source came from, for errors only the linker finds.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Site {
+    pub file: Option<PathBuf>,
+    pub location: Location,
+    pub expansions: Vec<Expansion>,
+}
+
+impl Site {
+    pub fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError::new(self.location, message)
+            .in_file(self.file.as_deref())
+            .expanded_from(&self.expansions)
+    }
+}
+
+/// An expression only the linker can evaluate. Symbols in it are keyed the
+/// way [`Object`] keys them, and those that were known when assembling have
+/// been replaced by their values.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Deferred {
+    pub expr: Expr,
+    pub section: usize,
+    /// Offset of the line in `section`, for `*`
+    pub pc: i64,
+    pub site: Site,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FixupKind {
+    Byte,
+    Word,
+    /// Offset of the target from the end of a two byte branch
+    Branch,
+}
+
+/// Bytes the linker fills in once it knows where everything is.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Fixup {
+    /// Offset in the section
+    pub offset: i64,
+    pub kind: FixupKind,
+    pub value: Deferred,
+}
+
+/// A module's part of a segment.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Section {
+    pub name: String,
+    /// Labels in it are zero page addresses
+    pub zeropage: bool,
+    pub bytes: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+impl Section {
+    pub fn new(name: &str, zeropage: bool) -> Self {
+        Section {
+            name: name.to_string(),
+            zeropage,
+            bytes: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Definition {
+    Constant(i64),
+    Label {
+        section: usize,
+        offset: i64,
+    },
+    /// A constant defined in terms of relocatable symbols
+    Expr(Deferred),
+    Import,
+}
+
+/// Relocatable output of [`Assembler::assemble_object`], for the
+/// [`Linker`] to place.
+///
+/// [`Assembler::assemble_object`]: crate::Assembler::assemble_object
+/// [`Linker`]: crate::Linker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub(crate) sections: Vec<Section>,
+    /// Every symbol, keyed like [`crate::Program::symbols`]
+    pub(crate) symbols: BTreeMap<String, Definition>,
+    pub(crate) exports: Vec<(String, Site)>,
+    pub(crate) imports: BTreeMap<String, Site>,
+}
+
+impl Object {
+    /// Names and sizes of the segments the module uses.
+    pub fn segments(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
+        self.sections
+            .iter()
+            .map(|section| (section.name.as_str(), section.bytes.len()))
+    }
+
+    pub fn exports(&self) -> impl Iterator<Item = &str> + '_ {
+        self.exports.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn imports(&self) -> impl Iterator<Item = &str> + '_ {
+        self.imports.keys().map(String::as_str)
+    }
+}
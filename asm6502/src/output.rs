@@ -0,0 +1,237 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::assembler::Program;
+
+/// Bytes per listing row. Lines with more continue on further rows.
+const LISTING_ROW: usize = 4;
+
+/// Bytes listed per line before the rest is elided, so `.res` and `.org`
+/// padding don't flood the listing.
+const LISTING_MAX: usize = 16;
+
+/// Internal RAM, which debuggers keep labels for apart from the ROM's.
+const RAM: RangeInclusive<u16> = 0x0000..=0x07FF;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `name` with characters debuggers don't accept in labels replaced by `_`.
+fn export_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '@' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Program {
+    /// Labels that are CPU addresses, by address.
+    fn label_addresses(&self) -> Vec<(u16, &str)> {
+        let mut labels: Vec<(u16, &str)> = self
+            .labels
+            .iter()
+            .filter_map(|name| {
+                let address = u16::try_from(self.symbols[name]).ok()?;
+                Some((address, name.as_str()))
+            })
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    /// Writes a listing with each line's address, the bytes it assembled to
+    /// and its source. Instructions are also shown the way
+    /// [`crate::prettyprint`] disassembles them, so the two agree.
+    ///
+    /// ```text
+    /// $8000  a2 00        LDX #$00          reset:  LDX #0
+    /// ```
+    pub fn write_listing<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for line in &self.listing {
+            let shown = &line.bytes[..line.bytes.len().min(LISTING_MAX)];
+            let mut rows = shown.chunks(LISTING_ROW);
+            writeln!(
+                writer,
+                "${:04x}  {:<11}  {:<16}  {}",
+                line.address,
+                hex(rows.next().unwrap_or_default()),
+                line.instruction.as_deref().unwrap_or_default(),
+                line.source.trim_end()
+            )?;
+            for (index, row) in rows.enumerate() {
+                let address = line.address as usize + (index + 1) * LISTING_ROW;
+                writeln!(writer, "${:04x}  {}", address as u16, hex(row))?;
+            }
+            if line.bytes.len() > LISTING_MAX {
+                writeln!(
+                    writer,
+                    "       ... {} more bytes",
+                    line.bytes.len() - LISTING_MAX
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the labels in `addresses` as an FCEUX `.nl` file. FCEUX reads
+    /// RAM labels from `game.nes.ram.nl` and each 16 KiB PRG bank's from
+    /// `game.nes.<bank>.nl`, so call this once per file with its range.
+    pub fn write_nl<W: Write>(
+        &self,
+        writer: &mut W,
+        addresses: RangeInclusive<u16>,
+    ) -> io::Result<()> {
+        for (address, name) in self.label_addresses() {
+            if addresses.contains(&address) {
+                writeln!(writer, "${:04X}#{}#", address, export_name(name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the labels as a Mesen `.mlb` file, taking PRG-ROM to start at
+    /// address `prg`, usually `$8000`. Labels in RAM and in the assembled
+    /// PRG-ROM are written, others are left out since Mesen can't place
+    /// them.
+    pub fn write_mlb<W: Write>(&self, writer: &mut W, prg: u16) -> io::Result<()> {
+        let end = usize::from(self.origin) + self.bytes.len();
+        for (address, name) in self.label_addresses() {
+            if RAM.contains(&address) {
+                writeln!(writer, "R:{:04X}:{}", address, export_name(name))?;
+            } else if address >= prg && usize::from(address) < end {
+                writeln!(writer, "P:{:04X}:{}", address - prg, export_name(name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every symbol as JSON:
+    ///
+    /// ```json
+    /// {
+    ///   "origin": 32768,
+    ///   "symbols": [
+    ///     {"name": "reset", "value": 32768, "label": true}
+    ///   ]
+    /// }
+    /// ```
+    pub fn write_symbols_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"origin\": {},", self.origin)?;
+        writeln!(writer, "  \"symbols\": [")?;
+        for (index, (name, value)) in self.symbols.iter().enumerate() {
+            let separator = if index + 1 < self.symbols.len() {
+                ","
+            } else {
+                ""
+            };
+            writeln!(
+                writer,
+                "    {{\"name\": {}, \"value\": {}, \"label\": {}}}{}",
+                json_string(name),
+                value,
+                self.labels.contains(name),
+                separator
+            )?;
+        }
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dump, Assembler};
+
+    fn program() -> crate::Program {
+        Assembler::new()
+            .assemble(
+                "
+                buffer = $0300
+                .org $0010
+                counter: .res 1
+                .org $8000
+                reset:  LDX #0
+                @loop:  STA buffer,x
+                        INX
+                        BNE @loop
+                table:  .byte 1, 2, 3, 4, 5, 6
+                        .res 20
+                ",
+            )
+            .unwrap()
+    }
+
+    fn text(write: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> String {
+        let mut out = Vec::new();
+        write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_listing() {
+        let program = program();
+        let listing = text(|out| program.write_listing(out));
+        let lines: Vec<&str> = listing.lines().collect();
+
+        let stx = lines.iter().find(|line| line.starts_with("$8002")).unwrap();
+        let disassembled = dump(program.bytes[0x8002 - 0x10..0x8005 - 0x10].to_vec());
+        assert!(stx.contains(&disassembled[0].to_string()));
+        assert!(stx.starts_with("$8002  9d 00 03"));
+        assert!(stx.ends_with("@loop:  STA buffer,x"));
+
+        let table = lines
+            .iter()
+            .position(|line| line.contains("table:"))
+            .unwrap();
+        assert!(lines[table].starts_with("$8008  01 02 03 04"));
+        assert_eq!(lines[table + 1], "$800c  05 06");
+        assert!(lines[table + 2].starts_with("$800e  00 00 00 00"));
+        assert!(lines.contains(&"       ... 4 more bytes"));
+    }
+
+    #[test]
+    fn test_symbol_files() {
+        let program = program();
+        assert_eq!(
+            text(|out| program.write_nl(out, 0x8000..=0xBFFF)),
+            "$8000#reset#\n$8002#reset@loop#\n$8008#table#\n"
+        );
+        assert_eq!(
+            text(|out| program.write_nl(out, 0x0000..=0x07FF)),
+            "$0010#counter#\n"
+        );
+        // counter is RAM, not part of PRG even though the program starts there
+        assert_eq!(
+            text(|out| program.write_mlb(out, 0x8000)),
+            "R:0010:counter\nP:0000:reset\nP:0002:reset@loop\nP:0008:table\n"
+        );
+
+        let json = text(|out| program.write_symbols_json(out));
+        assert!(json.contains("{\"name\": \"buffer\", \"value\": 768, \"label\": false},"));
+        assert!(json.contains("{\"name\": \"table\", \"value\": 32776, \"label\": true}\n"));
+        assert!(json.starts_with("{\n  \"origin\": 16,\n"));
+    }
+}
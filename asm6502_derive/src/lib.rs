@@ -1,49 +1,92 @@
 extern crate proc_macro;
 
-use darling::FromDeriveInput;
+use darling::{FromDeriveInput, FromMeta};
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, DeriveInput};
 
+/// Opcode bytes for one addressing mode, written `zero = 0x04` or, for
+/// unofficial instructions with several encodings, `zero(0x04, 0x44)`. The
+/// first is the one encoded; all of them decode.
+#[derive(Debug, Clone)]
+struct Opcodes(Vec<u8>);
+
+impl FromMeta for Opcodes {
+    fn from_value(value: &syn::Lit) -> darling::Result<Self> {
+        u8::from_value(value).map(|opcode| Opcodes(vec![opcode]))
+    }
+
+    fn from_list(items: &[syn::NestedMeta]) -> darling::Result<Self> {
+        let opcodes = items
+            .iter()
+            .map(|item| match item {
+                syn::NestedMeta::Lit(lit) => u8::from_value(lit),
+                syn::NestedMeta::Meta(_) => Err(darling::Error::unexpected_type("meta")),
+            })
+            .collect::<darling::Result<Vec<u8>>>()?;
+        if opcodes.is_empty() {
+            return Err(darling::Error::too_few_items(1));
+        }
+        Ok(Opcodes(opcodes))
+    }
+}
+
 #[derive(FromDeriveInput, Debug)]
 #[darling(attributes(asm6502))]
 struct Asm6502 {
+    /// Undocumented instruction, shown with a `*` prefix
+    #[darling(default)]
+    pub unofficial: bool,
+    /// Mnemonic to show instead of the type's name, for unofficial
+    /// encodings of an instruction that already has a type
     #[darling(default)]
-    pub implicit: Option<u8>,
+    pub mnemonic: Option<String>,
     #[darling(default)]
-    pub accumulator: Option<u8>,
+    pub implicit: Option<Opcodes>,
     #[darling(default)]
-    pub immediate: Option<u8>,
+    pub accumulator: Option<Opcodes>,
     #[darling(default)]
-    pub zero: Option<u8>,
+    pub immediate: Option<Opcodes>,
     #[darling(default)]
-    pub zero_x: Option<u8>,
+    pub zero: Option<Opcodes>,
     #[darling(default)]
-    pub zero_y: Option<u8>,
+    pub zero_x: Option<Opcodes>,
     #[darling(default)]
-    pub relative: Option<u8>,
+    pub zero_y: Option<Opcodes>,
     #[darling(default)]
-    pub absolute: Option<u8>,
+    pub relative: Option<Opcodes>,
     #[darling(default)]
-    pub absolute_x: Option<u8>,
+    pub absolute: Option<Opcodes>,
     #[darling(default)]
-    pub absolute_y: Option<u8>,
+    pub absolute_x: Option<Opcodes>,
     #[darling(default)]
-    pub indirect: Option<u8>,
+    pub absolute_y: Option<Opcodes>,
     #[darling(default)]
-    pub indirect_x: Option<u8>,
+    pub indirect: Option<Opcodes>,
     #[darling(default)]
-    pub indirect_y: Option<u8>,
+    pub indirect_x: Option<Opcodes>,
+    #[darling(default)]
+    pub indirect_y: Option<Opcodes>,
     pub ident: syn::Ident,
+    pub data: darling::ast::Data<(), syn::Field>,
 }
 
 macro_rules! optype {
-    ($result: ident, $field: expr, $($ctor: tt)+) => {
-        if let Some(val) = $field {
-            $result.push(quote! {
-                #val => {
-                    bytes.next();
-                    Some(Self($($ctor)*))
+    ($self: ident, $result: ident, $field: expr, $($ctor: tt)+) => {
+        if let Some(Opcodes(vals)) = &$field {
+            $result.push(if $self.keeps_opcode() {
+                quote! {
+                    #(#vals)|* => {
+                        let opcode = *bytes.next().unwrap();
+                        Some(Self($($ctor)*, opcode))
+                    }
+                }
+            } else {
+                quote! {
+                    #(#vals)|* => {
+                        bytes.next();
+                        Some(Self($($ctor)*))
+                    }
                 }
             });
         }
@@ -51,37 +94,70 @@ macro_rules! optype {
 }
 
 impl Asm6502 {
+    /// Whether the type is `Name(AddressMode, u8)`, remembering which of
+    /// several opcodes it was decoded from so it encodes back to it.
+    fn keeps_opcode(&self) -> bool {
+        self.data
+            .as_ref()
+            .take_struct()
+            .is_some_and(|fields| fields.len() == 2)
+    }
+
+    fn modes(&self) -> [&Option<Opcodes>; 13] {
+        [
+            &self.implicit,
+            &self.accumulator,
+            &self.immediate,
+            &self.zero,
+            &self.zero_x,
+            &self.zero_y,
+            &self.relative,
+            &self.absolute,
+            &self.absolute_x,
+            &self.absolute_y,
+            &self.indirect,
+            &self.indirect_x,
+            &self.indirect_y,
+        ]
+    }
+
     fn build_from_peekable(&self) -> proc_macro2::TokenStream {
         let mut branches = Vec::new();
 
-        optype!(branches, self.implicit, AddressMode::Implicit);
-        optype!(branches, self.accumulator, AddressMode::Accumulator);
+        optype!(self, branches, self.implicit, AddressMode::Implicit);
+        optype!(self, branches, self.accumulator, AddressMode::Accumulator);
         optype!(
+            self,
             branches,
             self.immediate,
             AddressMode::Immediate(*bytes.next().unwrap())
         );
         optype!(
+            self,
             branches,
             self.zero,
             AddressMode::Zero(*bytes.next().unwrap())
         );
         optype!(
+            self,
             branches,
             self.zero_x,
             AddressMode::ZeroX(*bytes.next().unwrap())
         );
         optype!(
+            self,
             branches,
             self.zero_y,
             AddressMode::ZeroY(*bytes.next().unwrap())
         );
         optype!(
+            self,
             branches,
             self.relative,
             AddressMode::Relative(i8::from_le_bytes([*bytes.next().unwrap()]))
         );
         optype!(
+            self,
             branches,
             self.absolute,
             AddressMode::Absolute(u16::from_le_bytes([
@@ -90,6 +166,7 @@ impl Asm6502 {
             ]))
         );
         optype!(
+            self,
             branches,
             self.absolute_x,
             AddressMode::AbsoluteX(u16::from_le_bytes([
@@ -98,6 +175,7 @@ impl Asm6502 {
             ]))
         );
         optype!(
+            self,
             branches,
             self.absolute_y,
             AddressMode::AbsoluteY(u16::from_le_bytes([
@@ -106,6 +184,7 @@ impl Asm6502 {
             ]))
         );
         optype!(
+            self,
             branches,
             self.indirect,
             AddressMode::Indirect(u16::from_le_bytes([
@@ -114,11 +193,13 @@ impl Asm6502 {
             ]))
         );
         optype!(
+            self,
             branches,
             self.indirect_x,
             AddressMode::IndirectX(*bytes.next().unwrap())
         );
         optype!(
+            self,
             branches,
             self.indirect_y,
             AddressMode::IndirectY(*bytes.next().unwrap())
@@ -136,18 +217,106 @@ impl Asm6502 {
             }
         }
     }
+
+    /// Maps each addressing mode to the opcode byte encoding it, the inverse
+    /// of `from_peekable`.
+    fn build_opcode_for(&self) -> proc_macro2::TokenStream {
+        let opcode = |field: &Option<Opcodes>| match field {
+            Some(Opcodes(vals)) => {
+                let val = vals[0];
+                quote!(Some(#val))
+            }
+            None => quote!(None),
+        };
+        let implicit = opcode(&self.implicit);
+        let accumulator = opcode(&self.accumulator);
+        let immediate = opcode(&self.immediate);
+        let zero = opcode(&self.zero);
+        let zero_x = opcode(&self.zero_x);
+        let zero_y = opcode(&self.zero_y);
+        let relative = opcode(&self.relative);
+        let absolute = opcode(&self.absolute);
+        let absolute_x = opcode(&self.absolute_x);
+        let absolute_y = opcode(&self.absolute_y);
+        let indirect = opcode(&self.indirect);
+        let indirect_x = opcode(&self.indirect_x);
+        let indirect_y = opcode(&self.indirect_y);
+
+        quote! {
+            fn opcode_for(mode: &AddressMode) -> Option<u8> {
+                match mode {
+                    AddressMode::Implicit => #implicit,
+                    AddressMode::Accumulator => #accumulator,
+                    AddressMode::Immediate(_) => #immediate,
+                    AddressMode::Zero(_) => #zero,
+                    AddressMode::ZeroX(_) => #zero_x,
+                    AddressMode::ZeroY(_) => #zero_y,
+                    AddressMode::Relative(_) => #relative,
+                    AddressMode::Absolute(_) => #absolute,
+                    AddressMode::AbsoluteX(_) => #absolute_x,
+                    AddressMode::AbsoluteY(_) => #absolute_y,
+                    AddressMode::Indirect(_) => #indirect,
+                    AddressMode::IndirectX(_) => #indirect_x,
+                    AddressMode::IndirectY(_) => #indirect_y,
+                }
+            }
+        }
+    }
 }
 
 fn expand_derive(input: DeriveInput) -> Result<proc_macro2::TokenStream, Vec<syn::Error>> {
     let parsed = Asm6502::from_derive_input(&input).unwrap();
     let name = parsed.ident.clone();
 
+    let aliased = parsed
+        .modes()
+        .iter()
+        .any(|mode| matches!(mode, Some(Opcodes(vals)) if vals.len() > 1));
+    if aliased && !parsed.keeps_opcode() {
+        return Err(vec![syn::Error::new_spanned(
+            &input.ident,
+            "an instruction with several opcodes for a mode must be `Name(AddressMode, u8)`",
+        )]);
+    }
+
     let from_peekable = parsed.build_from_peekable();
+    let opcode_for = parsed.build_opcode_for();
+
+    let mnemonic = format!(
+        "{}{}",
+        if parsed.unofficial { "*" } else { "" },
+        parsed.mnemonic.clone().unwrap_or_else(|| name.to_string())
+    );
+    let unofficial = parsed.unofficial;
+
+    let (construct, opcode) = if parsed.keeps_opcode() {
+        (
+            quote!(Some(opcode) => Ok(Self(mode, opcode))),
+            quote!(Some(self.1)),
+        )
+    } else {
+        (
+            quote!(Some(_) => Ok(Self(mode))),
+            quote!(Self::opcode_for(&self.0)),
+        )
+    };
 
     Ok(quote! {
         impl InstructionConstruct for #name {
             #from_peekable
 
+            #opcode_for
+
+            fn new(mode: AddressMode) -> Result<Self, EncodeError> {
+                match Self::opcode_for(&mode) {
+                    #construct,
+                    None => Err(EncodeError::UnsupportedMode {
+                        mnemonic: stringify!(#name),
+                        mode,
+                    }),
+                }
+            }
+
             fn name(&self) -> &'static str {
                 stringify!(#name)
             }
@@ -157,13 +326,30 @@ fn expand_derive(input: DeriveInput) -> Result<proc_macro2::TokenStream, Vec<syn
             fn size(&self) -> u8 {
                 self.0.size()
             }
+
+            fn encode(&self) -> Vec<u8> {
+                let opcode = #opcode.unwrap_or_else(|| {
+                    panic!(
+                        "{} doesn't support {} addressing",
+                        stringify!(#name),
+                        self.0.name()
+                    )
+                });
+                let mut bytes = vec![opcode];
+                bytes.extend(self.0.operand_bytes());
+                bytes
+            }
+
+            fn unofficial(&self) -> bool {
+                #unofficial
+            }
         }
 
         impl std::fmt::Display for #name {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 use AddressMode::*;
 
-                write!(f, stringify!(#name))?;
+                write!(f, #mnemonic)?;
 
                 match self.0 {
                     Implicit | Accumulator => write!(f, ""),
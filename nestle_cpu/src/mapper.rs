@@ -1,7 +1,7 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use crate::memory::{Memory, MemoryBuilder};
-use nestle_ines::Ines;
+use nestle_ines::{Ines, PrgBankSize};
 
 pub trait Mapper {
     fn map_image(image: Ines) -> Result<Memory>;
@@ -17,15 +17,35 @@ impl Mapper for NROM {
 
         println!("prg rom: 0x{:x}", image.prg.len());
 
-        if image.prg.len() == (0xBFFF - 0x8000 + 1) {
-            builder.add_data(0x8000..=0xBFFF, image.prg.clone())?;
-            builder.add_data(0xC000..=0xFFFF, image.prg)?;
-            builder.add_mirror(0xC000..=0xFFFF, 0x8000..=0xBFFF)?;
-        } else {
-            builder.add_data(0x8000..=0xFFFF, image.prg)?;
-        }
+        // NROM-128 has a single bank, which shows up in both halves
+        let banks = image.prg_banks(PrgBankSize::Size16K);
+        let (first, last) = banks
+            .first()
+            .zip(banks.last())
+            .ok_or_else(|| anyhow!("NROM image has no PRG-ROM"))?;
+        builder.add_data(0x8000..=0xBFFF, first.to_vec())?;
+        builder.add_data(0xC000..=0xFFFF, last.to_vec())?;
 
         builder.add_readonly(0x8000..=0xFFFF);
         Ok(builder.build())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nrom_mirroring() {
+        let image = Ines::builder()
+            .place(0, 0x8000, &[0xEA])
+            .unwrap()
+            .reset_vector(0x8000)
+            .build()
+            .unwrap();
+        let memory = NROM::map_image(image).unwrap();
+        assert_eq!(memory.read_u8(0x8000), 0xEA);
+        assert_eq!(memory.read_u8(0xC000), 0xEA);
+        assert_eq!(memory.read_u16(0xFFFC), 0x8000);
+    }
+}
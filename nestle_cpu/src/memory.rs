@@ -17,6 +17,7 @@ pub struct Segment {
     range: RangeInclusive<usize>,
 }
 
+#[allow(dead_code)]
 impl Segment {
     fn mirror(src: RangeInclusive<usize>, dest: RangeInclusive<usize>) -> Self {
         Segment {
@@ -58,24 +59,18 @@ impl Segment {
     }
 
     fn is_mirror(&self) -> bool {
-        match self.ttype {
-            SegmentType::Mirror { .. } => true,
-            _ => false,
-        }
+        matches!(self.ttype, SegmentType::Mirror { .. })
     }
 
     fn is_readonly(&self) -> bool {
-        match self.ttype {
-            SegmentType::Readonly => true,
-            _ => false,
-        }
+        matches!(self.ttype, SegmentType::Readonly)
     }
 }
 
 impl Ord for Segment {
     fn cmp(&self, other: &Self) -> Ordering {
-        match self.range.start().cmp(&other.range.start()) {
-            Ordering::Equal => self.range.end().cmp(&other.range.end()),
+        match self.range.start().cmp(other.range.start()) {
+            Ordering::Equal => self.range.end().cmp(other.range.end()),
             x => x,
         }
     }
@@ -104,6 +99,7 @@ impl MemoryBuilder {
         self.segments.push(Segment::readonly(range));
     }
 
+    #[allow(dead_code)]
     pub fn add_mirror(
         &mut self,
         src: RangeInclusive<usize>,
@@ -118,7 +114,7 @@ impl MemoryBuilder {
     }
 
     pub fn add_data(&mut self, range: RangeInclusive<usize>, bytes: Vec<u8>) -> Result<()> {
-        if bytes.len() != ((range.end() - range.start() + 1) as usize) {
+        if bytes.len() != (range.end() - range.start() + 1) {
             Err(anyhow!(
                 "invalid segment, byte length doesn't equal to segment length"
             ))
@@ -133,7 +129,7 @@ impl MemoryBuilder {
         let mut memory: [u8; ADDRESS_SPACE + 1] = [0; ADDRESS_SPACE + 1];
 
         for datum in data {
-            (&mut memory[datum.range.clone()]).copy_from_slice(&datum.into_bytes());
+            memory[datum.range.clone()].copy_from_slice(&datum.into_bytes());
         }
 
         segments.sort();
@@ -145,12 +141,14 @@ impl MemoryBuilder {
     }
 }
 
+#[allow(dead_code)]
 pub struct Memory {
     // storage
     data: [u8; 0x10000],
     segments: Vec<Segment>,
 }
 
+#[allow(dead_code)]
 impl Memory {
     fn sync_memory(&mut self, src: usize, dest: usize, offset: usize, length: usize) {
         for i in 0..length {
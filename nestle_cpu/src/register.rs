@@ -12,6 +12,7 @@ bitflags! {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Registers {
     pc: u16,   // 0x34
@@ -29,6 +30,10 @@ impl Registers {
             ..Default::default()
         }
     }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
 }
 
 impl Default for Registers {
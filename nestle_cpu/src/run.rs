@@ -3,20 +3,22 @@ use std::ops::RangeInclusive;
 use std::path::Path;
 
 use asm6502::Opcode;
-use nestle_ines::Ines;
 
 use crate::mapper::{Mapper, NROM};
 use crate::memory::Memory;
 use crate::register::Registers;
 
+// Not driven by anything yet
+#[allow(dead_code)]
 pub struct CPU {
     registers: Registers,
     memory: Memory,
 }
 
+#[allow(dead_code)]
 impl CPU {
     pub fn from_path(path: &Path) -> Result<Self> {
-        let ines = Ines::from_path(path)?;
+        let ines = nestle_ines::load(path)?;
         let memory = NROM::map_image(ines)?;
         let initial_pc = memory.read_u16(0xFFFC);
         let registers = Registers::with_pc(initial_pc);
@@ -27,8 +29,9 @@ impl CPU {
         self.memory.pprint_memory(range);
     }
 
-    fn instructions(&self) -> impl Iterator<Item = Opcode> {
-        todo!()
+    fn instructions(&self) -> impl Iterator<Item = Opcode> + '_ {
+        let pc = self.registers.pc() as usize;
+        asm6502::opcodes(self.memory.iter_range(pc..=0xFFFF).peekable())
     }
 }
 
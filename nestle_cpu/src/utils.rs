@@ -1,5 +1,6 @@
 use std::ops::RangeInclusive;
 
+#[allow(dead_code)]
 pub fn pprint_binaries(binaries: &[u8], range: RangeInclusive<usize>) {
     let start = *range.start();
     let chunk_size = 16;
@@ -0,0 +1,200 @@
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::error::{InesError, Result};
+
+/// Extensions of archive members picked when no member is named.
+const ROM_EXTENSIONS: [&str; 4] = ["nes", "unf", "unif", "fds"];
+
+/// Compressed containers ROMs are commonly stored in, told apart by their
+/// magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Gzip,
+}
+
+impl ArchiveFormat {
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if bytes.starts_with(b"\x1f\x8b") {
+            Some(ArchiveFormat::Gzip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file unpacked from an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// Path within a zip archive, or the original file name stored in a gzip
+    /// header, which may be empty
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom| ext.eq_ignore_ascii_case(rom))
+        })
+}
+
+fn archive_error(err: ZipError) -> InesError {
+    match err {
+        ZipError::Io(err) => InesError::IOError(err),
+        err => InesError::Archive(err.to_string()),
+    }
+}
+
+fn unzip(bytes: &[u8], member: Option<&str>) -> Result<Member> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(archive_error)?;
+
+    let name = match member {
+        Some(name) => name.to_string(),
+        None => {
+            let mut candidates: Vec<String> = archive
+                .file_names()
+                .filter(|name| is_rom_name(name))
+                .map(str::to_string)
+                .collect();
+            match candidates.len() {
+                0 => return Err(InesError::NoArchiveMember),
+                1 => candidates.remove(0),
+                _ => {
+                    candidates.sort();
+                    return Err(InesError::AmbiguousArchiveMember(candidates));
+                }
+            }
+        }
+    };
+
+    let mut data = Vec::new();
+    let read = match archive.by_name(&name) {
+        Ok(mut file) => file.read_to_end(&mut data).map_err(InesError::from),
+        Err(ZipError::FileNotFound) => return Err(InesError::MissingArchiveMember(name)),
+        Err(err) => Err(archive_error(err)),
+    };
+    read.map_err(|e| e.with_member(&name))?;
+    Ok(Member { name, data })
+}
+
+fn gunzip(bytes: &[u8]) -> Result<Member> {
+    let mut decoder = GzDecoder::new(bytes);
+    let mut data = Vec::new();
+    decoder.read_to_end(&mut data)?;
+
+    let name = decoder
+        .header()
+        .and_then(|header| header.filename())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .unwrap_or_default();
+    Ok(Member { name, data })
+}
+
+/// Unpacks `bytes` if they're a zip or gzip archive, or returns `None` for
+/// anything else. Zip archives must hold exactly one ROM unless `member`
+/// names the one to use; gzip streams only hold one file so `member` is
+/// ignored.
+pub fn unpack(bytes: &[u8], member: Option<&str>) -> Result<Option<Member>> {
+    match ArchiveFormat::detect(bytes) {
+        Some(ArchiveFormat::Zip) => unzip(bytes, member).map(Some),
+        Some(ArchiveFormat::Gzip) => gunzip(bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads a file, unpacking it first if it's an archive. Errors name both the
+/// file and the archive member.
+pub(crate) fn load<T>(
+    path: &Path,
+    member: Option<&str>,
+    parse: impl FnOnce(&[u8]) -> Result<T>,
+) -> Result<T> {
+    fs::read(path)
+        .map_err(InesError::from)
+        .and_then(|bytes| match unpack(&bytes, member)? {
+            Some(member) => parse(&member.data).map_err(|e| e.with_member(&member.name)),
+            None => parse(&bytes),
+        })
+        .map_err(|e| e.with_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::{Compression, GzBuilder};
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_unpack_zip() {
+        let bytes = zip(&[("readme.txt", b"hello"), ("Game.NES", b"rom")]);
+        assert_eq!(ArchiveFormat::detect(&bytes), Some(ArchiveFormat::Zip));
+        let member = unpack(&bytes, None).unwrap().unwrap();
+        assert_eq!(member.name, "Game.NES");
+        assert_eq!(member.data, b"rom");
+
+        let member = unpack(&bytes, Some("readme.txt")).unwrap().unwrap();
+        assert_eq!(member.data, b"hello");
+
+        match unpack(&bytes, Some("other.nes")).unwrap_err() {
+            InesError::MissingArchiveMember(name) => assert_eq!(name, "other.nes"),
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        let bytes = zip(&[("b.nes", b""), ("a.fds", b"")]);
+        match unpack(&bytes, None).unwrap_err() {
+            InesError::AmbiguousArchiveMember(names) => assert_eq!(names, ["a.fds", "b.nes"]),
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        let bytes = zip(&[("readme.txt", b"")]);
+        assert!(matches!(
+            unpack(&bytes, None),
+            Err(InesError::NoArchiveMember)
+        ));
+    }
+
+    #[test]
+    fn test_unpack_gzip() {
+        let mut encoder = GzBuilder::new()
+            .filename("game.nes")
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(b"rom").unwrap();
+        let bytes = encoder.finish().unwrap();
+
+        assert_eq!(ArchiveFormat::detect(&bytes), Some(ArchiveFormat::Gzip));
+        let member = unpack(&bytes, None).unwrap().unwrap();
+        assert_eq!(member.name, "game.nes");
+        assert_eq!(member.data, b"rom");
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"rom").unwrap();
+        let member = unpack(&encoder.finish().unwrap(), None).unwrap().unwrap();
+        assert_eq!(member.name, "");
+
+        assert_eq!(unpack(b"NES\x1a", None).unwrap(), None);
+    }
+}
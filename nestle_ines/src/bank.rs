@@ -0,0 +1,176 @@
+use std::slice::ChunksExact;
+
+use crate::model::{Ines, InesRef};
+
+/// Sizes PRG-ROM is switched in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrgBankSize {
+    Size8K,
+    Size16K,
+    Size32K,
+}
+
+impl PrgBankSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            PrgBankSize::Size8K => 0x2000,
+            PrgBankSize::Size16K => 0x4000,
+            PrgBankSize::Size32K => 0x8000,
+        }
+    }
+}
+
+/// Sizes CHR-ROM is switched in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChrBankSize {
+    Size1K,
+    Size2K,
+    Size4K,
+    Size8K,
+}
+
+impl ChrBankSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            ChrBankSize::Size1K => 0x0400,
+            ChrBankSize::Size2K => 0x0800,
+            ChrBankSize::Size4K => 0x1000,
+            ChrBankSize::Size8K => 0x2000,
+        }
+    }
+}
+
+/// A ROM section split into equally sized banks. Bytes past the last whole
+/// bank aren't part of any bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Banks<'a> {
+    data: &'a [u8],
+    size: usize,
+}
+
+impl<'a> Banks<'a> {
+    pub fn new(data: &'a [u8], size: usize) -> Self {
+        assert!(size != 0, "bank size must be non-zero");
+        Banks { data, size }
+    }
+
+    pub fn bank_size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of whole banks.
+    pub fn len(&self) -> usize {
+        self.data.len() / self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bank `n`, wrapping around when `n` is out of range the way boards
+    /// ignore bank register bits they don't have address lines for.
+    pub fn bank(&self, n: usize) -> Option<&'a [u8]> {
+        match self.len() {
+            0 => None,
+            len => {
+                let start = n % len * self.size;
+                Some(&self.data[start..start + self.size])
+            }
+        }
+    }
+
+    pub fn first(&self) -> Option<&'a [u8]> {
+        self.bank(0)
+    }
+
+    /// The last bank, which most mappers fix at the top of the CPU address
+    /// space so the vectors are always mapped.
+    pub fn last(&self) -> Option<&'a [u8]> {
+        self.bank(self.len().wrapping_sub(1))
+    }
+
+    pub fn iter(&self) -> ChunksExact<'a, u8> {
+        self.data.chunks_exact(self.size)
+    }
+}
+
+impl<'a> IntoIterator for Banks<'a> {
+    type Item = &'a [u8];
+    type IntoIter = ChunksExact<'a, u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> InesRef<'a> {
+    pub fn prg_banks(&self, size: PrgBankSize) -> Banks<'a> {
+        Banks::new(self.prg, size.bytes())
+    }
+
+    /// CHR-ROM banks, empty for CHR-RAM boards.
+    pub fn chr_banks(&self, size: ChrBankSize) -> Banks<'a> {
+        Banks::new(self.chr.unwrap_or_default(), size.bytes())
+    }
+}
+
+impl Ines {
+    pub fn prg_banks(&self, size: PrgBankSize) -> Banks<'_> {
+        Banks::new(&self.prg, size.bytes())
+    }
+
+    /// CHR-ROM banks, empty for CHR-RAM boards.
+    pub fn chr_banks(&self, size: ChrBankSize) -> Banks<'_> {
+        Banks::new(self.chr.as_deref().unwrap_or_default(), size.bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(banks: usize, size: usize) -> Vec<u8> {
+        (0..banks).flat_map(|n| vec![n as u8; size]).collect()
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let ines = Ines {
+            prg: numbered(8, 0x2000),
+            ..Default::default()
+        };
+
+        let banks = ines.prg_banks(PrgBankSize::Size8K);
+        assert_eq!(banks.len(), 8);
+        assert_eq!(banks.bank(3).unwrap()[0], 3);
+        assert_eq!(banks.bank(11).unwrap()[0], 3);
+        assert_eq!(banks.last().unwrap()[0], 7);
+        assert_eq!(
+            banks.iter().map(|bank| bank[0]).collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+
+        let banks = ines.prg_banks(PrgBankSize::Size32K);
+        assert_eq!(banks.len(), 2);
+        assert_eq!(banks.bank(1).unwrap().len(), 0x8000);
+        assert_eq!(banks.bank(1).unwrap()[0], 4);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let ines = Ines {
+            chr: Some(numbered(8, 0x400)),
+            ..Default::default()
+        };
+        assert_eq!(ines.chr_banks(ChrBankSize::Size1K).len(), 8);
+        assert_eq!(ines.chr_banks(ChrBankSize::Size2K).bank(1).unwrap()[0], 2);
+        assert_eq!(ines.chr_banks(ChrBankSize::Size4K).last().unwrap()[0], 4);
+        assert_eq!(ines.view().chr_banks(ChrBankSize::Size8K).len(), 1);
+
+        let ines = Ines::default();
+        let banks = ines.chr_banks(ChrBankSize::Size8K);
+        assert!(banks.is_empty());
+        assert_eq!(banks.bank(0), None);
+        assert_eq!(banks.last(), None);
+    }
+}
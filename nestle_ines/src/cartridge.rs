@@ -0,0 +1,303 @@
+use crate::bank::PrgBankSize;
+use crate::error::{InesError, Result, Section};
+use crate::header::CHR_ROM_UNIT;
+use crate::model::{ConsoleType, Ines, InesHeaderBuilder, Mirroring, Timing, TRAINER_SIZE};
+use crate::write::BinWrite;
+
+/// Value unused ROM space is filled with, like an erased EPROM.
+const FILL: u8 = 0xFF;
+
+/// Builds a complete [`Ines`] image bank by bank, mostly for fabricating
+/// test ROMs:
+///
+/// ```
+/// use nestle_ines::Ines;
+///
+/// // LDA #$01; JMP $8002
+/// let ines = Ines::builder()
+///     .place(0, 0x8000, &[0xA9, 0x01, 0x4C, 0x02, 0x80])?
+///     .reset_vector(0x8000)
+///     .build()?;
+/// assert_eq!(ines.prg.len(), 16384);
+/// # Ok::<(), nestle_ines::InesError>(())
+/// ```
+///
+/// PRG banks are 16 KiB unless [`CartridgeBuilder::prg_bank_size`] says
+/// otherwise. A bank placed at CPU address `A` is assumed to be mapped at `A`
+/// rounded down to the bank size, and the vectors end up in the last bank.
+#[derive(Debug, Clone)]
+pub struct CartridgeBuilder {
+    header: InesHeaderBuilder,
+    prg_bank_size: PrgBankSize,
+    prg: Vec<Vec<u8>>,
+    chr: Vec<u8>,
+    trainer: Option<Vec<u8>>,
+    nmi: Option<u16>,
+    reset: Option<u16>,
+    irq: Option<u16>,
+}
+
+impl CartridgeBuilder {
+    pub fn new() -> Self {
+        CartridgeBuilder {
+            header: InesHeaderBuilder::new(),
+            prg_bank_size: PrgBankSize::Size16K,
+            prg: Vec::new(),
+            chr: Vec::new(),
+            trainer: None,
+            nmi: None,
+            reset: None,
+            irq: None,
+        }
+    }
+
+    pub fn mapper(&mut self, mapper: u16) -> &mut Self {
+        self.header.mapper(mapper);
+        self
+    }
+
+    pub fn submapper(&mut self, submapper: u8) -> &mut Self {
+        self.header.submapper(submapper);
+        self
+    }
+
+    pub fn mirroring(&mut self, mirroring: Mirroring) -> &mut Self {
+        self.header.mirroring(mirroring);
+        self
+    }
+
+    pub fn battery(&mut self, battery: bool) -> &mut Self {
+        self.header.battery(battery);
+        self
+    }
+
+    pub fn console_type(&mut self, console: ConsoleType) -> &mut Self {
+        self.header.console_type(console);
+        self
+    }
+
+    pub fn timing(&mut self, timing: Timing) -> &mut Self {
+        self.header.timing(timing);
+        self
+    }
+
+    /// Size of the banks [`CartridgeBuilder::push_prg_bank`] and
+    /// [`CartridgeBuilder::place`] work with. Only affects banks added
+    /// afterwards.
+    pub fn prg_bank_size(&mut self, size: PrgBankSize) -> &mut Self {
+        self.prg_bank_size = size;
+        self
+    }
+
+    fn blank_bank(&self) -> Vec<u8> {
+        vec![FILL; self.prg_bank_size.bytes()]
+    }
+
+    /// Appends a PRG bank starting with `data`, filling the rest with `$FF`.
+    pub fn push_prg_bank(&mut self, data: &[u8]) -> Result<&mut Self> {
+        let mut bank = self.blank_bank();
+        if data.len() > bank.len() {
+            return Err(InesError::UnencodableSize {
+                section: Section::PrgRom,
+                size: data.len(),
+            });
+        }
+
+        bank[..data.len()].copy_from_slice(data);
+        self.prg.push(bank);
+        Ok(self)
+    }
+
+    /// Writes `bytes` into PRG bank `bank` so they appear at CPU `address`
+    /// when the bank is mapped, adding blank banks up to `bank` if needed.
+    pub fn place(&mut self, bank: usize, address: u16, bytes: &[u8]) -> Result<&mut Self> {
+        let size = self.prg_bank_size.bytes();
+        let offset = usize::from(address) % size;
+        if address < 0x8000 || offset + bytes.len() > size {
+            return Err(InesError::Placement {
+                address,
+                len: bytes.len(),
+                bank_size: size,
+            });
+        }
+
+        while self.prg.len() <= bank {
+            self.prg.push(self.blank_bank());
+        }
+        let bank = &mut self.prg[bank];
+        if bank.len() != size {
+            return Err(InesError::Placement {
+                address,
+                len: bytes.len(),
+                bank_size: bank.len(),
+            });
+        }
+        bank[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(self)
+    }
+
+    pub fn nmi_vector(&mut self, address: u16) -> &mut Self {
+        self.nmi = Some(address);
+        self
+    }
+
+    pub fn reset_vector(&mut self, address: u16) -> &mut Self {
+        self.reset = Some(address);
+        self
+    }
+
+    pub fn irq_vector(&mut self, address: u16) -> &mut Self {
+        self.irq = Some(address);
+        self
+    }
+
+    /// Appends an 8 KiB CHR-ROM bank starting with `data`, filling the rest
+    /// with zeroes.
+    pub fn push_chr_bank(&mut self, data: &[u8]) -> Result<&mut Self> {
+        if data.len() > CHR_ROM_UNIT {
+            return Err(InesError::UnencodableSize {
+                section: Section::ChrRom,
+                size: data.len(),
+            });
+        }
+
+        let start = self.chr.len();
+        self.chr.resize(start + CHR_ROM_UNIT, 0);
+        self.chr[start..start + data.len()].copy_from_slice(data);
+        Ok(self)
+    }
+
+    /// 512 byte trainer, padded with zeroes.
+    pub fn trainer(&mut self, data: &[u8]) -> Result<&mut Self> {
+        if data.len() > TRAINER_SIZE {
+            return Err(InesError::UnencodableSize {
+                section: Section::Trainer,
+                size: data.len(),
+            });
+        }
+
+        let mut trainer = vec![0; TRAINER_SIZE];
+        trainer[..data.len()].copy_from_slice(data);
+        self.trainer = Some(trainer);
+        self.header.trainer(true);
+        Ok(self)
+    }
+
+    /// Assembles the image. A blank PRG bank is added if none were, since a
+    /// cartridge needs at least one.
+    pub fn build(&self) -> Result<Ines> {
+        let mut prg: Vec<u8> = self.prg.concat();
+        if prg.is_empty() {
+            prg = self.blank_bank();
+        }
+
+        let end = prg.len();
+        let vectors = [(6, self.nmi), (4, self.reset), (2, self.irq)];
+        for &(from_end, vector) in &vectors {
+            if let Some(address) = vector {
+                prg[end - from_end..end - from_end + 2].copy_from_slice(&address.to_le_bytes());
+            }
+        }
+
+        let mut header = self.header.clone();
+        header
+            .prg_rom_size(prg.len())?
+            .chr_rom_size(self.chr.len())?;
+
+        Ok(Ines {
+            header: header.build(),
+            trainer: self.trainer.clone(),
+            prg,
+            chr: if self.chr.is_empty() {
+                None
+            } else {
+                Some(self.chr.clone())
+            },
+            ..Default::default()
+        })
+    }
+
+    /// Assembles the image as file bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.build()?.to_bytes()?)
+    }
+}
+
+impl Default for CartridgeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ines {
+    pub fn builder() -> CartridgeBuilder {
+        CartridgeBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::HeaderFormat;
+
+    #[test]
+    fn test_cartridge_builder() {
+        let ines = Ines::builder()
+            .mapper(2)
+            .mirroring(Mirroring::Vertical)
+            .battery(true)
+            .push_prg_bank(&[0xEA])
+            .unwrap()
+            .place(1, 0xC000, &[0x4C, 0x00, 0xC0])
+            .unwrap()
+            .reset_vector(0xC000)
+            .nmi_vector(0xC100)
+            .irq_vector(0xC200)
+            .push_chr_bank(&[0x01])
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(ines.header.format(), HeaderFormat::Ines);
+        assert_eq!(ines.header.mapper_number(), 2);
+        assert_eq!(ines.header.mirroring(), Mirroring::Vertical);
+        assert!(ines.header.has_battery());
+        assert_eq!(ines.header.prg_rom_size(), Some(0x8000));
+        assert_eq!(ines.prg[0], 0xEA);
+        assert_eq!(ines.prg[1], 0xFF);
+        assert_eq!(&ines.prg[0x4000..0x4003], &[0x4C, 0x00, 0xC0]);
+        assert_eq!(&ines.prg[0x7FFA..], &[0x00, 0xC1, 0x00, 0xC0, 0x00, 0xC2]);
+        assert_eq!(ines.chr.as_ref().map(Vec::len), Some(8192));
+        assert!(ines.validate().is_empty());
+
+        let bytes = Ines::builder().to_bytes().unwrap();
+        assert_eq!(Ines::from_bytes(&bytes).unwrap().prg.len(), 16384);
+    }
+
+    #[test]
+    fn test_cartridge_builder_banks() {
+        let ines = Ines::builder()
+            .prg_bank_size(PrgBankSize::Size8K)
+            .place(2, 0xE000, &[0x60])
+            .unwrap()
+            .trainer(b"trainer")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(ines.prg.len(), 3 * 8192);
+        assert_eq!(ines.prg[2 * 8192], 0x60);
+        assert_eq!(ines.header.format(), HeaderFormat::Nes2);
+        assert_eq!(ines.trainer.as_ref().map(Vec::len), Some(512));
+
+        let mut builder = Ines::builder();
+        assert!(builder.place(0, 0x6000, &[0]).is_err());
+        assert!(builder.place(0, 0xBFFF, &[0, 0]).is_err());
+        assert!(builder.push_prg_bank(&[0; 0x4001]).is_err());
+        builder
+            .push_prg_bank(&[])
+            .unwrap()
+            .prg_bank_size(PrgBankSize::Size8K);
+        assert!(builder.place(0, 0x8000, &[0]).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use std::io::{Read, Write};
+
+use crate::error::{InesError, Result};
+use crate::model::{Ines, InesRef};
+
+/// Bytes in one 8x8 tile: two bit planes of 8 bytes each.
+pub const TILE_SIZE: usize = 16;
+
+/// Sheets are as wide as one pattern table.
+pub const SHEET_TILES_WIDE: usize = 16;
+const SHEET_WIDTH: usize = SHEET_TILES_WIDE * 8;
+
+/// RGBA colors for the four pixel values of a tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 4]; 4]);
+
+impl Palette {
+    pub const GRAYSCALE: Palette = Palette([
+        [0x00, 0x00, 0x00, 0xFF],
+        [0x55, 0x55, 0x55, 0xFF],
+        [0xAA, 0xAA, 0xAA, 0xFF],
+        [0xFF, 0xFF, 0xFF, 0xFF],
+    ]);
+
+    /// Pixel value whose color is closest to `rgba`, ignoring alpha.
+    fn nearest(&self, rgba: &[u8]) -> u8 {
+        let distance = |color: &[u8; 4]| {
+            (0..3)
+                .map(|i| (color[i] as i32 - rgba[i] as i32).pow(2))
+                .sum::<i32>()
+        };
+        (0..4).min_by_key(|&i| distance(&self.0[i])).unwrap() as u8
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::GRAYSCALE
+    }
+}
+
+/// Decodes a 2bpp planar tile into rows of pixel values 0-3.
+pub fn decode_tile(tile: &[u8; TILE_SIZE]) -> [[u8; 8]; 8] {
+    let mut pixels = [[0u8; 8]; 8];
+    for (y, row) in pixels.iter_mut().enumerate() {
+        let (low, high) = (tile[y], tile[y + 8]);
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let bit = 7 - x;
+            *pixel = (low >> bit & 1) | (high >> bit & 1) << 1;
+        }
+    }
+    pixels
+}
+
+/// Inverse of [`decode_tile`]. Only the low two bits of each pixel are used.
+pub fn encode_tile(pixels: &[[u8; 8]; 8]) -> [u8; TILE_SIZE] {
+    let mut tile = [0u8; TILE_SIZE];
+    for (y, row) in pixels.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            let bit = 7 - x;
+            tile[y] |= (pixel & 1) << bit;
+            tile[y + 8] |= (pixel >> 1 & 1) << bit;
+        }
+    }
+    tile
+}
+
+/// CHR data rendered as an RGBA image, 16 tiles wide so each 4 KiB pattern
+/// table is a 128x128 block and tables are stacked top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileSheet {
+    pub width: usize,
+    pub height: usize,
+    /// Row-major RGBA pixels
+    pub rgba: Vec<u8>,
+}
+
+fn image_error<E: std::fmt::Display>(err: E) -> InesError {
+    InesError::Image(err.to_string())
+}
+
+impl TileSheet {
+    /// Renders every whole tile in `chr`.
+    pub fn from_chr(chr: &[u8], palette: &Palette) -> Self {
+        let tiles = chr.len() / TILE_SIZE;
+        let rows = tiles.div_ceil(SHEET_TILES_WIDE);
+        let (width, height) = (SHEET_WIDTH, rows * 8);
+        let mut rgba = vec![0u8; width * height * 4];
+
+        for (n, tile) in chr.chunks_exact(TILE_SIZE).enumerate() {
+            let mut raw = [0u8; TILE_SIZE];
+            raw.copy_from_slice(tile);
+            let (left, top) = (n % SHEET_TILES_WIDE * 8, n / SHEET_TILES_WIDE * 8);
+
+            for (y, row) in decode_tile(&raw).iter().enumerate() {
+                for (x, &pixel) in row.iter().enumerate() {
+                    let at = ((top + y) * width + left + x) * 4;
+                    rgba[at..at + 4].copy_from_slice(&palette.0[pixel as usize]);
+                }
+            }
+        }
+
+        TileSheet {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    /// Converts the sheet back into CHR data, mapping each pixel to the
+    /// nearest palette color. The sheet must be 128 pixels wide and a whole
+    /// number of tiles high.
+    pub fn to_chr(&self, palette: &Palette) -> Result<Vec<u8>> {
+        if self.width != SHEET_WIDTH || !self.height.is_multiple_of(8) {
+            return Err(InesError::Image(format!(
+                "tile sheet must be {} pixels wide and a multiple of 8 high, found {}x{}",
+                SHEET_WIDTH, self.width, self.height
+            )));
+        }
+        if self.rgba.len() != self.width * self.height * 4 {
+            return Err(InesError::Image(format!(
+                "{}x{} sheet needs {} bytes of RGBA, found {}",
+                self.width,
+                self.height,
+                self.width * self.height * 4,
+                self.rgba.len()
+            )));
+        }
+
+        let tiles = self.height / 8 * SHEET_TILES_WIDE;
+        let mut chr = Vec::with_capacity(tiles * TILE_SIZE);
+        for n in 0..tiles {
+            let (left, top) = (n % SHEET_TILES_WIDE * 8, n / SHEET_TILES_WIDE * 8);
+            let mut pixels = [[0u8; 8]; 8];
+            for (y, row) in pixels.iter_mut().enumerate() {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let at = ((top + y) * self.width + left + x) * 4;
+                    *pixel = palette.nearest(&self.rgba[at..at + 4]);
+                }
+            }
+            chr.extend_from_slice(&encode_tile(&pixels));
+        }
+        Ok(chr)
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<()> {
+        let mut encoder = Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.rgba))
+            .map_err(image_error)
+    }
+
+    /// Reads a PNG of any color type, expanding it to RGBA.
+    pub fn read_png<R: Read>(reader: R) -> Result<Self> {
+        let mut decoder = Decoder::new(reader);
+        decoder.set_transformations(Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(image_error)?;
+        let mut pixels = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).map_err(image_error)?;
+        pixels.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            ColorType::Rgba => pixels,
+            ColorType::Rgb => pixels
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+            ColorType::Grayscale => pixels.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+            ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Indexed => unreachable!("palettes are expanded by the decoder"),
+        };
+
+        Ok(TileSheet {
+            width: info.width as usize,
+            height: info.height as usize,
+            rgba,
+        })
+    }
+
+    /// Writes a binary (P6) PPM. PPM has no alpha channel, so it's dropped.
+    pub fn write_ppm<W: Write>(&self, mut writer: W) -> Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let rgb: Vec<u8> = self
+            .rgba
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect();
+        writer.write_all(&rgb)?;
+        Ok(())
+    }
+
+    /// Reads a binary (P6) PPM with 8-bit samples.
+    pub fn read_ppm<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        // magic, width, height and maxval, separated by whitespace and
+        // comments, then a single whitespace byte before the pixels
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 {
+            match bytes.get(pos) {
+                Some(b'#') => {
+                    while bytes.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                Some(_) => {
+                    let start = pos;
+                    while bytes.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        pos += 1;
+                    }
+                    fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+                }
+                None => return Err(InesError::Image("truncated PPM header".into())),
+            }
+        }
+        pos += 1;
+
+        if fields[0] != "P6" {
+            return Err(InesError::Image(format!(
+                "unsupported PPM type '{}', expected P6",
+                fields[0]
+            )));
+        }
+        let number = |field: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|_| InesError::Image(format!("invalid PPM header field '{}'", field)))
+        };
+        let (width, height, maxval) = (
+            number(&fields[1])?,
+            number(&fields[2])?,
+            number(&fields[3])?,
+        );
+        if maxval != 255 {
+            return Err(InesError::Image(format!(
+                "unsupported PPM maxval {}, expected 255",
+                maxval
+            )));
+        }
+
+        let end = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(3))
+            .and_then(|n| n.checked_add(pos))
+            .ok_or_else(|| {
+                InesError::Image(format!("PPM dimensions {}x{} are too large", width, height))
+            })?;
+        let rgb = bytes.get(pos..end).ok_or_else(|| {
+            InesError::Image(format!("PPM pixel data truncated for {}x{}", width, height))
+        })?;
+        Ok(TileSheet {
+            width,
+            height,
+            rgba: rgb
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+        })
+    }
+}
+
+impl InesRef<'_> {
+    /// Renders CHR-ROM as a tile sheet, empty for CHR-RAM boards.
+    pub fn chr_sheet(&self, palette: &Palette) -> TileSheet {
+        TileSheet::from_chr(self.chr.unwrap_or_default(), palette)
+    }
+}
+
+impl Ines {
+    /// Renders CHR-ROM as a tile sheet, empty for CHR-RAM boards.
+    pub fn chr_sheet(&self, palette: &Palette) -> TileSheet {
+        self.view().chr_sheet(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the "1/2" tile from the NESdev wiki's PPU pattern table article
+    const TILE: [u8; TILE_SIZE] = [
+        0x41, 0xC2, 0x44, 0x48, 0x10, 0x20, 0x40, 0x80, 0x01, 0x02, 0x04, 0x08, 0x16, 0x21, 0x42,
+        0x87,
+    ];
+
+    #[test]
+    fn test_tile_round_trip() {
+        let pixels = decode_tile(&TILE);
+        assert_eq!(pixels[0], [0, 1, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(pixels[7], [3, 0, 0, 0, 0, 2, 2, 2]);
+        assert_eq!(encode_tile(&pixels), TILE);
+    }
+
+    #[test]
+    fn test_sheet_round_trip() {
+        let chr: Vec<u8> = (0..8192).map(|n| (n * 7 % 251) as u8).collect();
+        let palette = Palette([
+            [0x0F, 0x0F, 0x0F, 0xFF],
+            [0xF8, 0x38, 0x00, 0xFF],
+            [0x00, 0x78, 0xF8, 0xFF],
+            [0xFC, 0xFC, 0xFC, 0xFF],
+        ]);
+        let sheet = TileSheet::from_chr(&chr, &palette);
+        assert_eq!((sheet.width, sheet.height), (128, 256));
+        assert_eq!(sheet.to_chr(&palette).unwrap(), chr);
+
+        let mut png = Vec::new();
+        sheet.write_png(&mut png).unwrap();
+        let decoded = TileSheet::read_png(&png[..]).unwrap();
+        assert_eq!(decoded, sheet);
+
+        let mut ppm = Vec::new();
+        sheet.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n128 256\n255\n"));
+        let decoded = TileSheet::read_ppm(&ppm[..]).unwrap();
+        assert_eq!(decoded.to_chr(&palette).unwrap(), chr);
+    }
+
+    #[test]
+    fn test_sheet_errors() {
+        let sheet = TileSheet {
+            width: 64,
+            height: 8,
+            rgba: vec![0; 64 * 8 * 4],
+        };
+        assert!(matches!(
+            sheet.to_chr(&Palette::GRAYSCALE),
+            Err(InesError::Image(_))
+        ));
+        assert!(TileSheet::read_ppm(&b"P3\n1 1\n255\n0 0 0"[..]).is_err());
+        assert!(TileSheet::read_ppm(&b"P6\n# comment\n2 2\n255\n\0\0\0"[..]).is_err());
+        assert!(matches!(
+            TileSheet::read_ppm(&b"P6 4294967296 4294967296 255\n\0\0\0"[..]),
+            Err(InesError::Image(message)) if message.contains("too large")
+        ));
+
+        let ines = Ines::default();
+        assert_eq!(ines.chr_sheet(&Palette::GRAYSCALE).height, 0);
+    }
+}
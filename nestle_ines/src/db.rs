@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{InesError, Result};
+use crate::model::{HeaderFormat, InesHeader, InesHeaderBuilder, InesRef, Mirroring};
+
+/// Database shipped with the crate, in the format [`RomDb::from_tsv`] reads.
+const BUNDLED: &str = include_str!("../data/romdb.tsv");
+
+const COLUMNS: usize = 12;
+
+/// CRC32 and SHA-1 of a block of data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Checksum {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+impl Checksum {
+    /// Checksum of several slices hashed back to back.
+    pub fn of(parts: &[&[u8]]) -> Self {
+        let mut crc32 = crc32fast::Hasher::new();
+        let mut sha1 = sha1_smol::Sha1::new();
+        for part in parts {
+            crc32.update(part);
+            sha1.update(part);
+        }
+
+        Checksum {
+            crc32: crc32.finalize(),
+            sha1: sha1.digest().bytes(),
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08X} ", self.crc32)?;
+        self.sha1.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// Checksums of an image's ROM sections. `rom` covers PRG-ROM followed by
+/// CHR-ROM, without header or trainer, which is what databases key on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomHashes {
+    pub prg: Checksum,
+    pub chr: Checksum,
+    pub rom: Checksum,
+}
+
+impl RomHashes {
+    pub fn of(rom: &InesRef<'_>) -> Self {
+        let chr = rom.chr.unwrap_or_default();
+        RomHashes {
+            prg: Checksum::of(&[rom.prg]),
+            chr: Checksum::of(&[chr]),
+            rom: Checksum::of(&[rom.prg, chr]),
+        }
+    }
+}
+
+/// Authoritative cartridge description for one dump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbEntry {
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: u16,
+    pub submapper: u8,
+    /// `None` when the mapper controls mirroring and the header bit is unused
+    pub mirroring: Option<Mirroring>,
+    pub battery: bool,
+    /// Sizes in bytes
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub board: String,
+    pub name: String,
+}
+
+/// A header field that disagreed with the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Mapper {
+        found: u16,
+        expected: u16,
+    },
+    Submapper {
+        found: u8,
+        expected: u8,
+    },
+    Mirroring {
+        found: Mirroring,
+        expected: Mirroring,
+    },
+    Battery {
+        found: bool,
+        expected: bool,
+    },
+    PrgRam {
+        found: usize,
+        expected: usize,
+    },
+    PrgNvram {
+        found: usize,
+        expected: usize,
+    },
+    ChrRam {
+        found: usize,
+        expected: usize,
+    },
+    ChrNvram {
+        found: usize,
+        expected: usize,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Mapper { found, expected } => {
+                write!(f, "mapper is {}, should be {}", found, expected)
+            }
+            Difference::Submapper { found, expected } => {
+                write!(f, "submapper is {}, should be {}", found, expected)
+            }
+            Difference::Mirroring { found, expected } => {
+                write!(f, "mirroring is {:?}, should be {:?}", found, expected)
+            }
+            Difference::Battery { found, expected } => {
+                write!(f, "battery flag is {}, should be {}", found, expected)
+            }
+            Difference::PrgRam { found, expected } => {
+                write!(f, "PRG-RAM is {} bytes, should be {}", found, expected)
+            }
+            Difference::PrgNvram { found, expected } => {
+                write!(f, "PRG-NVRAM is {} bytes, should be {}", found, expected)
+            }
+            Difference::ChrRam { found, expected } => {
+                write!(f, "CHR-RAM is {} bytes, should be {}", found, expected)
+            }
+            Difference::ChrNvram { found, expected } => {
+                write!(f, "CHR-NVRAM is {} bytes, should be {}", found, expected)
+            }
+        }
+    }
+}
+
+/// Result of checking a header against the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Correction<'a> {
+    pub entry: &'a DbEntry,
+    /// The header with every difference fixed
+    pub header: InesHeader,
+    pub differences: Vec<Difference>,
+}
+
+impl Correction<'_> {
+    pub fn is_needed(&self) -> bool {
+        !self.differences.is_empty()
+    }
+}
+
+/// Cartridge database keyed by the checksum of PRG-ROM followed by CHR-ROM.
+#[derive(Debug, Clone, Default)]
+pub struct RomDb {
+    entries: Vec<DbEntry>,
+    by_crc32: HashMap<u32, usize>,
+    by_sha1: HashMap<[u8; 20], usize>,
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        return None;
+    }
+    let mut sha1 = [0u8; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(sha1)
+}
+
+fn parse_entry(fields: &[&str]) -> std::result::Result<DbEntry, String> {
+    let number = |i: usize| {
+        fields[i]
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number '{}'", fields[i]))
+    };
+
+    let crc32 =
+        u32::from_str_radix(fields[0], 16).map_err(|_| format!("invalid CRC32 '{}'", fields[0]))?;
+    let sha1 = match fields[1] {
+        "-" => None,
+        hex => Some(parse_sha1(hex).ok_or_else(|| format!("invalid SHA-1 '{}'", hex))?),
+    };
+    let mirroring = match fields[4] {
+        "H" => Some(Mirroring::Horizontal),
+        "V" => Some(Mirroring::Vertical),
+        "4" => Some(Mirroring::FourScreen),
+        "-" => None,
+        x => return Err(format!("invalid mirroring '{}'", x)),
+    };
+    let battery = match fields[5] {
+        "0" => false,
+        "1" => true,
+        x => return Err(format!("invalid battery flag '{}'", x)),
+    };
+
+    let entry = DbEntry {
+        crc32,
+        sha1,
+        mapper: fields[2]
+            .parse()
+            .map_err(|_| format!("invalid mapper '{}'", fields[2]))?,
+        submapper: fields[3]
+            .parse()
+            .map_err(|_| format!("invalid submapper '{}'", fields[3]))?,
+        mirroring,
+        battery,
+        prg_ram_size: number(6)?,
+        prg_nvram_size: number(7)?,
+        chr_ram_size: number(8)?,
+        chr_nvram_size: number(9)?,
+        board: fields[10].to_string(),
+        name: fields[11].to_string(),
+    };
+
+    // catch sizes a header can't hold now rather than when correcting
+    entry
+        .apply(&mut InesHeader::builder())
+        .map_err(|e| e.to_string())?;
+    Ok(entry)
+}
+
+impl DbEntry {
+    fn apply<'b>(&self, builder: &'b mut InesHeaderBuilder) -> Result<&'b mut InesHeaderBuilder> {
+        builder
+            .mapper(self.mapper)
+            .submapper(self.submapper)
+            .battery(self.battery)
+            .prg_ram_size(self.prg_ram_size)?
+            .prg_nvram_size(self.prg_nvram_size)?
+            .chr_ram_size(self.chr_ram_size)?
+            .chr_nvram_size(self.chr_nvram_size)
+    }
+}
+
+impl RomDb {
+    /// The database bundled with the crate.
+    pub fn bundled() -> Self {
+        Self::from_tsv(BUNDLED).expect("bundled ROM database is valid")
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        fs::read_to_string(path)
+            .map_err(InesError::from)
+            .and_then(|text| Self::from_tsv(&text))
+            .map_err(|e| e.with_path(path))
+    }
+
+    /// Reads a tab separated database with the columns `crc32 sha1 mapper
+    /// submapper mirroring battery prg_ram prg_nvram chr_ram chr_nvram board
+    /// name`. Blank lines and lines starting with `#` are skipped.
+    pub fn from_tsv(text: &str) -> Result<Self> {
+        let mut db = RomDb::default();
+
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != COLUMNS {
+                return Err(InesError::Database {
+                    line: n + 1,
+                    message: format!("expected {} columns, found {}", COLUMNS, fields.len()),
+                });
+            }
+            let entry = parse_entry(&fields).map_err(|message| InesError::Database {
+                line: n + 1,
+                message,
+            })?;
+            db.insert(entry);
+        }
+
+        Ok(db)
+    }
+
+    /// Adds an entry, replacing any earlier one with the same checksums.
+    pub fn insert(&mut self, entry: DbEntry) {
+        let index = self.entries.len();
+        self.by_crc32.insert(entry.crc32, index);
+        if let Some(sha1) = entry.sha1 {
+            self.by_sha1.insert(sha1, index);
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[DbEntry] {
+        &self.entries
+    }
+
+    /// Finds the entry for a dump, by SHA-1 when the entry has one and by
+    /// CRC32 otherwise.
+    pub fn lookup(&self, hashes: &RomHashes) -> Option<&DbEntry> {
+        if let Some(&index) = self.by_sha1.get(&hashes.rom.sha1) {
+            return Some(&self.entries[index]);
+        }
+
+        let entry = &self.entries[*self.by_crc32.get(&hashes.rom.crc32)?];
+        match entry.sha1 {
+            Some(sha1) if sha1 != hashes.rom.sha1 => None,
+            _ => Some(entry),
+        }
+    }
+
+    /// Checks an image's header against the database, or `None` if the dump
+    /// is unknown. RAM sizes only exist in NES 2.0 headers, so they're only
+    /// compared when the image already has one.
+    pub fn correct<'a>(&'a self, rom: &InesRef<'_>) -> Result<Option<Correction<'a>>> {
+        let entry = match self.lookup(&RomHashes::of(rom)) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let found = &rom.header;
+        let mut start = found.clone();
+        if found.format() == HeaderFormat::Archaic {
+            // bytes 7-15 of archaic headers are garbage, only flags 6 counts
+            start = InesHeader {
+                prg_size: found.prg_size,
+                chr_size: found.chr_size,
+                mapper: found.mapper,
+                ..Default::default()
+            };
+        }
+        let mut builder = InesHeaderBuilder::from_header(&start);
+        let mut differences = Vec::new();
+
+        if found.mapper_number() != entry.mapper {
+            differences.push(Difference::Mapper {
+                found: found.mapper_number(),
+                expected: entry.mapper,
+            });
+        }
+        builder.mapper(entry.mapper);
+
+        let nes2 = found.nes2();
+        let submapper = nes2.as_ref().map_or(0, |nes2| nes2.submapper);
+        if submapper != entry.submapper {
+            differences.push(Difference::Submapper {
+                found: submapper,
+                expected: entry.submapper,
+            });
+            builder.submapper(entry.submapper);
+        }
+
+        if let Some(expected) = entry.mirroring {
+            if found.mirroring() != expected {
+                differences.push(Difference::Mirroring {
+                    found: found.mirroring(),
+                    expected,
+                });
+                builder.mirroring(expected);
+            }
+        }
+
+        if found.has_battery() != entry.battery {
+            differences.push(Difference::Battery {
+                found: found.has_battery(),
+                expected: entry.battery,
+            });
+            builder.battery(entry.battery);
+        }
+
+        if let Some(nes2) = nes2 {
+            let mut compare = |found, expected, difference: fn(usize, usize) -> Difference| {
+                if found != expected {
+                    differences.push(difference(found, expected));
+                }
+            };
+            compare(nes2.prg_ram_size, entry.prg_ram_size, |found, expected| {
+                Difference::PrgRam { found, expected }
+            });
+            compare(
+                nes2.prg_nvram_size,
+                entry.prg_nvram_size,
+                |found, expected| Difference::PrgNvram { found, expected },
+            );
+            compare(nes2.chr_ram_size, entry.chr_ram_size, |found, expected| {
+                Difference::ChrRam { found, expected }
+            });
+            compare(
+                nes2.chr_nvram_size,
+                entry.chr_nvram_size,
+                |found, expected| Difference::ChrNvram { found, expected },
+            );
+            entry.apply(&mut builder)?;
+        }
+
+        Ok(Some(Correction {
+            entry,
+            header: builder.build(),
+            differences,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::Ines;
+
+    const DB: &str = "# test database\n\
+        \n\
+        00000000\t-\t1\t0\t-\t1\t0\t8192\t8192\t0\tNES-SNROM\tNothing\n";
+
+    fn ines(mirroring: Mirroring, mapper: u16) -> Ines {
+        let mut builder = InesHeader::builder();
+        builder.mirroring(mirroring).mapper(mapper);
+        Ines {
+            header: builder.build(),
+            prg: vec![0x60; 16384],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let checksum = Checksum::of(&[b"123", b"456789"]);
+        assert_eq!(checksum.crc32, 0xCBF43926);
+        assert_eq!(
+            checksum.to_string(),
+            "CBF43926 f7c3bc1d808e04732adf679965ccc34ca7ae3441"
+        );
+    }
+
+    #[test]
+    fn test_rom_db_correct() {
+        let mut db = RomDb::from_tsv(DB).unwrap();
+        let rom = ines(Mirroring::Vertical, 4);
+        let hashes = RomHashes::of(&rom.view());
+        db.insert(DbEntry {
+            crc32: hashes.rom.crc32,
+            sha1: Some(hashes.rom.sha1),
+            ..db.entries()[0].clone()
+        });
+
+        let correction = db.correct(&rom.view()).unwrap().unwrap();
+        assert_eq!(correction.entry.board, "NES-SNROM");
+        assert_eq!(
+            correction.differences,
+            vec![
+                Difference::Mapper {
+                    found: 4,
+                    expected: 1
+                },
+                Difference::Battery {
+                    found: false,
+                    expected: true
+                },
+            ]
+        );
+        assert_eq!(correction.header.mapper_number(), 1);
+        assert!(correction.header.has_battery());
+        // mapper-controlled mirroring is left alone, iNES stays iNES
+        assert_eq!(correction.header.mirroring(), Mirroring::Vertical);
+        assert_eq!(correction.header.format(), HeaderFormat::Ines);
+
+        let nes2 = Ines {
+            header: InesHeaderBuilder::from_header(&rom.header)
+                .timing(crate::model::Timing::MultiRegion)
+                .build(),
+            ..rom
+        };
+        let correction = db.correct(&nes2.view()).unwrap().unwrap();
+        assert_eq!(correction.differences.len(), 4);
+        assert_eq!(
+            correction.differences[2],
+            Difference::PrgNvram {
+                found: 0,
+                expected: 8192
+            }
+        );
+        let fixed = correction.header.nes2().unwrap();
+        assert_eq!(fixed.prg_nvram_size, 8192);
+        assert_eq!(fixed.chr_ram_size, 8192);
+
+        let unknown = ines(Mirroring::Vertical, 0);
+        assert_eq!(
+            RomDb::from_tsv(DB)
+                .unwrap()
+                .correct(&unknown.view())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rom_db_errors() {
+        match RomDb::from_tsv("# header\n00000000\t-\t1\n") {
+            Err(InesError::Database { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match RomDb::from_tsv("00000000\t-\t1\t0\tX\t0\t0\t0\t0\t0\tb\tn\n") {
+            Err(InesError::Database { line, message }) => {
+                assert_eq!(line, 1);
+                assert_eq!(message, "invalid mirroring 'X'");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(RomDb::from_tsv("00000000\t-\t1\t0\tH\t0\t100\t0\t0\t0\tb\tn\n").is_err());
+    }
+
+    #[test]
+    fn test_bundled_db() {
+        assert!(!RomDb::bundled().entries().is_empty());
+    }
+}
@@ -1,9 +1,200 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use crate::patch::PatchFile;
+use crate::rom::Format;
+use crate::unif::UnifMirroring;
+
+/// Part of an image a parse error refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Header,
+    Trainer,
+    PrgRom,
+    ChrRom,
+    PrgRam,
+    ChrRam,
+    InstRom,
+    Prom,
+    /// UNIF chunk with the given ID
+    Chunk([u8; 4]),
+    /// FDS block with the given block code
+    Block(u8),
+    /// IPS, UPS or BPS patch
+    Patch,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Section::Header => "header",
+            Section::Trainer => "trainer",
+            Section::PrgRom => "PRG-ROM",
+            Section::ChrRom => "CHR-ROM",
+            Section::PrgRam => "PRG-RAM",
+            Section::ChrRam => "CHR-RAM",
+            Section::InstRom => "INST-ROM",
+            Section::Prom => "PROM",
+            Section::Patch => "patch",
+            Section::Chunk(id) => return write!(f, "chunk '{}'", String::from_utf8_lossy(id)),
+            Section::Block(code) => return write!(f, "block {}", code),
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum InesError {
-    #[error("IO Error")]
+    #[error("IO Error: {0}")]
     IOError(#[from] std::io::Error),
+
+    #[error("bad magic {found:02x?} at offset {offset:#x}")]
+    BadMagic { offset: u64, found: [u8; 4] },
+
+    #[error(
+        "{section} truncated at offset {offset:#x}: expected {expected} bytes, found {actual}"
+    )]
+    Truncated {
+        section: Section,
+        offset: u64,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("invalid NES 2.0 {section} size encoding (LSB {lsb:#04x}, MSB {msb:#x})")]
+    InvalidSize { section: Section, lsb: u8, msb: u8 },
+
+    #[error("{section} size of {size} bytes can't be encoded in a header")]
+    UnencodableSize { section: Section, size: usize },
+
+    #[error("expected block {expected} at offset {offset:#x}, found {found}")]
+    InvalidBlock {
+        offset: u64,
+        expected: u8,
+        found: u8,
+    },
+
+    #[error("{section} checksum mismatch at offset {offset:#x}: stored {expected:#x}, computed {actual:#x}")]
+    ChecksumMismatch {
+        section: Section,
+        offset: u64,
+        expected: u32,
+        actual: u32,
+    },
+
+    #[error("missing required chunk '{}'", String::from_utf8_lossy(.0))]
+    MissingChunk([u8; 4]),
+
+    #[error("unsupported required chunk '{}'", String::from_utf8_lossy(.0))]
+    UnsupportedChunk([u8; 4]),
+
+    #[error("unknown board '{0}'")]
+    UnknownBoard(String),
+
+    #[error("{0:?} mirroring can't be expressed in an iNES header")]
+    UnsupportedMirroring(UnifMirroring),
+
+    #[error("{0} images don't describe a cartridge")]
+    NotACartridge(Format),
+
+    #[error("unrecognized ROM format")]
+    UnknownFormat,
+
+    #[error("invalid patch command at offset {offset:#x}: {reason}")]
+    InvalidPatch { offset: u64, reason: &'static str },
+
+    #[error("{file} CRC32 is {actual:#010x}, patch expects {expected:#010x}")]
+    PatchChecksum {
+        file: PatchFile,
+        expected: u32,
+        actual: u32,
+    },
+
+    #[error("ROM database line {line}: {message}")]
+    Database { line: usize, message: String },
+
+    #[error("invalid archive: {0}")]
+    Archive(String),
+
+    #[error("archive has no .nes, .unf or .fds member")]
+    NoArchiveMember,
+
+    #[error("archive has several ROM members, pick one of {}", .0.join(", "))]
+    AmbiguousArchiveMember(Vec<String>),
+
+    #[error("archive has no member '{0}'")]
+    MissingArchiveMember(String),
+
+    #[error("in archive member '{member}': {source}")]
+    ArchiveMember {
+        member: String,
+        source: Box<InesError>,
+    },
+
+    #[error("{len} bytes at {address:#06x} don't fit in a {bank_size} byte PRG bank")]
+    Placement {
+        address: u16,
+        len: usize,
+        bank_size: usize,
+    },
+
+    #[error("tile sheet image: {0}")]
+    Image(String),
+
+    #[error("unable to load ROM from '{}': {}", path.display(), source)]
+    Load {
+        path: PathBuf,
+        source: Box<InesError>,
+    },
+}
+
+impl InesError {
+    pub(crate) fn with_path(self, path: &Path) -> Self {
+        InesError::Load {
+            path: path.to_owned(),
+            source: Box::new(self),
+        }
+    }
+
+    pub(crate) fn with_member(self, member: &str) -> Self {
+        InesError::ArchiveMember {
+            member: member.to_owned(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Archive member the error occurred in, if the ROM was unpacked from one.
+    pub fn member(&self) -> Option<&str> {
+        match self {
+            InesError::ArchiveMember { member, .. } => Some(member),
+            InesError::Load { source, .. } => source.member(),
+            _ => None,
+        }
+    }
+
+    /// File the error occurred in, if it was loaded from disk.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            InesError::Load { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Byte offset into the image where parsing failed, if known.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            InesError::BadMagic { offset, .. }
+            | InesError::Truncated { offset, .. }
+            | InesError::InvalidBlock { offset, .. }
+            | InesError::ChecksumMismatch { offset, .. }
+            | InesError::InvalidPatch { offset, .. } => Some(*offset),
+            InesError::Load { source, .. } | InesError::ArchiveMember { source, .. } => {
+                source.offset()
+            }
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, InesError>;
@@ -0,0 +1,393 @@
+use std::path::Path;
+
+use crate::archive;
+use crate::error::{InesError, Result, Section};
+use crate::model::Sections;
+
+const FWNES_HEADER_SIZE: usize = 16;
+/// Side size in fwNES images, which strip the block checksums.
+const FDS_SIDE_SIZE: usize = 65500;
+/// Side size in QD dumps, which keep them.
+const QD_SIDE_SIZE: usize = 65536;
+
+const DISK_INFO_SIZE: usize = 56;
+const FILE_AMOUNT_SIZE: usize = 2;
+const FILE_HEADER_SIZE: usize = 16;
+
+const DISK_INFO_BLOCK: u8 = 1;
+const FILE_AMOUNT_BLOCK: u8 = 2;
+const FILE_HEADER_BLOCK: u8 = 3;
+const FILE_DATA_BLOCK: u8 = 4;
+
+const DISK_VERIFICATION: &[u8; 14] = b"*NINTENDO-HVC*";
+
+/// Layout of a disk image on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdsFormat {
+    /// fwNES `.fds`, 65500 bytes per side without block checksums
+    Fwnes,
+    /// Raw QD dump, 65536 bytes per side with a CRC after every block
+    Qd,
+}
+
+/// Disk info block, block 1 of every side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskInfo {
+    pub manufacturer: u8,
+    /// Three letter game code
+    pub game_name: [u8; 3],
+    pub game_type: u8,
+    pub revision: u8,
+    /// 0 for side A, 1 for side B
+    pub side: u8,
+    pub disk_number: u8,
+    pub disk_type: u8,
+    /// Files with an ID up to this one are loaded at boot
+    pub boot_file: u8,
+    /// BCD year (Showa era), month, day
+    pub manufacturing_date: [u8; 3],
+    pub rewritten_date: [u8; 3],
+    /// The whole block, including the block code
+    pub raw: [u8; DISK_INFO_SIZE],
+}
+
+impl DiskInfo {
+    fn from_block(block: &[u8]) -> Self {
+        let mut raw = [0u8; DISK_INFO_SIZE];
+        raw.copy_from_slice(block);
+
+        DiskInfo {
+            manufacturer: raw[15],
+            game_name: [raw[16], raw[17], raw[18]],
+            game_type: raw[19],
+            revision: raw[20],
+            side: raw[21],
+            disk_number: raw[22],
+            disk_type: raw[23],
+            boot_file: raw[25],
+            manufacturing_date: [raw[31], raw[32], raw[33]],
+            rewritten_date: [raw[44], raw[45], raw[46]],
+            raw,
+        }
+    }
+}
+
+/// Where the BIOS copies a file to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Prg,
+    Chr,
+    /// Nametable data loaded into VRAM
+    Nametable,
+    Other(u8),
+}
+
+impl FileKind {
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => FileKind::Prg,
+            1 => FileKind::Chr,
+            2 => FileKind::Nametable,
+            x => FileKind::Other(x),
+        }
+    }
+}
+
+/// A file header block together with its data block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdsFile {
+    pub number: u8,
+    pub id: u8,
+    pub name: [u8; 8],
+    /// Load address in the destination memory
+    pub address: u16,
+    pub kind: FileKind,
+    pub data: Vec<u8>,
+}
+
+impl FdsFile {
+    /// File name with trailing padding removed.
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.name)
+            .trim_end_matches([' ', '\0'])
+            .to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskSide {
+    pub info: DiskInfo,
+    /// File count from the file amount block. Copy protection sometimes
+    /// hides files past it, which are still listed in `files`.
+    pub file_amount: u8,
+    pub files: Vec<FdsFile>,
+}
+
+impl DiskSide {
+    pub fn file(&self, name: &str) -> Option<&FdsFile> {
+        self.files.iter().find(|file| file.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdsImage {
+    pub format: FdsFormat,
+    /// Optional fwNES header, kept verbatim
+    pub header: Option<[u8; FWNES_HEADER_SIZE]>,
+    pub sides: Vec<DiskSide>,
+}
+
+/// FDS block checksum, CRC-16 with the reflected CCITT polynomial seeded so
+/// that the implicit $80 gap terminator is accounted for.
+pub fn block_crc(block: &[u8]) -> u16 {
+    let mut sum: u16 = 0x8000;
+    for &byte in block.iter().chain(&[0, 0]) {
+        for bit in 0..8 {
+            let carry = sum & 1 != 0;
+            sum = (sum >> 1) | (u16::from((byte >> bit) & 1) << 15);
+            if carry {
+                sum ^= 0x8408;
+            }
+        }
+    }
+    sum
+}
+
+/// Reads blocks off a single side.
+struct SideReader<'a> {
+    sections: Sections<'a>,
+    format: FdsFormat,
+}
+
+impl<'a> SideReader<'a> {
+    fn peek_code(&self) -> Option<u8> {
+        self.sections.remaining().first().copied()
+    }
+
+    fn block(&mut self, code: u8, len: usize) -> Result<&'a [u8]> {
+        let offset = self.sections.offset();
+        let block = self.sections.take(Section::Block(code), len)?;
+        if block[0] != code {
+            return Err(InesError::InvalidBlock {
+                offset,
+                expected: code,
+                found: block[0],
+            });
+        }
+
+        if self.format == FdsFormat::Qd {
+            let crc = self.sections.take(Section::Block(code), 2)?;
+            let expected = u16::from_le_bytes([crc[0], crc[1]]);
+            let actual = block_crc(block);
+            if expected != actual {
+                return Err(InesError::ChecksumMismatch {
+                    section: Section::Block(code),
+                    offset,
+                    expected: expected.into(),
+                    actual: actual.into(),
+                });
+            }
+        }
+
+        Ok(block)
+    }
+
+    fn side(&mut self) -> Result<DiskSide> {
+        let offset = self.sections.offset();
+        let info = self.block(DISK_INFO_BLOCK, DISK_INFO_SIZE)?;
+        if &info[1..15] != DISK_VERIFICATION {
+            let mut found = [0u8; 4];
+            found.copy_from_slice(&info[1..5]);
+            return Err(InesError::BadMagic { offset, found });
+        }
+        let info = DiskInfo::from_block(info);
+        let file_amount = self.block(FILE_AMOUNT_BLOCK, FILE_AMOUNT_SIZE)?[1];
+
+        let mut files = Vec::new();
+        while self.peek_code() == Some(FILE_HEADER_BLOCK) {
+            let header = self.block(FILE_HEADER_BLOCK, FILE_HEADER_SIZE)?;
+            let size = usize::from(u16::from_le_bytes([header[13], header[14]]));
+            let data = self.block(FILE_DATA_BLOCK, size + 1)?;
+
+            let mut name = [0u8; 8];
+            name.copy_from_slice(&header[3..11]);
+            files.push(FdsFile {
+                number: header[1],
+                id: header[2],
+                name,
+                address: u16::from_le_bytes([header[11], header[12]]),
+                kind: FileKind::from_byte(header[15]),
+                data: data[1..].to_vec(),
+            });
+        }
+
+        Ok(DiskSide {
+            info,
+            file_amount,
+            files,
+        })
+    }
+}
+
+impl FdsImage {
+    /// Loads an image, unpacking it first if it's zipped or gzipped.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        archive::load(path, None, Self::from_bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (header, body) = if bytes.starts_with(b"FDS\x1a") {
+            let mut sections = Sections::new(bytes, 0);
+            let mut header = [0u8; FWNES_HEADER_SIZE];
+            header.copy_from_slice(sections.take(Section::Header, FWNES_HEADER_SIZE)?);
+            (Some(header), sections.remaining())
+        } else {
+            (None, bytes)
+        };
+        let base = (bytes.len() - body.len()) as u64;
+
+        let (format, side_size) = if !body.is_empty() && body.len() % QD_SIDE_SIZE == 0 {
+            (FdsFormat::Qd, QD_SIDE_SIZE)
+        } else {
+            (FdsFormat::Fwnes, FDS_SIDE_SIZE)
+        };
+
+        let sides = body
+            .chunks(side_size)
+            .enumerate()
+            .map(|(n, side)| {
+                let offset = base + (n * side_size) as u64;
+                SideReader {
+                    sections: Sections::new(side, offset),
+                    format,
+                }
+                .side()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(FdsImage {
+            format,
+            header,
+            sides,
+        })
+    }
+
+    /// Every file on the disk, with the index of the side it's on.
+    pub fn files(&self) -> impl Iterator<Item = (usize, &FdsFile)> {
+        self.sides
+            .iter()
+            .enumerate()
+            .flat_map(|(n, side)| side.files.iter().map(move |file| (n, file)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk_info(side: u8) -> Vec<u8> {
+        let mut block = vec![0u8; DISK_INFO_SIZE];
+        block[0] = DISK_INFO_BLOCK;
+        block[1..15].copy_from_slice(DISK_VERIFICATION);
+        block[16..19].copy_from_slice(b"TST");
+        block[21] = side;
+        block[25] = 0x0F;
+        block
+    }
+
+    fn file(number: u8, name: &[u8; 8], address: u16, kind: u8, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut header = vec![FILE_HEADER_BLOCK, number, number];
+        header.extend(name);
+        header.extend(&address.to_le_bytes());
+        header.extend(&(data.len() as u16).to_le_bytes());
+        header.push(kind);
+
+        let mut block = vec![FILE_DATA_BLOCK];
+        block.extend(data);
+        vec![header, block]
+    }
+
+    fn side(blocks: &[Vec<u8>], format: FdsFormat) -> Vec<u8> {
+        let mut result = Vec::new();
+        for block in blocks {
+            result.extend(block);
+            if format == FdsFormat::Qd {
+                result.extend(&block_crc(block).to_le_bytes());
+            }
+        }
+        let size = match format {
+            FdsFormat::Fwnes => FDS_SIDE_SIZE,
+            FdsFormat::Qd => QD_SIDE_SIZE,
+        };
+        result.resize(size, 0);
+        result
+    }
+
+    fn blocks(side_number: u8) -> Vec<Vec<u8>> {
+        let mut blocks = vec![disk_info(side_number), vec![FILE_AMOUNT_BLOCK, 2]];
+        blocks.extend(file(0, b"KYODAKU-", 0x2800, 2, &[0x24; 224]));
+        blocks.extend(file(1, b"MAIN    ", 0x6000, 0, &[0xEA; 4096]));
+        blocks
+    }
+
+    #[test]
+    fn test_fds_parse() {
+        let mut bytes = b"FDS\x1a\x02".to_vec();
+        bytes.resize(FWNES_HEADER_SIZE, 0);
+        bytes.extend(side(&blocks(0), FdsFormat::Fwnes));
+        bytes.extend(side(&blocks(1), FdsFormat::Fwnes));
+
+        let image = FdsImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.format, FdsFormat::Fwnes);
+        assert!(image.header.is_some());
+        assert_eq!(image.sides.len(), 2);
+        assert_eq!(image.sides[1].info.side, 1);
+        assert_eq!(&image.sides[0].info.game_name, b"TST");
+        assert_eq!(image.sides[0].file_amount, 2);
+
+        let main = image.sides[0].file("MAIN").unwrap();
+        assert_eq!(main.address, 0x6000);
+        assert_eq!(main.kind, FileKind::Prg);
+        assert_eq!(main.data, vec![0xEA; 4096]);
+        assert_eq!(image.files().count(), 4);
+    }
+
+    #[test]
+    fn test_qd_checksums() {
+        let mut bytes = side(&blocks(0), FdsFormat::Qd);
+        let image = FdsImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.format, FdsFormat::Qd);
+        assert_eq!(image.header, None);
+        assert_eq!(image.sides[0].files.len(), 2);
+
+        // corrupt the file amount block
+        bytes[DISK_INFO_SIZE + 2 + 1] = 3;
+        match FdsImage::from_bytes(&bytes) {
+            Err(InesError::ChecksumMismatch {
+                section, offset, ..
+            }) => {
+                assert_eq!(section, Section::Block(FILE_AMOUNT_BLOCK));
+                assert_eq!(offset, (DISK_INFO_SIZE + 2) as u64);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fds_bad_block() {
+        let mut blocks = blocks(0);
+        blocks[1][0] = 5;
+        match FdsImage::from_bytes(&side(&blocks, FdsFormat::Fwnes)) {
+            Err(InesError::InvalidBlock {
+                offset,
+                expected,
+                found,
+            }) => {
+                assert_eq!(offset, DISK_INFO_SIZE as u64);
+                assert_eq!(expected, FILE_AMOUNT_BLOCK);
+                assert_eq!(found, 5);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,798 @@
+use binread::BinRead;
+use std::io::{self, Write};
+
+use crate::error::{InesError, Result, Section};
+use crate::write::BinWrite;
+
+pub(crate) const PRG_ROM_UNIT: usize = 16384;
+pub(crate) const CHR_ROM_UNIT: usize = 8192;
+
+/// Header revision, identified by bits 2-3 of flags 7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// Pre-1.0 iNES header. Bytes 7-15 are unreliable and commonly contain
+    /// ripper signatures such as "DiskDude!".
+    Archaic,
+    Ines,
+    Nes2,
+}
+
+/// Nametable arrangement hard-wired on the cartridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+/// Console the image is meant for, from bits 0-1 of flags 7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+    /// NES 2.0 extended console type, from the low nibble of byte 13
+    Extended(u8),
+}
+
+/// CPU/PPU timing of the console the image targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// RP2C02 ("NTSC NES")
+    Ntsc,
+    /// RP2C07 ("Licensed PAL NES")
+    Pal,
+    /// Works on both NTSC and PAL consoles
+    MultiRegion,
+    /// UA6538 ("Dendy")
+    Dendy,
+}
+
+impl Timing {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        }
+    }
+}
+
+/// PPU fitted to a Vs. System board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VsPpu {
+    RP2C03B,
+    RP2C03G,
+    RP2C04_0001,
+    RP2C04_0002,
+    RP2C04_0003,
+    RP2C04_0004,
+    RC2C03B,
+    RC2C03C,
+    RC2C05_01,
+    RC2C05_02,
+    RC2C05_03,
+    RC2C05_04,
+    RC2C05_05,
+    Reserved(u8),
+}
+
+impl VsPpu {
+    fn from_bits(bits: u8) -> Self {
+        use VsPpu::*;
+        match bits & 0x0F {
+            0x0 => RP2C03B,
+            0x1 => RP2C03G,
+            0x2 => RP2C04_0001,
+            0x3 => RP2C04_0002,
+            0x4 => RP2C04_0003,
+            0x5 => RP2C04_0004,
+            0x6 => RC2C03B,
+            0x7 => RC2C03C,
+            0x8 => RC2C05_01,
+            0x9 => RC2C05_02,
+            0xA => RC2C05_03,
+            0xB => RC2C05_04,
+            0xC => RC2C05_05,
+            x => Reserved(x),
+        }
+    }
+}
+
+/// Vs. System board variant, including the copy protection it expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VsHardware {
+    Unisystem,
+    UnisystemRbiBaseball,
+    UnisystemTkoBoxing,
+    UnisystemSuperXevious,
+    UnisystemIceClimber,
+    DualSystem,
+    DualSystemRaidOnBungelingBay,
+    Reserved(u8),
+}
+
+impl VsHardware {
+    fn from_bits(bits: u8) -> Self {
+        use VsHardware::*;
+        match bits & 0x0F {
+            0x0 => Unisystem,
+            0x1 => UnisystemRbiBaseball,
+            0x2 => UnisystemTkoBoxing,
+            0x3 => UnisystemSuperXevious,
+            0x4 => UnisystemIceClimber,
+            0x5 => DualSystem,
+            0x6 => DualSystemRaidOnBungelingBay,
+            x => Reserved(x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VsSystem {
+    pub ppu: VsPpu,
+    pub hardware: VsHardware,
+}
+
+/// Fields only present in NES 2.0 headers, decoded from bytes 8-15.
+///
+/// ROM sizes are available for every header revision through
+/// [`InesHeader::prg_rom_size`] and [`InesHeader::chr_rom_size`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nes2Header {
+    /// 12-bit mapper number
+    pub mapper: u16,
+    pub submapper: u8,
+    /// Sizes in bytes
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    /// Only set for Vs. System images
+    pub vs_system: Option<VsSystem>,
+    /// Number of miscellaneous ROMs following the CHR data
+    pub misc_roms: u8,
+    /// Default expansion device, as numbered on the NESdev wiki
+    pub expansion_device: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BinRead)]
+#[br(magic = b"NES\x1a")]
+pub struct InesHeader {
+    pub prg_size: u8, // 4
+    pub chr_size: u8, // 5
+    pub mapper: u8,   // 6
+    pub flags7: u8,   // 7
+    pub flags8: u8,   // 8
+    pub flags9: u8,   // 9
+    pub flags10: u8,  // 10
+    pub flags11: u8,  // 11
+    pub flags12: u8,  // 12
+    pub flags13: u8,  // 13
+    pub flags14: u8,  // 14
+    pub flags15: u8,  // 15
+}
+
+/// Decodes a NES 2.0 ROM size. A most significant nibble of `0xF` selects the
+/// exponent-multiplier form, `2^E * (MM * 2 + 1)` with the LSB laid out as
+/// `EEEEEEMM`. Returns `None` when the result doesn't fit in `usize`.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = u32::from(lsb >> 2);
+        let multiplier = usize::from(lsb & 0b11) * 2 + 1;
+        1usize.checked_shl(exponent)?.checked_mul(multiplier)
+    } else {
+        (usize::from(msb) << 8 | usize::from(lsb)).checked_mul(unit)
+    }
+}
+
+/// Inverse of [`nes2_rom_size`], preferring the plain unit count and falling
+/// back to the exponent-multiplier form. Returns `(lsb, msb)`.
+fn encode_rom_size(size: usize, unit: usize) -> Option<(u8, u8)> {
+    if size.is_multiple_of(unit) && size / unit < 0xF00 {
+        let units = size / unit;
+        return Some((units as u8, (units >> 8) as u8));
+    }
+
+    let exponent = size.trailing_zeros();
+    let multiplier = size >> exponent;
+    match multiplier {
+        1 | 3 | 5 | 7 => Some(((exponent as u8) << 2 | (multiplier as u8 >> 1), 0x0F)),
+        _ => None,
+    }
+}
+
+/// Decodes a NES 2.0 RAM shift count, where a non-zero value means
+/// `64 << shift` bytes.
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        x => 64 << x,
+    }
+}
+
+/// Inverse of [`nes2_ram_size`], or `None` unless `size` is zero or a power
+/// of two the shift count can reach.
+fn encode_ram_size(size: usize) -> Option<u8> {
+    match size {
+        0 => Some(0),
+        _ if size.is_power_of_two() && size >= 128 => {
+            let shift = size.trailing_zeros() - 6;
+            if shift <= 0x0F {
+                Some(shift as u8)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+impl InesHeader {
+    pub fn builder() -> InesHeaderBuilder {
+        InesHeaderBuilder::new()
+    }
+
+    /// Builds a header from its raw 16 bytes, or `None` if the magic doesn't
+    /// match.
+    pub fn from_bytes(bytes: &[u8; 16]) -> Option<Self> {
+        if bytes[..4] != b"NES\x1a"[..] {
+            return None;
+        }
+
+        Some(InesHeader {
+            prg_size: bytes[4],
+            chr_size: bytes[5],
+            mapper: bytes[6],
+            flags7: bytes[7],
+            flags8: bytes[8],
+            flags9: bytes[9],
+            flags10: bytes[10],
+            flags11: bytes[11],
+            flags12: bytes[12],
+            flags13: bytes[13],
+            flags14: bytes[14],
+            flags15: bytes[15],
+        })
+    }
+
+    /// Classifies the header by bits 2-3 of flags 7. The reserved value 3
+    /// isn't an error: like any other header whose bytes 7-15 can't be
+    /// trusted, NESdev says to read it as [`HeaderFormat::Archaic`], so
+    /// dumps with garbage there still load.
+    pub fn format(&self) -> HeaderFormat {
+        match self.flags7 & 0b0000_1100 {
+            0b1000 => HeaderFormat::Nes2,
+            0b0000 if [self.flags12, self.flags13, self.flags14, self.flags15] == [0; 4] => {
+                HeaderFormat::Ines
+            }
+            _ => HeaderFormat::Archaic,
+        }
+    }
+
+    pub fn is_nes2(&self) -> bool {
+        self.format() == HeaderFormat::Nes2
+    }
+
+    /// PRG-ROM size in bytes, or `None` if a NES 2.0 size is out of range.
+    pub fn prg_rom_size(&self) -> Option<usize> {
+        if self.is_nes2() {
+            nes2_rom_size(self.prg_size, self.flags9 & 0x0F, PRG_ROM_UNIT)
+        } else {
+            Some(usize::from(self.prg_size) * PRG_ROM_UNIT)
+        }
+    }
+
+    /// CHR-ROM size in bytes, or `None` if a NES 2.0 size is out of range.
+    pub fn chr_rom_size(&self) -> Option<usize> {
+        if self.is_nes2() {
+            nes2_rom_size(self.chr_size, self.flags9 >> 4, CHR_ROM_UNIT)
+        } else {
+            Some(usize::from(self.chr_size) * CHR_ROM_UNIT)
+        }
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        if self.mapper & 0b0000_1000 != 0 {
+            Mirroring::FourScreen
+        } else if self.mapper & 0b0000_0001 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.mapper & 0b0000_0010 != 0
+    }
+
+    pub fn has_trainer(&self) -> bool {
+        self.mapper & 0b0000_0100 != 0
+    }
+
+    /// Mapper number assembled from every nibble the header revision defines.
+    /// Archaic headers only contribute the low nibble from flags 6, since
+    /// their flags 7 is usually garbage.
+    pub fn mapper_number(&self) -> u16 {
+        let low = u16::from(self.mapper >> 4);
+        match self.format() {
+            HeaderFormat::Archaic => low,
+            HeaderFormat::Ines => u16::from(self.flags7 & 0xF0) | low,
+            HeaderFormat::Nes2 => {
+                u16::from(self.flags8 & 0x0F) << 8 | u16::from(self.flags7 & 0xF0) | low
+            }
+        }
+    }
+
+    pub fn console_type(&self) -> ConsoleType {
+        if self.format() == HeaderFormat::Archaic {
+            return ConsoleType::Nes;
+        }
+
+        match self.flags7 & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Extended(self.flags13 & 0x0F),
+        }
+    }
+
+    /// Decodes the NES 2.0 specific fields, or `None` for older headers.
+    pub fn nes2(&self) -> Option<Nes2Header> {
+        if !self.is_nes2() {
+            return None;
+        }
+
+        let vs_system = if self.console_type() == ConsoleType::VsSystem {
+            Some(VsSystem {
+                ppu: VsPpu::from_bits(self.flags13),
+                hardware: VsHardware::from_bits(self.flags13 >> 4),
+            })
+        } else {
+            None
+        };
+
+        Some(Nes2Header {
+            mapper: self.mapper_number(),
+            submapper: self.flags8 >> 4,
+            prg_ram_size: nes2_ram_size(self.flags10 & 0x0F),
+            prg_nvram_size: nes2_ram_size(self.flags10 >> 4),
+            chr_ram_size: nes2_ram_size(self.flags11 & 0x0F),
+            chr_nvram_size: nes2_ram_size(self.flags11 >> 4),
+            timing: Timing::from_bits(self.flags12),
+            vs_system,
+            misc_roms: self.flags14 & 0b11,
+            expansion_device: self.flags15 & 0b0011_1111,
+        })
+    }
+}
+
+impl BinWrite for InesHeader {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"NES\x1a")?;
+        writer.write_all(&[
+            self.prg_size,
+            self.chr_size,
+            self.mapper,
+            self.flags7,
+            self.flags8,
+            self.flags9,
+            self.flags10,
+            self.flags11,
+            self.flags12,
+            self.flags13,
+            self.flags14,
+            self.flags15,
+        ])
+    }
+}
+
+impl Default for InesHeader {
+    fn default() -> Self {
+        InesHeader {
+            prg_size: 1,
+            chr_size: 0,
+            mapper: 0,
+            flags7: 0,
+            flags8: 0,
+            flags9: 0,
+            flags10: 0,
+            flags11: 0,
+            flags12: 0,
+            flags13: 0,
+            flags14: 0,
+            flags15: 0,
+        }
+    }
+}
+
+/// Builds an [`InesHeader`] from typed values. The header is promoted to
+/// NES 2.0 whenever a value can't be expressed in iNES.
+#[derive(Debug, Clone)]
+pub struct InesHeaderBuilder {
+    header: InesHeader,
+}
+
+impl InesHeaderBuilder {
+    pub fn new() -> Self {
+        InesHeaderBuilder {
+            header: Default::default(),
+        }
+    }
+
+    /// Starts from an existing header, keeping every field that isn't set.
+    pub fn from_header(header: &InesHeader) -> Self {
+        InesHeaderBuilder {
+            header: header.clone(),
+        }
+    }
+
+    fn set_nes2(&mut self) {
+        self.header.flags7 = (self.header.flags7 & !0b0000_1100) | 0b0000_1000;
+    }
+
+    fn flag6(&mut self, mask: u8, value: bool) -> &mut Self {
+        if value {
+            self.header.mapper |= mask;
+        } else {
+            self.header.mapper &= !mask;
+        }
+        self
+    }
+
+    /// PRG-ROM size in 16 KiB units
+    pub fn prg_size(&mut self, size: u8) -> &mut Self {
+        self.header.prg_size = size;
+        self
+    }
+
+    /// CHR-ROM size in 8 KiB units
+    pub fn chr_size(&mut self, size: u8) -> &mut Self {
+        self.header.chr_size = size;
+        self
+    }
+
+    /// PRG-ROM size in bytes, switching to NES 2.0 when iNES can't express it
+    pub fn prg_rom_size(&mut self, size: usize) -> Result<&mut Self> {
+        let (lsb, msb) = encode_rom_size(size, PRG_ROM_UNIT).ok_or(InesError::UnencodableSize {
+            section: Section::PrgRom,
+            size,
+        })?;
+        self.header.prg_size = lsb;
+        self.header.flags9 = (self.header.flags9 & 0xF0) | msb;
+        if msb != 0 {
+            self.set_nes2();
+        }
+        Ok(self)
+    }
+
+    /// CHR-ROM size in bytes, switching to NES 2.0 when iNES can't express it
+    pub fn chr_rom_size(&mut self, size: usize) -> Result<&mut Self> {
+        let (lsb, msb) = encode_rom_size(size, CHR_ROM_UNIT).ok_or(InesError::UnencodableSize {
+            section: Section::ChrRom,
+            size,
+        })?;
+        self.header.chr_size = lsb;
+        self.header.flags9 = (self.header.flags9 & 0x0F) | (msb << 4);
+        if msb != 0 {
+            self.set_nes2();
+        }
+        Ok(self)
+    }
+
+    fn ram_size(&mut self, section: Section, nvram: bool, size: usize) -> Result<&mut Self> {
+        let shift = encode_ram_size(size).ok_or(InesError::UnencodableSize { section, size })?;
+        let flags = match section {
+            Section::ChrRam => &mut self.header.flags11,
+            _ => &mut self.header.flags10,
+        };
+        *flags = if nvram {
+            (*flags & 0x0F) | (shift << 4)
+        } else {
+            (*flags & 0xF0) | shift
+        };
+        if shift != 0 {
+            self.set_nes2();
+        }
+        Ok(self)
+    }
+
+    /// Volatile PRG-RAM size in bytes, a NES 2.0 only field
+    pub fn prg_ram_size(&mut self, size: usize) -> Result<&mut Self> {
+        self.ram_size(Section::PrgRam, false, size)
+    }
+
+    /// Battery-backed PRG-RAM size in bytes, a NES 2.0 only field
+    pub fn prg_nvram_size(&mut self, size: usize) -> Result<&mut Self> {
+        self.ram_size(Section::PrgRam, true, size)
+    }
+
+    /// Volatile CHR-RAM size in bytes, a NES 2.0 only field
+    pub fn chr_ram_size(&mut self, size: usize) -> Result<&mut Self> {
+        self.ram_size(Section::ChrRam, false, size)
+    }
+
+    /// Battery-backed CHR-RAM size in bytes, a NES 2.0 only field
+    pub fn chr_nvram_size(&mut self, size: usize) -> Result<&mut Self> {
+        self.ram_size(Section::ChrRam, true, size)
+    }
+
+    pub fn mirroring(&mut self, mirroring: Mirroring) -> &mut Self {
+        match mirroring {
+            Mirroring::Horizontal => self.flag6(0b0000_1001, false),
+            Mirroring::Vertical => self.flag6(0b0000_1000, false).flag6(0b0000_0001, true),
+            Mirroring::FourScreen => self.flag6(0b0000_1000, true),
+        }
+    }
+
+    pub fn battery(&mut self, battery: bool) -> &mut Self {
+        self.flag6(0b0000_0010, battery)
+    }
+
+    pub fn trainer(&mut self, trainer: bool) -> &mut Self {
+        self.flag6(0b0000_0100, trainer)
+    }
+
+    /// Mapper number, switching to NES 2.0 above 255. Only the low 12 bits
+    /// fit in a header; higher bits are dropped.
+    pub fn mapper(&mut self, mapper: u16) -> &mut Self {
+        let header = &mut self.header;
+        header.mapper = (header.mapper & 0x0F) | ((mapper as u8 & 0x0F) << 4);
+        header.flags7 = (header.flags7 & 0x0F) | (mapper as u8 & 0xF0);
+        header.flags8 = (header.flags8 & 0xF0) | ((mapper >> 8) as u8 & 0x0F);
+
+        if mapper > 0xFF {
+            self.set_nes2();
+        }
+        self
+    }
+
+    /// NES 2.0 submapper. Only the low 4 bits fit in a header; higher bits
+    /// are dropped.
+    pub fn submapper(&mut self, submapper: u8) -> &mut Self {
+        self.header.flags8 = (self.header.flags8 & 0x0F) | ((submapper & 0x0F) << 4);
+        if submapper != 0 {
+            self.set_nes2();
+        }
+        self
+    }
+
+    pub fn console_type(&mut self, console: ConsoleType) -> &mut Self {
+        let bits = match console {
+            ConsoleType::Nes => 0,
+            ConsoleType::VsSystem => 1,
+            ConsoleType::PlayChoice10 => 2,
+            ConsoleType::Extended(kind) => {
+                self.header.flags13 = (self.header.flags13 & 0xF0) | (kind & 0x0F);
+                self.set_nes2();
+                3
+            }
+        };
+        self.header.flags7 = (self.header.flags7 & !0b11) | bits;
+        self
+    }
+
+    pub fn timing(&mut self, timing: Timing) -> &mut Self {
+        let bits = match timing {
+            Timing::Ntsc => 0,
+            Timing::Pal => 1,
+            Timing::MultiRegion => 2,
+            Timing::Dendy => 3,
+        };
+        self.header.flags12 = (self.header.flags12 & !0b11) | bits;
+        if timing != Timing::Ntsc {
+            self.set_nes2();
+        }
+        self
+    }
+
+    /// Number of miscellaneous ROMs after the CHR data, a NES 2.0 only field
+    /// that holds at most 3. Larger counts are written as 3.
+    pub fn misc_roms(&mut self, count: u8) -> &mut Self {
+        self.header.flags14 = (self.header.flags14 & !0b11) | (count.min(3));
+        if count != 0 {
+            self.set_nes2();
+        }
+        self
+    }
+
+    pub fn build(&self) -> InesHeader {
+        self.header.clone()
+    }
+}
+
+impl Default for InesHeaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use binread::BinReaderExt;
+    use std::io::Cursor;
+
+    fn parse(bytes: &[u8; 16]) -> InesHeader {
+        Cursor::new(&bytes[..]).read_le().unwrap()
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let bytes = b"NES\x1a\x02\x01\x41\x58\x21\x10\x07\x79\x01\x00\x01\x02";
+        assert_eq!(parse(bytes).to_bytes().unwrap(), bytes);
+        assert_eq!(InesHeader::from_bytes(bytes), Some(parse(bytes)));
+        assert_eq!(InesHeader::from_bytes(&[0; 16]), None);
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let header = parse(b"NES\x1a\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        assert_eq!(header.prg_size, 1u8);
+        assert_eq!(header.chr_size, 0u8);
+        assert_eq!(header.mapper, 0u8);
+        assert_eq!(header.format(), HeaderFormat::Ines);
+        assert_eq!(header.nes2(), None);
+    }
+
+    #[test]
+    fn test_archaic_header() {
+        let header = parse(b"NES\x1a\x02\x01\x10\x44iskDude!");
+        assert_eq!(header.format(), HeaderFormat::Archaic);
+        assert_eq!(header.prg_rom_size(), Some(2 * 16384));
+        assert_eq!(header.chr_rom_size(), Some(8192));
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let header = parse(b"NES\x1a\x02\x01\x41\x58\x21\x10\x07\x79\x01\x00\x01\x02");
+        assert_eq!(header.format(), HeaderFormat::Nes2);
+        assert_eq!(header.prg_rom_size(), Some(2 * 16384));
+        assert_eq!(header.chr_rom_size(), Some(257 * 8192));
+
+        let nes2 = header.nes2().unwrap();
+        assert_eq!(nes2.mapper, 0x154);
+        assert_eq!(nes2.submapper, 2);
+        assert_eq!(nes2.prg_ram_size, 8192);
+        assert_eq!(nes2.prg_nvram_size, 0);
+        assert_eq!(nes2.chr_ram_size, 32768);
+        assert_eq!(nes2.chr_nvram_size, 8192);
+        assert_eq!(nes2.timing, Timing::Pal);
+        assert_eq!(nes2.vs_system, None);
+        assert_eq!(nes2.misc_roms, 1);
+        assert_eq!(nes2.expansion_device, 2);
+    }
+
+    #[test]
+    fn test_header_flags() {
+        let header = parse(b"NES\x1a\x02\x01\x47\x12\x00\x00\x00\x00\x00\x00\x00\x00");
+        assert_eq!(header.mirroring(), Mirroring::Vertical);
+        assert!(header.has_battery());
+        assert!(header.has_trainer());
+        assert_eq!(header.mapper_number(), 0x14);
+        assert_eq!(header.console_type(), ConsoleType::PlayChoice10);
+
+        let header = parse(b"NES\x1a\x02\x01\x18\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        assert_eq!(header.mirroring(), Mirroring::FourScreen);
+        assert!(!header.has_battery());
+        assert!(!header.has_trainer());
+        assert_eq!(header.console_type(), ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_archaic_mapper_number() {
+        let header = parse(b"NES\x1a\x02\x01\x10\x44iskDude!");
+        assert_eq!(header.mapper_number(), 1);
+        assert_eq!(header.console_type(), ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_header_builder() {
+        let header = InesHeader::builder()
+            .prg_size(2)
+            .chr_size(1)
+            .mirroring(Mirroring::Vertical)
+            .battery(true)
+            .mapper(4)
+            .build();
+        assert_eq!(header.format(), HeaderFormat::Ines);
+        assert_eq!(header.mirroring(), Mirroring::Vertical);
+        assert!(header.has_battery());
+        assert!(!header.has_trainer());
+        assert_eq!(header.mapper_number(), 4);
+        assert_eq!(header.prg_rom_size(), Some(2 * 16384));
+
+        let header = InesHeader::builder()
+            .mapper(0x154)
+            .submapper(2)
+            .mirroring(Mirroring::FourScreen)
+            .console_type(ConsoleType::VsSystem)
+            .build();
+        assert_eq!(header.format(), HeaderFormat::Nes2);
+        assert_eq!(header.mapper_number(), 0x154);
+        assert_eq!(header.nes2().unwrap().submapper, 2);
+        assert_eq!(header.mirroring(), Mirroring::FourScreen);
+        assert_eq!(header.console_type(), ConsoleType::VsSystem);
+
+        let header = InesHeader::builder().misc_roms(2).build();
+        assert_eq!(header.format(), HeaderFormat::Nes2);
+        assert_eq!(header.nes2().unwrap().misc_roms, 2);
+    }
+
+    #[test]
+    fn test_header_builder_sizes() {
+        let header = InesHeader::builder()
+            .prg_rom_size(32768)
+            .unwrap()
+            .chr_rom_size(0)
+            .unwrap()
+            .build();
+        assert_eq!(header.format(), HeaderFormat::Ines);
+        assert_eq!(header.prg_size, 2);
+
+        let header = InesHeader::builder()
+            .prg_rom_size(300 * 16384)
+            .unwrap()
+            .chr_rom_size(24)
+            .unwrap()
+            .timing(Timing::Dendy)
+            .build();
+        assert_eq!(header.format(), HeaderFormat::Nes2);
+        assert_eq!(header.prg_rom_size(), Some(300 * 16384));
+        assert_eq!(header.chr_rom_size(), Some(24));
+        assert_eq!(header.nes2().unwrap().timing, Timing::Dendy);
+
+        assert!(InesHeader::builder().prg_rom_size(9).is_err());
+    }
+
+    #[test]
+    fn test_header_builder_ram_sizes() {
+        let ines = InesHeader::builder().mapper(4).build();
+        let header = InesHeaderBuilder::from_header(&ines)
+            .prg_nvram_size(8192)
+            .unwrap()
+            .chr_ram_size(0)
+            .unwrap()
+            .build();
+        assert_eq!(header.mapper_number(), 4);
+
+        let nes2 = header.nes2().unwrap();
+        assert_eq!(nes2.prg_ram_size, 0);
+        assert_eq!(nes2.prg_nvram_size, 8192);
+        assert_eq!(nes2.chr_ram_size, 0);
+
+        assert!(InesHeader::builder().chr_ram_size(64).is_err());
+        assert!(InesHeader::builder().prg_ram_size(3000).is_err());
+    }
+
+    #[test]
+    fn test_nes2_exponent_size() {
+        let mut header = InesHeader {
+            prg_size: 0b0000_1101, // 2^3 * 3
+            chr_size: 0b0101_0000, // 2^20
+            flags7: 0b0000_1000,
+            flags9: 0xFF,
+            ..Default::default()
+        };
+        assert_eq!(header.prg_rom_size(), Some(24));
+        assert_eq!(header.chr_rom_size(), Some(1 << 20));
+
+        header.prg_size = 0xFF;
+        assert_eq!(header.prg_rom_size(), None);
+    }
+
+    #[test]
+    fn test_nes2_vs_system() {
+        let header = InesHeader {
+            flags7: 0b0000_1001,
+            flags13: 0x52,
+            ..Default::default()
+        };
+        let vs = header.nes2().unwrap().vs_system.unwrap();
+        assert_eq!(vs.ppu, VsPpu::RP2C04_0001);
+        assert_eq!(vs.hardware, VsHardware::DualSystem);
+    }
+}
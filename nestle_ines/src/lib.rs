@@ -1,5 +1,30 @@
+pub mod archive;
+pub mod bank;
+pub mod cartridge;
+pub mod chr;
+pub mod db;
 mod error;
+pub mod fds;
+mod header;
 pub mod model;
+pub mod nsf;
+pub mod patch;
+pub mod playchoice;
+mod rom;
+pub mod unif;
+pub mod validate;
+mod write;
 
-pub use crate::error::Result;
-pub use crate::model::Ines;
+pub use crate::archive::ArchiveFormat;
+pub use crate::bank::{Banks, ChrBankSize, PrgBankSize};
+pub use crate::cartridge::CartridgeBuilder;
+pub use crate::chr::{Palette, TileSheet};
+pub use crate::db::{RomDb, RomHashes};
+pub use crate::error::{InesError, Result, Section};
+pub use crate::fds::FdsImage;
+pub use crate::model::{Ines, InesRef, Trailing};
+pub use crate::nsf::Nsf;
+pub use crate::playchoice::PlayChoiceProm;
+pub use crate::rom::{load, Format, LoadOptions, Rom};
+pub use crate::unif::Unif;
+pub use crate::write::BinWrite;
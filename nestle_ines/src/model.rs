@@ -1,90 +1,388 @@
-use anyhow::{anyhow, Context, Result};
-use binread::{BinRead, BinReaderExt};
-use std::fs::File;
+use binread::io::{Read, Seek, SeekFrom};
+use binread::{BinRead, BinResult, ReadOptions};
+use std::io::{self, Write};
 use std::path::Path;
 
-#[derive(Debug, BinRead)]
+use crate::archive;
+use crate::error::{InesError, Result, Section};
+use crate::write::{invalid_data, BinWrite};
+
+pub use crate::header::{
+    ConsoleType, HeaderFormat, InesHeader, InesHeaderBuilder, Mirroring, Nes2Header, Timing,
+    VsHardware, VsPpu, VsSystem,
+};
+
+#[derive(Debug, Default)]
 pub struct Ines {
     pub header: InesHeader,
 
-    #[br(if(header.mapper & 0b00100000 != 0), count=512)]
     pub trainer: Option<Vec<u8>>,
 
-    #[br(count = (header.prg_size as u32) * 16384)]
     pub prg: Vec<u8>,
 
-    #[br(if(header.chr_size != 0), count = (header.chr_size as u32) * 8192)]
     pub chr: Option<Vec<u8>>,
-    // PlayChoice fields ..
+
+    /// PlayChoice-10 INST-ROM, 8 KiB
+    pub inst_rom: Option<Vec<u8>>,
+
+    /// PlayChoice-10 PROM, 16 bytes of data followed by 16 bytes of CounterOut
+    pub prom: Option<Vec<u8>>,
+
+    /// Everything after the last section the header declares: the NES 2.0
+    /// miscellaneous ROM area, or data the header doesn't account for.
+    /// See [`Ines::trailing`].
+    pub misc: Option<Vec<u8>>,
 }
 
-impl Ines {
-    pub fn from_path(path: &Path) -> Result<Self> {
-        let mut reader = File::open(path)?;
-        reader
-            .read_le()
-            .map_err(|e| anyhow!("unable to load ROM from '{}': {}", path.display(), e))
+/// Borrowed view of an iNES/NES 2.0 image whose sections are slices into the
+/// input, for loading ROMs without copying them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InesRef<'a> {
+    pub header: InesHeader,
+    pub trainer: Option<&'a [u8]>,
+    pub prg: &'a [u8],
+    pub chr: Option<&'a [u8]>,
+    pub inst_rom: Option<&'a [u8]>,
+    pub prom: Option<&'a [u8]>,
+    pub misc: Option<&'a [u8]>,
+}
+
+/// The bytes following the last section an iNES/NES 2.0 header declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trailing<'a> {
+    /// NES 2.0 miscellaneous ROM area holding `count` ROMs. How it splits
+    /// into ROMs is up to the mapper, except that a single ROM spans all of it.
+    MiscRoms { count: u8, data: &'a [u8] },
+    /// Data the header has no field for, usually padding or junk left by a
+    /// dumping tool
+    Unknown(&'a [u8]),
+}
+
+impl<'a> Trailing<'a> {
+    pub fn data(&self) -> &'a [u8] {
+        match *self {
+            Trailing::MiscRoms { data, .. } | Trailing::Unknown(data) => data,
+        }
     }
+}
 
-    #[cfg(test)]
-    fn dump(&self) -> Vec<u8> {
-        let mut result = Vec::new();
-        result.extend(&self.header.dump());
+pub(crate) const HEADER_SIZE: usize = 16;
+pub(crate) const TRAINER_SIZE: usize = 512;
+pub(crate) const INST_ROM_SIZE: usize = 8192;
+pub(crate) const PROM_SIZE: usize = 32;
 
-        if let Some(trainer) = self.trainer.as_ref() {
-            result.extend(trainer);
+fn playchoice_len(header: &InesHeader, len: usize) -> usize {
+    match header.console_type() {
+        ConsoleType::PlayChoice10 => len,
+        _ => 0,
+    }
+}
+
+pub(crate) fn position<R: Seek>(reader: &mut R) -> Result<u64> {
+    Ok(reader.seek(SeekFrom::Current(0))?)
+}
+
+/// Reads everything left in `reader`.
+pub(crate) fn read_to_end<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        match reader.read(&mut chunk)? {
+            0 => return Ok(bytes),
+            n => bytes.extend_from_slice(&chunk[..n]),
         }
+    }
+}
 
-        result.extend(&self.prg);
+/// Cursor over an in-memory image that reports truncation per section.
+/// `base` is the offset of `bytes` within the original stream.
+pub(crate) struct Sections<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    base: u64,
+}
 
-        if let Some(chr) = self.chr.as_ref() {
-            result.extend(chr);
+impl<'a> Sections<'a> {
+    pub(crate) fn new(bytes: &'a [u8], base: u64) -> Self {
+        Sections {
+            bytes,
+            pos: 0,
+            base,
         }
+    }
 
-        result
+    pub(crate) fn offset(&self) -> u64 {
+        self.base + self.pos as u64
+    }
+
+    pub(crate) fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    /// Takes exactly `len` bytes of `section`.
+    pub(crate) fn take(&mut self, section: Section, len: usize) -> Result<&'a [u8]> {
+        let remaining = self.remaining();
+        if remaining.len() < len {
+            return Err(InesError::Truncated {
+                section,
+                offset: self.offset(),
+                expected: len,
+                actual: remaining.len(),
+            });
+        }
+
+        self.pos += len;
+        Ok(&remaining[..len])
+    }
+
+    /// Takes `len` bytes of `section` unless the input has already ended.
+    pub(crate) fn take_optional(
+        &mut self,
+        section: Section,
+        len: usize,
+    ) -> Result<Option<&'a [u8]>> {
+        if len == 0 || self.remaining().is_empty() {
+            Ok(None)
+        } else {
+            self.take(section, len).map(Some)
+        }
+    }
+}
+
+fn check_len(section: Section, actual: usize, expected: usize) -> io::Result<()> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "{} is {} bytes but the header declares {}",
+            section, actual, expected
+        )))
     }
 }
 
-impl Default for Ines {
-    fn default() -> Self {
+fn parse_header(sections: &mut Sections) -> Result<InesHeader> {
+    let offset = sections.offset();
+    let mut raw = [0u8; HEADER_SIZE];
+    raw.copy_from_slice(sections.take(Section::Header, HEADER_SIZE)?);
+
+    let header = InesHeader::from_bytes(&raw).ok_or_else(|| {
+        let mut found = [0u8; 4];
+        found.copy_from_slice(&raw[..4]);
+        InesError::BadMagic { offset, found }
+    })?;
+    Ok(header)
+}
+
+impl<'a> InesRef<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        Self::parse_at(bytes, 0)
+    }
+
+    pub(crate) fn parse_at(bytes: &'a [u8], base: u64) -> Result<Self> {
+        let mut sections = Sections::new(bytes, base);
+        let header = parse_header(&mut sections)?;
+        let prg_size = header.prg_rom_size().ok_or(InesError::InvalidSize {
+            section: Section::PrgRom,
+            lsb: header.prg_size,
+            msb: header.flags9 & 0x0F,
+        })?;
+        let chr_size = header.chr_rom_size().ok_or(InesError::InvalidSize {
+            section: Section::ChrRom,
+            lsb: header.chr_size,
+            msb: header.flags9 >> 4,
+        })?;
+
+        let trainer = if header.has_trainer() {
+            Some(sections.take(Section::Trainer, TRAINER_SIZE)?)
+        } else {
+            None
+        };
+
+        let prg = sections.take(Section::PrgRom, prg_size)?;
+
+        let chr = if chr_size != 0 {
+            Some(sections.take(Section::ChrRom, chr_size)?)
+        } else {
+            None
+        };
+
+        // PlayChoice-10 dumps frequently leave out the INST-ROM or PROM even
+        // though flags 7 announces them.
+        let inst_rom =
+            sections.take_optional(Section::InstRom, playchoice_len(&header, INST_ROM_SIZE))?;
+        let prom = if inst_rom.is_some() {
+            sections.take_optional(Section::Prom, PROM_SIZE)?
+        } else {
+            None
+        };
+
+        let misc = match sections.remaining() {
+            [] => None,
+            rest => Some(rest),
+        };
+
+        Ok(InesRef {
+            header,
+            trainer,
+            prg,
+            chr,
+            inst_rom,
+            prom,
+            misc,
+        })
+    }
+
+    /// Classifies the data after the last declared section, if there's any.
+    pub fn trailing(&self) -> Option<Trailing<'a>> {
+        let data = self.misc?;
+        match self.header.nes2() {
+            Some(nes2) if nes2.misc_roms > 0 => Some(Trailing::MiscRoms {
+                count: nes2.misc_roms,
+                data,
+            }),
+            _ => Some(Trailing::Unknown(data)),
+        }
+    }
+
+    pub fn to_ines(&self) -> Ines {
         Ines {
-            header: Default::default(),
-            trainer: None,
-            prg: Vec::new(),
-            chr: None,
+            header: self.header.clone(),
+            trainer: self.trainer.map(<[u8]>::to_vec),
+            prg: self.prg.to_vec(),
+            chr: self.chr.map(<[u8]>::to_vec),
+            inst_rom: self.inst_rom.map(<[u8]>::to_vec),
+            prom: self.prom.map(<[u8]>::to_vec),
+            misc: self.misc.map(<[u8]>::to_vec),
         }
     }
 }
 
-#[derive(Debug, BinRead)]
-#[br(magic = b"NES\x1a")]
-pub struct InesHeader {
-    pub prg_size: u8, // 4
-    pub chr_size: u8, // 5
-    pub mapper: u8,   // 6
-    pub extra: [u8; 9],
+impl<'a> From<InesRef<'a>> for Ines {
+    fn from(image: InesRef<'a>) -> Self {
+        image.to_ines()
+    }
 }
 
-impl InesHeader {
-    #[cfg(test)]
-    fn dump(&self) -> Vec<u8> {
-        [
-            &b"NES\x1a"[..],
-            &[self.prg_size, self.chr_size, self.mapper][..],
-            &self.extra[..],
-        ]
-        .concat()
+impl Ines {
+    /// Loads an image, unpacking it first if it's zipped or gzipped.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        archive::load(path, None, Self::from_bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        InesRef::parse(bytes).map(Ines::from)
+    }
+
+    pub fn from_reader<R: io::Read + io::Seek>(mut reader: R) -> Result<Self> {
+        Self::parse(&mut reader)
+    }
+
+    pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let base = position(reader)?;
+        let bytes = read_to_end(reader)?;
+        InesRef::parse_at(&bytes, base).map(Ines::from)
+    }
+
+    /// Borrows the image as an [`InesRef`].
+    pub fn view(&self) -> InesRef<'_> {
+        InesRef {
+            header: self.header.clone(),
+            trainer: self.trainer.as_deref(),
+            prg: &self.prg,
+            chr: self.chr.as_deref(),
+            inst_rom: self.inst_rom.as_deref(),
+            prom: self.prom.as_deref(),
+            misc: self.misc.as_deref(),
+        }
+    }
+
+    /// Classifies [`Ines::misc`] according to the header.
+    pub fn trailing(&self) -> Option<Trailing<'_>> {
+        self.view().trailing()
     }
 }
 
-impl Default for InesHeader {
-    fn default() -> Self {
-        InesHeader {
-            prg_size: 1,
-            chr_size: 0,
-            mapper: 0,
-            extra: Default::default(),
+impl BinRead for Ines {
+    type Args = ();
+
+    fn read_options<R: Read + Seek>(
+        reader: &mut R,
+        _: &ReadOptions,
+        _: Self::Args,
+    ) -> BinResult<Self> {
+        let pos = reader.seek(SeekFrom::Current(0))?;
+        Self::parse(reader).map_err(|err| match err {
+            InesError::IOError(err) => binread::Error::Io(err),
+            err => binread::Error::Custom {
+                pos: pos as usize,
+                err: Box::new(err),
+            },
+        })
+    }
+}
+
+impl BinWrite for InesRef<'_> {
+    /// Writes the image as an iNES/NES 2.0 file, refusing to produce one whose
+    /// sections disagree with the header.
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let header = &self.header;
+        let prg_size = header
+            .prg_rom_size()
+            .ok_or_else(|| invalid_data("invalid NES 2.0 PRG-ROM size".into()))?;
+        let chr_size = header
+            .chr_rom_size()
+            .ok_or_else(|| invalid_data("invalid NES 2.0 CHR-ROM size".into()))?;
+
+        let trainer_len = self.trainer.map_or(0, <[u8]>::len);
+        let trainer_size = if header.has_trainer() {
+            TRAINER_SIZE
+        } else {
+            0
+        };
+        check_len(Section::Trainer, trainer_len, trainer_size)?;
+        check_len(Section::PrgRom, self.prg.len(), prg_size)?;
+        check_len(Section::ChrRom, self.chr.map_or(0, <[u8]>::len), chr_size)?;
+
+        if self.inst_rom.is_none() && self.prom.is_some() {
+            return Err(invalid_data("PROM requires an INST-ROM".into()));
+        }
+        if let Some(inst_rom) = self.inst_rom {
+            check_len(
+                Section::InstRom,
+                inst_rom.len(),
+                playchoice_len(header, INST_ROM_SIZE),
+            )?;
+        }
+        if let Some(prom) = self.prom {
+            check_len(Section::Prom, prom.len(), PROM_SIZE)?;
+        }
+        // data after a PlayChoice-10 image without PROM would read back as PROM
+        if self.misc.is_some() && playchoice_len(header, 1) != 0 && self.prom.is_none() {
+            return Err(invalid_data(
+                "trailing data requires the PlayChoice-10 INST-ROM and PROM".into(),
+            ));
+        }
+
+        header.write_to(writer)?;
+        let sections = [
+            self.trainer,
+            Some(self.prg),
+            self.chr,
+            self.inst_rom,
+            self.prom,
+            self.misc,
+        ];
+        for section in sections.iter().flatten() {
+            writer.write_all(section)?;
         }
+        Ok(())
+    }
+}
+
+impl BinWrite for Ines {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.view().write_to(writer)
     }
 }
 
@@ -95,19 +393,14 @@ mod tests {
     use binread::BinReaderExt;
     use std::io::Cursor;
 
-    fn with_header(body: &[u8]) -> Vec<u8> {
-        let mut result = InesHeader::default().dump();
+    fn with_header_of(header: &InesHeader, body: &[u8]) -> Vec<u8> {
+        let mut result = header.to_bytes().unwrap();
         result.extend_from_slice(body);
         result
     }
 
-    #[test]
-    fn test_ines_header() {
-        let mut reader = Cursor::new(b"NES\x1a\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
-        let header: InesHeader = reader.read_le().unwrap();
-        assert_eq!(header.prg_size, 1u8);
-        assert_eq!(header.chr_size, 0u8);
-        assert_eq!(header.mapper, 0u8);
+    fn with_header(body: &[u8]) -> Vec<u8> {
+        with_header_of(&InesHeader::default(), body)
     }
 
     #[test]
@@ -119,11 +412,29 @@ mod tests {
     }
 
     #[test]
-    fn test_ines_parse_trainer() {
+    fn test_ines_parse_nes2() {
         let header = InesHeader {
-            mapper: 0b0010_0000,
+            prg_size: 0b0000_1101, // 2^3 * 3
+            chr_size: 1,
+            flags7: 0b0000_1000,
+            flags9: 0x0F,
             ..Default::default()
         };
+        let prg = b"\x01".repeat(24);
+        let chr = b"\x02".repeat(8192);
+
+        let mut bytes = header.to_bytes().unwrap();
+        bytes.extend(&prg);
+        bytes.extend(&chr);
+        let image: Ines = Cursor::new(bytes).read_le().unwrap();
+
+        assert_eq!(image.prg, prg);
+        assert_eq!(image.chr, Some(chr));
+    }
+
+    #[test]
+    fn test_ines_parse_trainer() {
+        let header = InesHeader::builder().trainer(true).build();
         let trainer = b"trainer\x01".repeat(64);
         let prg = b"\x01".repeat(16384);
 
@@ -133,7 +444,7 @@ mod tests {
             prg,
             ..Default::default()
         };
-        let mut reader = Cursor::new(ines.dump());
+        let mut reader = Cursor::new(ines.to_bytes().unwrap());
         let image: Ines = reader.read_le().unwrap();
 
         assert!(image.trainer.is_some());
@@ -157,7 +468,7 @@ mod tests {
             chr: Some(chr.clone()),
             ..Default::default()
         };
-        let mut reader = Cursor::new(ines.dump());
+        let mut reader = Cursor::new(ines.to_bytes().unwrap());
         let image: Ines = reader.read_le().unwrap();
 
         assert!(image.chr.is_some());
@@ -165,4 +476,181 @@ mod tests {
         assert_eq!(img_chr.len(), 8192 * 2);
         assert_eq!(img_chr, chr);
     }
+
+    #[test]
+    fn test_ines_parse_playchoice() {
+        let header = InesHeader::builder()
+            .console_type(ConsoleType::PlayChoice10)
+            .build();
+        let prg = b"\x01".repeat(16384);
+        let inst_rom = b"instrom\x03".repeat(1024);
+        let prom = b"\x04".repeat(32);
+
+        let ines = Ines {
+            header: header.clone(),
+            prg: prg.clone(),
+            inst_rom: Some(inst_rom.clone()),
+            prom: Some(prom.clone()),
+            ..Default::default()
+        };
+        let bytes = ines.to_bytes().unwrap();
+        let image: Ines = Cursor::new(&bytes).read_le().unwrap();
+
+        assert_eq!(image.inst_rom, Some(inst_rom.clone()));
+        assert_eq!(image.prom, Some(prom));
+        assert_eq!(image.to_bytes().unwrap(), bytes);
+
+        // INST-ROM and PROM are optional even when flags 7 announces them
+        let image: Ines = Cursor::new(with_header_of(&header, &prg))
+            .read_le()
+            .unwrap();
+        assert_eq!(image.inst_rom, None);
+        assert_eq!(image.prom, None);
+
+        // without PROM, trailing data couldn't be told apart from it
+        let ines = Ines {
+            header,
+            prg,
+            inst_rom: Some(inst_rom),
+            misc: Some(b"\x05".repeat(32)),
+            ..Default::default()
+        };
+        assert!(ines.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_ines_parse_trailing() {
+        let prg = b"\x01".repeat(16384);
+        let mut bytes = with_header(&prg);
+        bytes.extend_from_slice(b"junk");
+
+        let image = Ines::from_bytes(&bytes).unwrap();
+        assert_eq!(image.misc.as_deref(), Some(&b"junk"[..]));
+        assert_eq!(image.trailing(), Some(Trailing::Unknown(b"junk")));
+        assert_eq!(image.to_bytes().unwrap(), bytes);
+
+        let header = InesHeader::builder().misc_roms(1).build();
+        let misc = b"\x05".repeat(256);
+        let ines = Ines {
+            header,
+            prg,
+            misc: Some(misc.clone()),
+            ..Default::default()
+        };
+        let bytes = ines.to_bytes().unwrap();
+        let image = InesRef::parse(&bytes).unwrap();
+        assert_eq!(
+            image.trailing(),
+            Some(Trailing::MiscRoms {
+                count: 1,
+                data: &misc
+            })
+        );
+        assert_eq!(image.to_bytes().unwrap(), bytes);
+
+        let image = Ines::from_bytes(&with_header(&b"\x01".repeat(16384))).unwrap();
+        assert_eq!(image.trailing(), None);
+    }
+
+    #[test]
+    fn test_ines_write_mismatch() {
+        let ines = Ines {
+            prg: b"\x01".repeat(100),
+            ..Default::default()
+        };
+        let mut bytes = Vec::new();
+        assert!(ines.write_to(&mut bytes).is_err());
+        let err = ines.to_bytes().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let ines = Ines {
+            header: InesHeader::builder().trainer(true).build(),
+            prg: b"\x01".repeat(16384),
+            ..Default::default()
+        };
+        assert!(ines.write_to(&mut bytes).is_err());
+    }
+
+    fn parse_err(bytes: Vec<u8>) -> InesError {
+        Ines::from_bytes(&bytes).unwrap_err()
+    }
+
+    #[test]
+    fn test_ines_errors() {
+        match parse_err(b"NSF\x1a".repeat(4)) {
+            InesError::BadMagic { offset: 0, found } => assert_eq!(&found, b"NSF\x1a"),
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        match parse_err(with_header(&[0; 100])) {
+            InesError::Truncated {
+                section: Section::PrgRom,
+                offset: 16,
+                expected: 16384,
+                actual: 100,
+            } => (),
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        let header = InesHeader {
+            prg_size: 0xFF,
+            flags7: 0b0000_1000,
+            flags9: 0x0F,
+            ..Default::default()
+        };
+        match parse_err(header.to_bytes().unwrap()) {
+            InesError::InvalidSize {
+                section: Section::PrgRom,
+                lsb: 0xFF,
+                msb: 0x0F,
+            } => (),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_archaic_header() {
+        // Bits 2-3 of flags 7 set to 3 mean bytes 7-15 are garbage
+        let header = InesHeader {
+            prg_size: 1,
+            flags7: 0b0000_1100,
+            flags9: 0xFF,
+            ..Default::default()
+        };
+        let ines = Ines::from_bytes(&with_header_of(&header, &[0; 16384])).unwrap();
+        assert_eq!(ines.header.format(), HeaderFormat::Archaic);
+        assert_eq!(ines.prg.len(), 16384);
+    }
+
+    #[test]
+    fn test_ines_ref() {
+        let header = InesHeader::builder().chr_size(1).build();
+        let ines = Ines {
+            header,
+            prg: b"\x01".repeat(16384),
+            chr: Some(b"\x02".repeat(8192)),
+            ..Default::default()
+        };
+        let bytes = ines.to_bytes().unwrap();
+
+        let image = InesRef::parse(&bytes).unwrap();
+        assert_eq!(image.prg.as_ptr(), bytes[16..].as_ptr());
+        assert_eq!(image.chr, ines.chr.as_deref());
+        assert_eq!(image, ines.view());
+        assert_eq!(image.to_bytes().unwrap(), bytes);
+
+        let image = Ines::from_bytes(&bytes).unwrap();
+        assert_eq!(image.prg, ines.prg);
+
+        let image = Ines::from_reader(Cursor::new(&bytes)).unwrap();
+        assert_eq!(image.chr, ines.chr);
+    }
+
+    #[test]
+    fn test_ines_from_path_error() {
+        let path = Path::new("does/not/exist.nes");
+        let err = Ines::from_path(path).unwrap_err();
+        assert_eq!(err.path(), Some(path));
+        assert!(err.to_string().contains("does/not/exist.nes"));
+    }
 }
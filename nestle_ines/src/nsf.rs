@@ -0,0 +1,517 @@
+use std::fs;
+use std::path::Path;
+
+use bitflags::bitflags;
+
+use crate::error::{InesError, Result, Section};
+use crate::model::{Sections, Timing};
+use crate::unif::chunk_string;
+
+const HEADER_SIZE: usize = 128;
+const CHUNK_HEADER_SIZE: usize = 8;
+const INFO_SIZE: usize = 9;
+
+/// Play routine periods NSFe files use when there's no `RATE` chunk.
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+bitflags! {
+    /// Expansion audio chips a tune writes to.
+    pub struct Expansion: u8 {
+        const VRC6 = 0b0000_0001;
+        const VRC7 = 0b0000_0010;
+        const FDS = 0b0000_0100;
+        const MMC5 = 0b0000_1000;
+        const N163 = 0b0001_0000;
+        const SUNSOFT_5B = 0b0010_0000;
+        const VT02 = 0b0100_0000;
+    }
+}
+
+impl Default for Expansion {
+    fn default() -> Self {
+        Expansion::empty()
+    }
+}
+
+/// Per-track metadata from NSFe chunks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NsfTrack {
+    /// `tlbl`
+    pub title: Option<String>,
+    /// `taut`
+    pub author: Option<String>,
+    /// `time`, in milliseconds
+    pub time: Option<u32>,
+    /// `fade`, in milliseconds
+    pub fade: Option<u32>,
+}
+
+/// A chunk this parser doesn't interpret, kept in file order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NsfeChunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// An NSF, NSF2 or NSFe music file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsf {
+    /// Header version, 0 for NSFe files
+    pub version: u8,
+    pub songs: u8,
+    /// 1-based index of the song to play first
+    pub starting_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Only NSFe's `auth` chunk records the ripper
+    pub ripper: Option<String>,
+    /// Play routine period on NTSC, in microseconds
+    pub ntsc_speed: u16,
+    /// Play routine period on PAL, in microseconds
+    pub pal_speed: u16,
+    /// Regions the tune supports, `MultiRegion` when both are
+    pub timing: Timing,
+    /// Initial values for the $5FF8-$5FFF bank registers, `None` when
+    /// the tune isn't bankswitched
+    pub bankswitch: Option<[u8; 8]>,
+    pub expansion: Expansion,
+    /// NSF2 feature flags
+    pub flags: u8,
+    /// One entry per song
+    pub tracks: Vec<NsfTrack>,
+    /// `plst`, 0-based song numbers in play order
+    pub playlist: Option<Vec<u8>>,
+    pub data: Vec<u8>,
+    pub chunks: Vec<NsfeChunk>,
+}
+
+impl Default for Nsf {
+    fn default() -> Self {
+        Nsf {
+            version: 0,
+            songs: 1,
+            starting_song: 1,
+            load_address: 0,
+            init_address: 0,
+            play_address: 0,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: None,
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            timing: Timing::Ntsc,
+            bankswitch: None,
+            expansion: Expansion::empty(),
+            flags: 0,
+            tracks: Vec::new(),
+            playlist: None,
+            data: Vec::new(),
+            chunks: Vec::new(),
+        }
+    }
+}
+
+fn le16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn timing(region: u8) -> Timing {
+    if region & 0b10 != 0 {
+        Timing::MultiRegion
+    } else if region & 0b01 != 0 {
+        Timing::Pal
+    } else {
+        Timing::Ntsc
+    }
+}
+
+fn bankswitch(data: &[u8]) -> Option<[u8; 8]> {
+    let mut banks = [0u8; 8];
+    let len = data.len().min(banks.len());
+    banks[..len].copy_from_slice(&data[..len]);
+    if banks.iter().any(|&bank| bank != 0) {
+        Some(banks)
+    } else {
+        None
+    }
+}
+
+/// NUL-separated string list, as used by `auth`, `tlbl` and `taut`.
+fn strings(data: &[u8]) -> Vec<String> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    data.strip_suffix(&[0])
+        .unwrap_or(data)
+        .split(|&b| b == 0)
+        .map(chunk_string)
+        .collect()
+}
+
+/// Track lengths in milliseconds, negative values meaning "use the default".
+fn durations(data: &[u8]) -> impl Iterator<Item = Option<u32>> + '_ {
+    data.chunks_exact(4).map(|ms| {
+        let ms = i32::from_le_bytes([ms[0], ms[1], ms[2], ms[3]]);
+        if ms < 0 {
+            None
+        } else {
+            Some(ms as u32)
+        }
+    })
+}
+
+impl Nsf {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        fs::read(path)
+            .map_err(InesError::from)
+            .and_then(|bytes| Self::from_bytes(&bytes))
+            .map_err(|e| e.with_path(path))
+    }
+
+    /// Parses either a plain NSF (with NSF2 metadata) or an NSFe file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(b"NSFE") {
+            Self::parse_nsfe(bytes)
+        } else {
+            Self::parse_nsf(bytes)
+        }
+    }
+
+    fn parse_nsf(bytes: &[u8]) -> Result<Self> {
+        let mut sections = Sections::new(bytes, 0);
+        let header = sections.take(Section::Header, HEADER_SIZE)?;
+        if &header[..5] != b"NESM\x1a" {
+            let mut found = [0u8; 4];
+            found.copy_from_slice(&header[..4]);
+            return Err(InesError::BadMagic { offset: 0, found });
+        }
+
+        let mut nsf = Nsf {
+            version: header[5],
+            songs: header[6],
+            starting_song: header[7],
+            load_address: le16(header, 0x08),
+            init_address: le16(header, 0x0A),
+            play_address: le16(header, 0x0C),
+            title: chunk_string(&header[0x0E..0x2E]),
+            artist: chunk_string(&header[0x2E..0x4E]),
+            copyright: chunk_string(&header[0x4E..0x6E]),
+            ntsc_speed: le16(header, 0x6E),
+            bankswitch: bankswitch(&header[0x70..0x78]),
+            pal_speed: le16(header, 0x78),
+            timing: timing(header[0x7A]),
+            expansion: Expansion::from_bits_truncate(header[0x7B]),
+            ..Default::default()
+        };
+
+        // NSF2 gives the program length so metadata chunks can follow it
+        let len = u32::from_le_bytes([header[0x7D], header[0x7E], header[0x7F], 0]) as usize;
+        if nsf.version >= 2 && len != 0 {
+            nsf.flags = header[0x7C];
+            nsf.data = sections.take(Section::PrgRom, len)?.to_vec();
+            nsf.parse_chunks(&mut sections)?;
+        } else {
+            nsf.data = sections.remaining().to_vec();
+        }
+
+        nsf.tracks.resize(nsf.songs.into(), NsfTrack::default());
+        Ok(nsf)
+    }
+
+    fn parse_nsfe(bytes: &[u8]) -> Result<Self> {
+        let mut sections = Sections::new(bytes, 0);
+        sections.take(Section::Header, 4)?;
+
+        let mut nsf = Nsf::default();
+        let seen = nsf.parse_chunks(&mut sections)?;
+        for id in &[*b"INFO", *b"DATA"] {
+            if !seen.contains(id) {
+                return Err(InesError::MissingChunk(*id));
+            }
+        }
+
+        nsf.tracks.resize(nsf.songs.into(), NsfTrack::default());
+        Ok(nsf)
+    }
+
+    /// Reads NSFe chunks up to `NEND` or the end of input, returning the
+    /// IDs that were seen.
+    fn parse_chunks(&mut self, sections: &mut Sections<'_>) -> Result<Vec<[u8; 4]>> {
+        let mut seen = Vec::new();
+
+        while !sections.remaining().is_empty() {
+            let chunk = sections.take(Section::Header, CHUNK_HEADER_SIZE)?;
+            let len = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+            let mut id = [0u8; 4];
+            id.copy_from_slice(&chunk[4..]);
+            let offset = sections.offset();
+            let data = sections.take(Section::Chunk(id), len)?;
+            seen.push(id);
+
+            match &id {
+                b"INFO" => {
+                    let info = Sections::new(data, offset).take(Section::Chunk(id), INFO_SIZE)?;
+                    self.load_address = le16(info, 0);
+                    self.init_address = le16(info, 2);
+                    self.play_address = le16(info, 4);
+                    self.timing = timing(info[6]);
+                    self.expansion = Expansion::from_bits_truncate(info[7]);
+                    self.songs = data.get(8).copied().unwrap_or(1);
+                    // stored zero-based, so 255 has no one-based song to name
+                    self.starting_song = data.get(9).copied().unwrap_or(0).saturating_add(1);
+                }
+                b"DATA" => self.data = data.to_vec(),
+                b"BANK" => self.bankswitch = bankswitch(data),
+                b"RATE" => {
+                    if data.len() >= 2 {
+                        self.ntsc_speed = le16(data, 0);
+                    }
+                    if data.len() >= 4 {
+                        self.pal_speed = le16(data, 2);
+                    }
+                }
+                b"NEND" => break,
+                b"auth" => {
+                    let mut fields = strings(data).into_iter();
+                    self.title = fields.next().unwrap_or_default();
+                    self.artist = fields.next().unwrap_or_default();
+                    self.copyright = fields.next().unwrap_or_default();
+                    self.ripper = fields.next();
+                }
+                b"tlbl" => {
+                    let titles = strings(data);
+                    for (track, title) in self.tracks_mut(titles.len()).iter_mut().zip(titles) {
+                        track.title = Some(title);
+                    }
+                }
+                b"taut" => {
+                    let authors = strings(data);
+                    for (track, author) in self.tracks_mut(authors.len()).iter_mut().zip(authors) {
+                        track.author = Some(author);
+                    }
+                }
+                b"time" => {
+                    for (track, time) in self
+                        .tracks_mut(data.len() / 4)
+                        .iter_mut()
+                        .zip(durations(data))
+                    {
+                        track.time = time;
+                    }
+                }
+                b"fade" => {
+                    for (track, fade) in self
+                        .tracks_mut(data.len() / 4)
+                        .iter_mut()
+                        .zip(durations(data))
+                    {
+                        track.fade = fade;
+                    }
+                }
+                b"plst" => self.playlist = Some(data.to_vec()),
+                // chunks starting with an uppercase letter must be understood
+                _ if id[0].is_ascii_uppercase() => return Err(InesError::UnsupportedChunk(id)),
+                _ => self.chunks.push(NsfeChunk {
+                    id,
+                    data: data.to_vec(),
+                }),
+            }
+        }
+
+        Ok(seen)
+    }
+
+    /// Track list grown to hold at least `len` entries, since metadata may
+    /// arrive before the song count is final.
+    fn tracks_mut(&mut self, len: usize) -> &mut [NsfTrack] {
+        let len = len.max(self.songs.into());
+        if self.tracks.len() < len {
+            self.tracks.resize(len, NsfTrack::default());
+        }
+        &mut self.tracks
+    }
+
+    /// Whether the tune uses the $5FF8-$5FFF bank registers.
+    pub fn is_bankswitched(&self) -> bool {
+        self.bankswitch.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        let mut header = vec![0u8; HEADER_SIZE];
+        header[..5].copy_from_slice(b"NESM\x1a");
+        header[5] = 1;
+        header[6] = 3;
+        header[7] = 2;
+        header[0x08..0x0E].copy_from_slice(&[0x00, 0x80, 0x03, 0x80, 0x06, 0x80]);
+        header[0x0E..0x13].copy_from_slice(b"Title");
+        header[0x2E..0x34].copy_from_slice(b"Artist");
+        header[0x4E..0x52].copy_from_slice(b"1986");
+        header[0x6E..0x70].copy_from_slice(&DEFAULT_NTSC_SPEED.to_le_bytes());
+        header[0x78..0x7A].copy_from_slice(&DEFAULT_PAL_SPEED.to_le_bytes());
+        header[0x7A] = 0b10;
+        header[0x7B] = 0b0010_0001;
+        header
+    }
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut result = (data.len() as u32).to_le_bytes().to_vec();
+        result.extend(id);
+        result.extend(data);
+        result
+    }
+
+    #[test]
+    fn test_nsf_parse() {
+        let mut bytes = header();
+        bytes.extend(&[0x60; 256]);
+        let nsf = Nsf::from_bytes(&bytes).unwrap();
+
+        assert_eq!(nsf.version, 1);
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 2);
+        assert_eq!(nsf.load_address, 0x8000);
+        assert_eq!(nsf.init_address, 0x8003);
+        assert_eq!(nsf.play_address, 0x8006);
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.copyright, "1986");
+        assert_eq!(nsf.timing, Timing::MultiRegion);
+        assert_eq!(nsf.expansion, Expansion::VRC6 | Expansion::SUNSOFT_5B);
+        assert!(!nsf.is_bankswitched());
+        assert_eq!(nsf.tracks.len(), 3);
+        assert_eq!(nsf.data.len(), 256);
+
+        bytes[0x70..0x78].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        let nsf = Nsf::from_bytes(&bytes).unwrap();
+        assert_eq!(nsf.bankswitch, Some([0, 1, 2, 3, 4, 5, 6, 7]));
+    }
+
+    #[test]
+    fn test_nsf2_metadata() {
+        let mut bytes = header();
+        bytes[5] = 2;
+        bytes[0x7D] = 16;
+        bytes.extend(&[0x60; 16]);
+        bytes.extend(chunk(b"tlbl", b"One\0Two\0Three\0"));
+        bytes.extend(chunk(b"NEND", &[]));
+
+        let nsf = Nsf::from_bytes(&bytes).unwrap();
+        assert_eq!(nsf.data, vec![0x60; 16]);
+        assert_eq!(nsf.tracks[1].title.as_deref(), Some("Two"));
+    }
+
+    #[test]
+    fn test_nsfe_starting_song() {
+        let mut bytes = b"NSFE".to_vec();
+        bytes.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x00, 0x00, 2, 0xFF],
+        ));
+        bytes.extend(chunk(b"DATA", &[0x60]));
+
+        let nsf = Nsf::from_bytes(&bytes).unwrap();
+        assert_eq!(nsf.starting_song, 0xFF);
+    }
+
+    #[test]
+    fn test_track_count() {
+        // long names mustn't make a track per byte
+        let mut bytes = chunk(b"tlbl", &[b"A".repeat(150), b"B".repeat(149)].join(&0));
+        bytes.extend(chunk(b"taut", &b"C".repeat(300)));
+        let mut nsf = Nsf::default();
+        nsf.parse_chunks(&mut Sections::new(&bytes, 0)).unwrap();
+        assert_eq!(nsf.tracks.len(), 2);
+        assert_eq!(nsf.tracks[1].title, Some("B".repeat(149)));
+        assert_eq!(nsf.tracks[0].author, Some("C".repeat(300)));
+    }
+
+    #[test]
+    fn test_nsfe_parse() {
+        let mut bytes = b"NSFE".to_vec();
+        bytes.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x01, 0x04, 2, 1],
+        ));
+        bytes.extend(chunk(b"BANK", &[0, 1]));
+        bytes.extend(chunk(b"RATE", &[0x1A, 0x41]));
+        bytes.extend(chunk(b"auth", b"Game\0Composer\0Company\0Ripper\0"));
+        bytes.extend(chunk(b"tlbl", b"Intro\0Boss\0"));
+        let mut times = 90_000i32.to_le_bytes().to_vec();
+        times.extend(&(-1i32).to_le_bytes());
+        bytes.extend(chunk(b"time", &times));
+        bytes.extend(chunk(b"fade", &5_000i32.to_le_bytes()));
+        bytes.extend(chunk(b"plst", &[1, 0]));
+        bytes.extend(chunk(b"psfx", &[1]));
+        bytes.extend(chunk(b"DATA", &[0x60; 32]));
+        bytes.extend(chunk(b"NEND", &[]));
+
+        let nsf = Nsf::from_bytes(&bytes).unwrap();
+        assert_eq!(nsf.version, 0);
+        assert_eq!(nsf.songs, 2);
+        assert_eq!(nsf.starting_song, 2);
+        assert_eq!(nsf.timing, Timing::Pal);
+        assert_eq!(nsf.expansion, Expansion::FDS);
+        assert_eq!(nsf.bankswitch, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.ntsc_speed, 16666);
+        assert_eq!(nsf.pal_speed, DEFAULT_PAL_SPEED);
+        assert_eq!(nsf.title, "Game");
+        assert_eq!(nsf.ripper.as_deref(), Some("Ripper"));
+        assert_eq!(
+            nsf.tracks,
+            vec![
+                NsfTrack {
+                    title: Some("Intro".to_string()),
+                    author: None,
+                    time: Some(90_000),
+                    fade: Some(5_000),
+                },
+                NsfTrack {
+                    title: Some("Boss".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(nsf.playlist, Some(vec![1, 0]));
+        assert_eq!(nsf.chunks.len(), 1);
+        assert_eq!(nsf.data.len(), 32);
+    }
+
+    #[test]
+    fn test_nsfe_errors() {
+        let mut bytes = b"NSFE".to_vec();
+        bytes.extend(chunk(b"INFO", &[0; 9]));
+        bytes.extend(chunk(b"NEND", &[]));
+        match Nsf::from_bytes(&bytes) {
+            Err(InesError::MissingChunk(id)) => assert_eq!(&id, b"DATA"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut bytes = b"NSFE".to_vec();
+        bytes.extend(chunk(b"INFO", &[0; 9]));
+        bytes.extend(chunk(b"VRC9", &[0; 4]));
+        match Nsf::from_bytes(&bytes) {
+            Err(InesError::UnsupportedChunk(id)) => assert_eq!(&id, b"VRC9"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut bytes = b"NSFE".to_vec();
+        bytes.extend(chunk(b"INFO", &[0; 4]));
+        match Nsf::from_bytes(&bytes) {
+            Err(InesError::Truncated { section, .. }) => {
+                assert_eq!(section, Section::Chunk(*b"INFO"))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
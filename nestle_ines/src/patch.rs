@@ -0,0 +1,482 @@
+use std::fmt;
+
+use crate::error::{InesError, Result, Section};
+use crate::model::Sections;
+
+/// Size of the UPS/BPS footer: source, target and patch CRC32s.
+const FOOTER_SIZE: usize = 12;
+
+/// Largest target a UPS/BPS patch may ask for, well past any cartridge, so
+/// a corrupt size can't exhaust memory before the CRC catches it.
+const MAX_TARGET_SIZE: usize = 64 << 20;
+
+/// Patch formats, told apart by their magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(b"PATCH") {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(b"UPS1") {
+            Some(PatchFormat::Ups)
+        } else if patch.starts_with(b"BPS1") {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+
+    /// File extension patches of this format use.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PatchFormat::Ips => "ips",
+            PatchFormat::Ups => "ups",
+            PatchFormat::Bps => "bps",
+        }
+    }
+}
+
+/// Which of the checksums in a UPS/BPS footer failed to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFile {
+    Source,
+    Target,
+    Patch,
+}
+
+impl fmt::Display for PatchFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PatchFile::Source => "source",
+            PatchFile::Target => "target",
+            PatchFile::Patch => "patch",
+        };
+        f.write_str(name)
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    crc32fast::hash(bytes)
+}
+
+fn check_crc(file: PatchFile, expected: u32, bytes: &[u8]) -> Result<()> {
+    let actual = crc32(bytes);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(InesError::PatchChecksum {
+            file,
+            expected,
+            actual,
+        })
+    }
+}
+
+fn be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |acc, &b| acc << 8 | usize::from(b))
+}
+
+fn magic(sections: &mut Sections<'_>, magic: &[u8]) -> Result<()> {
+    let found = sections.take(Section::Patch, magic.len())?;
+    if found == magic {
+        return Ok(());
+    }
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&found[..4]);
+    Err(InesError::BadMagic {
+        offset: 0,
+        found: bytes,
+    })
+}
+
+/// Reads a UPS/BPS variable length number.
+fn varint(sections: &mut Sections<'_>) -> Result<usize> {
+    let offset = sections.offset();
+    let too_large = || InesError::InvalidPatch {
+        offset,
+        reason: "number too large",
+    };
+    let mut value: usize = 0;
+    let mut shift: usize = 1;
+
+    loop {
+        let byte = sections.take(Section::Patch, 1)?[0];
+        value = usize::from(byte & 0x7F)
+            .checked_mul(shift)
+            .and_then(|x| value.checked_add(x))
+            .ok_or_else(too_large)?;
+        if byte & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift = shift
+            .checked_shl(7)
+            .filter(|&s| s != 0)
+            .ok_or_else(too_large)?;
+        value = value.checked_add(shift).ok_or_else(too_large)?;
+    }
+}
+
+/// Reads the source and target sizes at the start of a UPS/BPS patch,
+/// checking the source size against `source` and bounding the target size.
+fn sizes(sections: &mut Sections<'_>, source: &[u8]) -> Result<usize> {
+    let offset = sections.offset();
+    if varint(sections)? != source.len() {
+        return Err(InesError::InvalidPatch {
+            offset,
+            reason: "source size doesn't match",
+        });
+    }
+    let offset = sections.offset();
+    let target_size = varint(sections)?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(InesError::InvalidPatch {
+            offset,
+            reason: "target too large",
+        });
+    }
+    Ok(target_size)
+}
+
+/// Splits a UPS/BPS patch into its body and footer CRCs, checking the
+/// patch's own CRC and the source's.
+fn checked_body<'a>(patch: &'a [u8], source: &[u8]) -> Result<(&'a [u8], u32)> {
+    if patch.len() < 4 + FOOTER_SIZE {
+        return Err(InesError::Truncated {
+            section: Section::Patch,
+            offset: 0,
+            expected: 4 + FOOTER_SIZE,
+            actual: patch.len(),
+        });
+    }
+
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_SIZE);
+    let crc = |at: usize| {
+        u32::from_le_bytes([footer[at], footer[at + 1], footer[at + 2], footer[at + 3]])
+    };
+    check_crc(PatchFile::Patch, crc(8), &patch[..patch.len() - 4])?;
+    check_crc(PatchFile::Source, crc(0), source)?;
+    Ok((body, crc(4)))
+}
+
+/// Applies an IPS patch. RLE records are expanded, records past the end
+/// grow the ROM, and a 3-byte offset after `EOF` truncates it.
+pub fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>> {
+    let mut sections = Sections::new(patch, 0);
+    magic(&mut sections, b"PATCH")?;
+    let mut target = rom.to_vec();
+
+    loop {
+        let record = sections.take(Section::Patch, 3)?;
+        if record == b"EOF" {
+            if sections.remaining().len() == 3 {
+                target.truncate(be(sections.remaining()));
+            }
+            return Ok(target);
+        }
+
+        let offset = be(record);
+        let size = be(sections.take(Section::Patch, 2)?);
+        if size == 0 {
+            let rle = sections.take(Section::Patch, 3)?;
+            let end = offset + be(&rle[..2]);
+            if target.len() < end {
+                target.resize(end, 0);
+            }
+            target[offset..end].iter_mut().for_each(|b| *b = rle[2]);
+        } else {
+            let data = sections.take(Section::Patch, size)?;
+            let end = offset + size;
+            if target.len() < end {
+                target.resize(end, 0);
+            }
+            target[offset..end].copy_from_slice(data);
+        }
+    }
+}
+
+/// Applies a UPS patch, verifying the source, target and patch CRCs.
+pub fn apply_ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>> {
+    let (body, target_crc) = checked_body(patch, rom)?;
+    let mut sections = Sections::new(body, 0);
+    magic(&mut sections, b"UPS1")?;
+    let target_size = sizes(&mut sections, rom)?;
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut pos = 0usize;
+
+    while !sections.remaining().is_empty() {
+        let offset = sections.offset();
+        pos = pos.saturating_add(varint(&mut sections)?);
+        loop {
+            let byte = sections.take(Section::Patch, 1)?[0];
+            if byte == 0 {
+                pos = pos.saturating_add(1);
+                break;
+            }
+            let out = target.get_mut(pos).ok_or(InesError::InvalidPatch {
+                offset,
+                reason: "write past the end of the target",
+            })?;
+            *out ^= byte;
+            pos += 1;
+        }
+    }
+
+    check_crc(PatchFile::Target, target_crc, &target)?;
+    Ok(target)
+}
+
+/// Moves a BPS copy cursor by a signed relative offset.
+fn relative(base: usize, data: usize) -> Option<usize> {
+    if data & 1 != 0 {
+        base.checked_sub(data >> 1)
+    } else {
+        base.checked_add(data >> 1)
+    }
+}
+
+/// Applies a BPS patch, verifying the source, target and patch CRCs.
+pub fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>> {
+    let (body, target_crc) = checked_body(patch, rom)?;
+    let mut sections = Sections::new(body, 0);
+    magic(&mut sections, b"BPS1")?;
+    let target_size = sizes(&mut sections, rom)?;
+    let metadata_size = varint(&mut sections)?;
+    sections.take(Section::Patch, metadata_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_pos = 0usize;
+    let mut target_pos = 0usize;
+
+    while !sections.remaining().is_empty() {
+        let offset = sections.offset();
+        let invalid = |reason| InesError::InvalidPatch { offset, reason };
+        let action = varint(&mut sections)?;
+        let len = (action >> 2) + 1;
+        if target
+            .len()
+            .checked_add(len)
+            .is_none_or(|end| end > target_size)
+        {
+            return Err(invalid("write past the end of the target"));
+        }
+
+        match action & 0b11 {
+            // SourceRead
+            0 => {
+                let start = target.len();
+                let data = rom
+                    .get(start..start + len)
+                    .ok_or_else(|| invalid("read past the end of the source"))?;
+                target.extend_from_slice(data);
+            }
+            // TargetRead
+            1 => target.extend_from_slice(sections.take(Section::Patch, len)?),
+            // SourceCopy
+            2 => {
+                source_pos = relative(source_pos, varint(&mut sections)?)
+                    .ok_or_else(|| invalid("copy before the start of the source"))?;
+                let data = rom
+                    .get(source_pos..source_pos + len)
+                    .ok_or_else(|| invalid("copy past the end of the source"))?;
+                target.extend_from_slice(data);
+                source_pos += len;
+            }
+            // TargetCopy, which may overlap the bytes it's writing
+            _ => {
+                target_pos = relative(target_pos, varint(&mut sections)?)
+                    .filter(|&pos| pos < target.len())
+                    .ok_or_else(|| invalid("copy outside the written target"))?;
+                for _ in 0..len {
+                    target.push(target[target_pos]);
+                    target_pos += 1;
+                }
+            }
+        }
+    }
+
+    check_crc(PatchFile::Target, target_crc, &target)?;
+    Ok(target)
+}
+
+/// Applies a patch in any supported format, picked by magic.
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>> {
+    match PatchFormat::detect(patch).ok_or(InesError::UnknownFormat)? {
+        PatchFormat::Ips => apply_ips(patch, rom),
+        PatchFormat::Ups => apply_ups(patch, rom),
+        PatchFormat::Bps => apply_bps(patch, rom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(mut value: usize) -> Vec<u8> {
+        let mut result = Vec::new();
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                result.push(byte | 0x80);
+                return result;
+            }
+            result.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut body: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        body.extend(&crc32(source).to_le_bytes());
+        body.extend(&crc32(target).to_le_bytes());
+        let crc = crc32(&body);
+        body.extend(&crc.to_le_bytes());
+        body
+    }
+
+    #[test]
+    fn test_varint() {
+        for &value in &[0, 1, 127, 128, 16511, 16512, 1 << 30] {
+            let bytes = encode(value);
+            assert_eq!(varint(&mut Sections::new(&bytes, 0)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_apply_ips() {
+        let rom = vec![0u8; 16];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+        // RLE record growing the ROM
+        patch.extend(&[0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        patch.extend(b"EOF");
+
+        let patched = apply(&patch, &rom).unwrap();
+        assert_eq!(patched.len(), 18);
+        assert_eq!(&patched[..4], &[0, 0, 0xAA, 0xBB]);
+        assert_eq!(&patched[14..], &[0xCC; 4]);
+
+        patch.extend(&[0x00, 0x00, 0x08]);
+        assert_eq!(
+            apply(&patch, &rom).unwrap(),
+            vec![0, 0, 0xAA, 0xBB, 0, 0, 0, 0]
+        );
+
+        let truncated = &patch[..patch.len() - 8];
+        assert!(matches!(
+            apply(truncated, &rom),
+            Err(InesError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn test_apply_ups() {
+        let source = b"Hello, World".to_vec();
+        let target = b"Hello, NES!!?".to_vec();
+
+        let mut body = b"UPS1".to_vec();
+        body.extend(encode(source.len()));
+        body.extend(encode(target.len()));
+        // skip 7 bytes, then XOR until the end of the target
+        body.extend(encode(7));
+        body.extend(
+            source[7..]
+                .iter()
+                .chain(&[0])
+                .zip(&target[7..])
+                .map(|(s, t)| s ^ t),
+        );
+        body.push(0);
+        let patch = with_footer(body, &source, &target);
+
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+        match apply(&patch, b"Goodbye") {
+            Err(InesError::PatchChecksum { file, .. }) => assert_eq!(file, PatchFile::Source),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut corrupt = patch.clone();
+        corrupt[10] ^= 1;
+        match apply(&corrupt, &source) {
+            Err(InesError::PatchChecksum { file, .. }) => assert_eq!(file, PatchFile::Patch),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_apply_bps() {
+        let source = b"abcdefgh".to_vec();
+        let target = b"abcdXYXYXYhgh".to_vec();
+
+        let mut body = b"BPS1".to_vec();
+        body.extend(encode(source.len()));
+        body.extend(encode(target.len()));
+        body.extend(encode(0));
+        // SourceRead "abcd"
+        body.extend(encode((4 - 1) << 2));
+        // TargetRead "XY"
+        body.extend(encode((2 - 1) << 2 | 1));
+        body.extend(b"XY");
+        // TargetCopy "XYXY" from offset 4, overlapping its own output
+        body.extend(encode((4 - 1) << 2 | 3));
+        body.extend(encode(4 << 1));
+        // SourceCopy "h" from offset 7, then "gh" from offset 6
+        body.extend(encode(2));
+        body.extend(encode(7 << 1));
+        body.extend(encode((2 - 1) << 2 | 2));
+        body.extend(encode(2 << 1 | 1));
+        let patch = with_footer(body, &source, &target);
+
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+    }
+
+    fn invalid_reason(result: Result<Vec<u8>>) -> &'static str {
+        match result {
+            Err(InesError::InvalidPatch { reason, .. }) => reason,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_patch_sizes() {
+        let source = b"abcdefgh".to_vec();
+        for magic in &[b"UPS1", b"BPS1"] {
+            let mut body = magic.to_vec();
+            body.extend(encode(source.len()));
+            body.extend(encode(MAX_TARGET_SIZE + 1));
+            body.extend(encode(0));
+            let patch = with_footer(body, &source, &[]);
+            assert_eq!(invalid_reason(apply(&patch, &source)), "target too large");
+
+            let mut body = magic.to_vec();
+            body.extend(encode(source.len() + 1));
+            body.extend(encode(0));
+            let patch = with_footer(body, &source, &[]);
+            assert_eq!(
+                invalid_reason(apply(&patch, &source)),
+                "source size doesn't match"
+            );
+        }
+
+        // a TargetCopy far longer than the target is refused before copying
+        let mut body = b"BPS1".to_vec();
+        body.extend(encode(source.len()));
+        body.extend(encode(4));
+        body.extend(encode(0));
+        body.extend(encode((2 - 1) << 2 | 1));
+        body.extend(b"XY");
+        body.extend(encode((1 << 40) << 2 | 3));
+        body.extend(encode(0));
+        let patch = with_footer(body, &source, b"XYXY");
+        assert_eq!(
+            invalid_reason(apply(&patch, &source)),
+            "write past the end of the target"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use crate::model::{Ines, InesRef, PROM_SIZE};
+
+/// Where the title the BIOS lists on its game selection menu sits in
+/// INST-ROM, and its maximum length.
+const TITLE_OFFSET: usize = 0x1F00;
+const TITLE_LEN: usize = 16;
+
+/// The PlayChoice-10 PROM, which feeds the RP5H01 security chip the BIOS
+/// checks before starting a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayChoiceProm {
+    /// 128-bit key the security chip shifts out
+    pub key: [u8; 16],
+    /// Inverted copy of the counter output lines
+    pub counter_out: [u8; 16],
+}
+
+impl PlayChoiceProm {
+    /// Splits the 32 byte PROM, or `None` if it's the wrong length.
+    pub fn from_bytes(prom: &[u8]) -> Option<Self> {
+        if prom.len() != PROM_SIZE {
+            return None;
+        }
+
+        let mut key = [0u8; 16];
+        let mut counter_out = [0u8; 16];
+        key.copy_from_slice(&prom[..16]);
+        counter_out.copy_from_slice(&prom[16..]);
+        Some(PlayChoiceProm { key, counter_out })
+    }
+}
+
+/// Game title from INST-ROM, or `None` if there's no printable ASCII title
+/// where the BIOS expects one.
+pub fn inst_rom_title(inst_rom: &[u8]) -> Option<String> {
+    let raw = inst_rom.get(TITLE_OFFSET..TITLE_OFFSET + TITLE_LEN)?;
+    let end = raw
+        .iter()
+        .position(|&b| b == 0x00 || b == 0xFF)
+        .unwrap_or(raw.len());
+    let title = &raw[..end];
+
+    if title.iter().all(|&b| b == b' ' || b.is_ascii_graphic()) {
+        let title = String::from_utf8_lossy(title).trim().to_string();
+        if !title.is_empty() {
+            return Some(title);
+        }
+    }
+    None
+}
+
+impl InesRef<'_> {
+    /// Title shown on the PlayChoice-10 selection menu.
+    pub fn playchoice_title(&self) -> Option<String> {
+        self.inst_rom.and_then(inst_rom_title)
+    }
+
+    pub fn playchoice_prom(&self) -> Option<PlayChoiceProm> {
+        self.prom.and_then(PlayChoiceProm::from_bytes)
+    }
+}
+
+impl Ines {
+    /// Title shown on the PlayChoice-10 selection menu.
+    pub fn playchoice_title(&self) -> Option<String> {
+        self.view().playchoice_title()
+    }
+
+    pub fn playchoice_prom(&self) -> Option<PlayChoiceProm> {
+        self.view().playchoice_prom()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::{ConsoleType, InesHeader};
+    use crate::write::BinWrite;
+
+    #[test]
+    fn test_playchoice_decode() {
+        let mut inst_rom = vec![0xFF; 8192];
+        inst_rom[TITLE_OFFSET..TITLE_OFFSET + 11].copy_from_slice(b"SUPER MARIO");
+        let prom: Vec<u8> = (0..32).collect();
+        let ines = Ines {
+            header: InesHeader::builder()
+                .console_type(ConsoleType::PlayChoice10)
+                .build(),
+            prg: vec![0; 16384],
+            inst_rom: Some(inst_rom),
+            prom: Some(prom),
+            ..Default::default()
+        };
+
+        let parsed = Ines::from_bytes(&ines.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.playchoice_title().as_deref(), Some("SUPER MARIO"));
+        let prom = parsed.playchoice_prom().unwrap();
+        assert_eq!(prom.key[15], 15);
+        assert_eq!(prom.counter_out[0], 16);
+    }
+
+    #[test]
+    fn test_inst_rom_title_garbage() {
+        let mut inst_rom = vec![0u8; 8192];
+        assert_eq!(inst_rom_title(&inst_rom), None);
+        inst_rom[TITLE_OFFSET] = 0x80;
+        assert_eq!(inst_rom_title(&inst_rom), None);
+        assert_eq!(inst_rom_title(&[0; 16]), None);
+        assert_eq!(PlayChoiceProm::from_bytes(&[0; 16]), None);
+    }
+}
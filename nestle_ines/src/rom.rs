@@ -0,0 +1,245 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive;
+use crate::error::{InesError, Result};
+use crate::fds::FdsImage;
+use crate::model::{HeaderFormat, Ines, HEADER_SIZE};
+use crate::nsf::Nsf;
+use crate::patch::{self, PatchFile, PatchFormat};
+use crate::unif::Unif;
+
+/// File formats understood by this crate, told apart by their magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ines,
+    Nes2,
+    Unif,
+    /// Famicom Disk System image, fwNES or raw QD
+    Fds,
+    /// NSF or NSFe music file
+    Nsf,
+}
+
+impl Format {
+    /// Identifies the format of an image from its leading bytes.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"NES\x1a") {
+            match bytes.get(7) {
+                Some(flags7) if flags7 & 0b0000_1100 == 0b0000_1000 => Some(Format::Nes2),
+                _ => Some(Format::Ines),
+            }
+        } else if bytes.starts_with(b"UNIF") {
+            Some(Format::Unif)
+        } else if bytes.starts_with(b"FDS\x1a") || bytes.starts_with(b"\x01*NINTENDO-HVC*") {
+            Some(Format::Fds)
+        } else if bytes.starts_with(b"NESM\x1a") || bytes.starts_with(b"NSFE") {
+            Some(Format::Nsf)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Ines => "iNES",
+            Format::Nes2 => "NES 2.0",
+            Format::Unif => "UNIF",
+            Format::Fds => "FDS",
+            Format::Nsf => "NSF",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An image in any supported format.
+#[derive(Debug)]
+pub enum Rom {
+    Ines(Ines),
+    Unif(Unif),
+    Fds(FdsImage),
+    Nsf(Nsf),
+}
+
+impl Rom {
+    /// Loads an image, unpacking it first if it's zipped or gzipped.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        archive::load(path, None, Self::from_bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match Format::detect(bytes).ok_or(InesError::UnknownFormat)? {
+            Format::Ines | Format::Nes2 => Ines::from_bytes(bytes).map(Rom::Ines),
+            Format::Unif => Unif::from_bytes(bytes).map(Rom::Unif),
+            Format::Fds => FdsImage::from_bytes(bytes).map(Rom::Fds),
+            Format::Nsf => Nsf::from_bytes(bytes).map(Rom::Nsf),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Rom::Ines(ines) if ines.header.format() == HeaderFormat::Nes2 => Format::Nes2,
+            Rom::Ines(_) => Format::Ines,
+            Rom::Unif(_) => Format::Unif,
+            Rom::Fds(_) => Format::Fds,
+            Rom::Nsf(_) => Format::Nsf,
+        }
+    }
+
+    /// Converts the image into an iNES cartridge description.
+    pub fn into_ines(self) -> Result<Ines> {
+        match self {
+            Rom::Ines(ines) => Ok(ines),
+            Rom::Unif(unif) => unif.to_ines(),
+            Rom::Fds(_) => Err(InesError::NotACartridge(Format::Fds)),
+            Rom::Nsf(_) => Err(InesError::NotACartridge(Format::Nsf)),
+        }
+    }
+}
+
+/// Options for loading a cartridge, used like `std::fs::OpenOptions`:
+///
+/// ```no_run
+/// # use std::path::{Path, PathBuf};
+/// let ines = nestle_ines::LoadOptions::new()
+///     .auto_patch(true)
+///     .load(Path::new("game.nes"))?;
+/// # Ok::<(), nestle_ines::InesError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    auto_patch: bool,
+    member: Option<String>,
+}
+
+impl LoadOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Applies a `.ips`, `.bps` or `.ups` file with the same stem as the
+    /// ROM, if one exists, before parsing it.
+    pub fn auto_patch(&mut self, auto_patch: bool) -> &mut Self {
+        self.auto_patch = auto_patch;
+        self
+    }
+
+    /// Archive member to load when the ROM is zipped, instead of the only
+    /// `.nes`, `.unf` or `.fds` file in it.
+    pub fn member(&mut self, member: &str) -> &mut Self {
+        self.member = Some(member.to_owned());
+        self
+    }
+
+    /// Loads a cartridge from any supported format, picked by magic, after
+    /// unpacking it if it's zipped or gzipped.
+    pub fn load(&self, path: &Path) -> Result<Ines> {
+        self.load_with(path, Rom::into_ines)
+    }
+
+    /// Like [`LoadOptions::load`], but keeps the image in its own format.
+    pub fn load_rom(&self, path: &Path) -> Result<Rom> {
+        self.load_with(path, Ok)
+    }
+
+    /// The image's bytes as [`LoadOptions::load`] parses them, unpacked and
+    /// patched, for checks like [`crate::validate::check`] that need the
+    /// file as it is.
+    pub fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.read_with(path, |bytes| Ok(bytes.to_vec()))
+    }
+
+    fn load_with<T>(&self, path: &Path, convert: impl FnOnce(Rom) -> Result<T>) -> Result<T> {
+        self.read_with(path, |bytes| Rom::from_bytes(bytes).and_then(convert))
+    }
+
+    fn read_with<T>(&self, path: &Path, parse: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+        // patches sit next to the archive and apply to the unpacked ROM
+        let patch = match sibling_patch(path) {
+            Some(patch_path) if self.auto_patch => {
+                let patch =
+                    fs::read(&patch_path).map_err(|e| InesError::from(e).with_path(&patch_path))?;
+                Some((patch_path, patch))
+            }
+            _ => None,
+        };
+
+        archive::load(path, self.member.as_deref(), |bytes| {
+            let patched;
+            let bytes = match &patch {
+                Some((patch_path, patch)) => {
+                    patched = patch_image(patch, bytes).map_err(|e| e.with_path(patch_path))?;
+                    &patched
+                }
+                None => bytes,
+            };
+            parse(bytes)
+        })
+    }
+}
+
+/// First patch file next to `path`, in order of preference.
+fn sibling_patch(path: &Path) -> Option<PathBuf> {
+    [PatchFormat::Bps, PatchFormat::Ups, PatchFormat::Ips]
+        .iter()
+        .map(|format| path.with_extension(format.extension()))
+        .find(|patch| patch.is_file())
+}
+
+/// Applies a patch to a whole image. UPS and BPS patches are usually made
+/// against headerless dumps, so those are retried without the iNES header
+/// when the source checksum doesn't match.
+fn patch_image(patch: &[u8], bytes: &[u8]) -> Result<Vec<u8>> {
+    match patch::apply(patch, bytes) {
+        Err(InesError::PatchChecksum {
+            file: PatchFile::Source,
+            ..
+        }) if bytes.starts_with(b"NES\x1a") && bytes.len() >= HEADER_SIZE => {
+            let (header, rom) = bytes.split_at(HEADER_SIZE);
+            let mut patched = header.to_vec();
+            patched.extend(patch::apply(patch, rom)?);
+            Ok(patched)
+        }
+        result => result,
+    }
+}
+
+/// Loads a cartridge from any supported format, picked by magic.
+pub fn load(path: &Path) -> Result<Ines> {
+    LoadOptions::new().load(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::write::BinWrite;
+
+    #[test]
+    fn test_format_detect() {
+        let ines = Ines {
+            prg: vec![0; 16384],
+            ..Default::default()
+        };
+        let bytes = ines.to_bytes().unwrap();
+        assert_eq!(Format::detect(&bytes), Some(Format::Ines));
+        assert_eq!(Format::detect(b"UNIF\x07\x00\x00\x00"), Some(Format::Unif));
+        assert_eq!(Format::detect(b"FDS\x1a\x01"), Some(Format::Fds));
+        assert_eq!(Format::detect(b"\x01*NINTENDO-HVC*"), Some(Format::Fds));
+        assert_eq!(Format::detect(b"NESM\x1a"), Some(Format::Nsf));
+        assert_eq!(Format::detect(b"NSFE"), Some(Format::Nsf));
+        assert_eq!(Format::detect(b"NES\x00"), None);
+
+        let rom = Rom::from_bytes(&bytes).unwrap();
+        assert_eq!(rom.format(), Format::Ines);
+        assert_eq!(rom.into_ines().unwrap().prg.len(), 16384);
+
+        assert!(matches!(
+            Rom::from_bytes(b"garbage"),
+            Err(InesError::UnknownFormat)
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::archive;
+use crate::error::{InesError, Result, Section};
+use crate::model::{Ines, InesHeader, Mirroring, Sections, Timing};
+
+const HEADER_SIZE: usize = 32;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+/// Prefixes UNIF board names carry in front of the PCB name.
+const BOARD_PREFIXES: &[&str] = &["NES-", "HVC-", "UNL-", "BMC-", "BTL-"];
+
+/// Known UNIF boards and their iNES mapper and submapper numbers, keyed by
+/// the board name without its prefix.
+const BOARDS: &[(&str, u16, u8)] = &[
+    ("NROM", 0, 0),
+    ("NROM-128", 0, 0),
+    ("NROM-256", 0, 0),
+    ("RROM", 0, 0),
+    ("RROM-128", 0, 0),
+    ("SAROM", 1, 0),
+    ("SBROM", 1, 0),
+    ("SCROM", 1, 0),
+    ("SC1ROM", 1, 0),
+    ("SEROM", 1, 0),
+    ("SFROM", 1, 0),
+    ("SGROM", 1, 0),
+    ("SHROM", 1, 0),
+    ("SH1ROM", 1, 0),
+    ("SIROM", 1, 0),
+    ("SJROM", 1, 0),
+    ("SKROM", 1, 0),
+    ("SLROM", 1, 0),
+    ("SL1ROM", 1, 0),
+    ("SL2ROM", 1, 0),
+    ("SL3ROM", 1, 0),
+    ("SLRROM", 1, 0),
+    ("SNROM", 1, 0),
+    ("SOROM", 1, 0),
+    ("SUROM", 1, 0),
+    ("SXROM", 1, 0),
+    ("UNROM", 2, 0),
+    ("UOROM", 2, 0),
+    ("CNROM", 3, 0),
+    ("TBROM", 4, 0),
+    ("TEROM", 4, 0),
+    ("TFROM", 4, 0),
+    ("TGROM", 4, 0),
+    ("TKROM", 4, 0),
+    ("TLROM", 4, 0),
+    ("TL1ROM", 4, 0),
+    ("TL2ROM", 4, 0),
+    ("TNROM", 4, 0),
+    ("TR1ROM", 4, 0),
+    ("TSROM", 4, 0),
+    ("TVROM", 4, 0),
+    ("HKROM", 4, 1),
+    ("EKROM", 5, 0),
+    ("ELROM", 5, 0),
+    ("ETROM", 5, 0),
+    ("EWROM", 5, 0),
+    ("AMROM", 7, 0),
+    ("ANROM", 7, 0),
+    ("AN1ROM", 7, 0),
+    ("AOROM", 7, 0),
+    ("PNROM", 9, 0),
+    ("PEEOROM", 9, 0),
+    ("FJROM", 10, 0),
+    ("FKROM", 10, 0),
+    ("CPROM", 13, 0),
+    ("BNROM", 34, 0),
+    ("GNROM", 66, 0),
+    ("MHROM", 66, 0),
+    ("NTBROM", 68, 0),
+    ("JLROM", 69, 0),
+    ("JSROM", 69, 0),
+    ("BTR", 69, 0),
+    ("UN1ROM", 94, 0),
+    ("EVENT", 105, 0),
+    ("TLSROM", 118, 0),
+    ("TKSROM", 118, 0),
+    ("TQROM", 119, 0),
+    ("DEROM", 206, 0),
+    ("DE1ROM", 206, 0),
+    ("DRROM", 206, 0),
+];
+
+/// Looks up the iNES mapper and submapper for a UNIF board name.
+pub fn board_mapper(board: &str) -> Option<(u16, u8)> {
+    let name = BOARD_PREFIXES
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+
+    BOARDS
+        .iter()
+        .find(|(known, _, _)| known.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper, submapper)| (mapper, submapper))
+}
+
+/// Nametable arrangement from the `MIRR` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnifMirroring {
+    Horizontal,
+    Vertical,
+    /// Always uses the nametable at $2000
+    SingleScreenA,
+    /// Always uses the nametable at $2400
+    SingleScreenB,
+    FourScreen,
+    MapperControlled,
+}
+
+impl UnifMirroring {
+    fn from_byte(byte: u8) -> Option<Self> {
+        use UnifMirroring::*;
+        match byte {
+            0 => Some(Horizontal),
+            1 => Some(Vertical),
+            2 => Some(SingleScreenA),
+            3 => Some(SingleScreenB),
+            4 => Some(FourScreen),
+            5 => Some(MapperControlled),
+            _ => None,
+        }
+    }
+}
+
+/// A chunk this parser doesn't interpret, kept in file order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnifChunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Unif {
+    pub revision: u32,
+    /// Board name from `MAPR`
+    pub board: String,
+    /// `NAME`
+    pub name: Option<String>,
+    /// `READ`
+    pub comments: Option<String>,
+    /// `TVCI`
+    pub timing: Option<Timing>,
+    /// `CTRL` controller bitfield
+    pub controllers: Option<u8>,
+    /// `BATR`
+    pub battery: bool,
+    /// `VROR`, CHR data is RAM even though CHR chunks are present
+    pub chr_ram: bool,
+    /// `MIRR`
+    pub mirroring: Option<UnifMirroring>,
+    /// `PRG0`-`PRGF`, keyed by chunk number
+    pub prg: BTreeMap<u8, Vec<u8>>,
+    /// `CHR0`-`CHRF`, keyed by chunk number
+    pub chr: BTreeMap<u8, Vec<u8>>,
+    pub chunks: Vec<UnifChunk>,
+}
+
+/// NUL-terminated string, or the whole slice if there is no terminator.
+pub(crate) fn chunk_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn chunk_byte(id: [u8; 4], offset: u64, data: &[u8]) -> Result<u8> {
+    data.first().copied().ok_or(InesError::Truncated {
+        section: Section::Chunk(id),
+        offset,
+        expected: 1,
+        actual: 0,
+    })
+}
+
+/// Bank number of a `PRGn`/`CHRn` chunk, given as a hex digit.
+fn bank_number(id: &[u8; 4], prefix: &[u8; 3]) -> Option<u8> {
+    if &id[..3] != prefix {
+        return None;
+    }
+    (id[3] as char).to_digit(16).map(|n| n as u8)
+}
+
+impl Unif {
+    /// Loads an image, unpacking it first if it's zipped or gzipped.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        archive::load(path, None, Self::from_bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut sections = Sections::new(bytes, 0);
+        let header = sections.take(Section::Header, HEADER_SIZE)?;
+        if &header[..4] != b"UNIF" {
+            let mut found = [0u8; 4];
+            found.copy_from_slice(&header[..4]);
+            return Err(InesError::BadMagic { offset: 0, found });
+        }
+
+        let mut unif = Unif {
+            revision: u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+            ..Default::default()
+        };
+        let mut has_board = false;
+
+        while !sections.remaining().is_empty() {
+            let chunk = sections.take(Section::Header, CHUNK_HEADER_SIZE)?;
+            let mut id = [0u8; 4];
+            id.copy_from_slice(&chunk[..4]);
+            let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+            let offset = sections.offset();
+            let data = sections.take(Section::Chunk(id), len)?;
+
+            match &id {
+                b"MAPR" => {
+                    unif.board = chunk_string(data);
+                    has_board = true;
+                }
+                b"NAME" => unif.name = Some(chunk_string(data)),
+                b"READ" => unif.comments = Some(chunk_string(data)),
+                b"TVCI" => {
+                    unif.timing = match chunk_byte(id, offset, data)? {
+                        0 => Some(Timing::Ntsc),
+                        1 => Some(Timing::Pal),
+                        2 => Some(Timing::MultiRegion),
+                        _ => None,
+                    }
+                }
+                b"CTRL" => unif.controllers = Some(chunk_byte(id, offset, data)?),
+                b"BATR" => unif.battery = true,
+                b"VROR" => unif.chr_ram = true,
+                b"MIRR" => unif.mirroring = UnifMirroring::from_byte(chunk_byte(id, offset, data)?),
+                _ => {
+                    if let Some(n) = bank_number(&id, b"PRG") {
+                        unif.prg.insert(n, data.to_vec());
+                    } else if let Some(n) = bank_number(&id, b"CHR") {
+                        unif.chr.insert(n, data.to_vec());
+                    } else {
+                        unif.chunks.push(UnifChunk {
+                            id,
+                            data: data.to_vec(),
+                        });
+                    }
+                }
+            }
+        }
+
+        if !has_board {
+            return Err(InesError::MissingChunk(*b"MAPR"));
+        }
+        if unif.prg.is_empty() {
+            return Err(InesError::MissingChunk(*b"PRG0"));
+        }
+
+        Ok(unif)
+    }
+
+    /// PRG chunks concatenated in bank order.
+    pub fn prg_rom(&self) -> Vec<u8> {
+        self.prg.values().flatten().copied().collect()
+    }
+
+    /// CHR chunks concatenated in bank order.
+    pub fn chr_rom(&self) -> Vec<u8> {
+        self.chr.values().flatten().copied().collect()
+    }
+
+    /// iNES mapper and submapper of the board, if it's a known one.
+    pub fn mapper(&self) -> Option<(u16, u8)> {
+        board_mapper(&self.board)
+    }
+
+    /// Converts the cartridge into an iNES image, using NES 2.0 when the
+    /// board or ROM sizes need it. Fails for single-screen and
+    /// mapper-controlled `MIRR` values, which the header has no way to say.
+    pub fn to_ines(&self) -> Result<Ines> {
+        let (mapper, submapper) = self
+            .mapper()
+            .ok_or_else(|| InesError::UnknownBoard(self.board.clone()))?;
+        let prg = self.prg_rom();
+        let chr = if self.chr_ram {
+            Vec::new()
+        } else {
+            self.chr_rom()
+        };
+
+        let mut builder = InesHeader::builder();
+        builder
+            .prg_rom_size(prg.len())?
+            .chr_rom_size(chr.len())?
+            .mapper(mapper)
+            .submapper(submapper)
+            .battery(self.battery);
+
+        match self.mirroring {
+            Some(UnifMirroring::Horizontal) | None => builder.mirroring(Mirroring::Horizontal),
+            Some(UnifMirroring::Vertical) => builder.mirroring(Mirroring::Vertical),
+            Some(UnifMirroring::FourScreen) => builder.mirroring(Mirroring::FourScreen),
+            Some(mirroring) => return Err(InesError::UnsupportedMirroring(mirroring)),
+        };
+        if let Some(timing) = self.timing {
+            builder.timing(timing);
+        }
+
+        Ok(Ines {
+            header: builder.build(),
+            prg,
+            chr: if chr.is_empty() { None } else { Some(chr) },
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::HeaderFormat;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut result = id.to_vec();
+        result.extend(&(data.len() as u32).to_le_bytes());
+        result.extend(data);
+        result
+    }
+
+    fn unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut result = b"UNIF".to_vec();
+        result.extend(&7u32.to_le_bytes());
+        result.extend(&[0; 24]);
+        for chunk in chunks {
+            result.extend(chunk);
+        }
+        result
+    }
+
+    #[test]
+    fn test_unif_parse() {
+        let bytes = unif(&[
+            chunk(b"MAPR", b"NES-TLROM\0"),
+            chunk(b"NAME", b"Test Cart\0"),
+            chunk(b"PRG1", &[2; 16384]),
+            chunk(b"PRG0", &[1; 16384]),
+            chunk(b"CHR0", &[3; 8192]),
+            chunk(b"MIRR", &[1]),
+            chunk(b"BATR", &[0]),
+            chunk(b"TVCI", &[1]),
+            chunk(b"DINF", &[0; 4]),
+        ]);
+        let unif = Unif::from_bytes(&bytes).unwrap();
+
+        assert_eq!(unif.revision, 7);
+        assert_eq!(unif.board, "NES-TLROM");
+        assert_eq!(unif.name.as_deref(), Some("Test Cart"));
+        assert_eq!(unif.mirroring, Some(UnifMirroring::Vertical));
+        assert_eq!(unif.timing, Some(Timing::Pal));
+        assert!(unif.battery);
+        assert_eq!(unif.chunks.len(), 1);
+        assert_eq!(&unif.chunks[0].id, b"DINF");
+
+        let prg = unif.prg_rom();
+        assert_eq!(prg.len(), 32768);
+        assert_eq!(prg[0], 1);
+        assert_eq!(prg[16384], 2);
+
+        let ines = unif.to_ines().unwrap();
+        assert_eq!(ines.header.mapper_number(), 4);
+        assert_eq!(ines.header.mirroring(), Mirroring::Vertical);
+        assert!(ines.header.has_battery());
+        assert_eq!(ines.header.format(), HeaderFormat::Nes2);
+        assert_eq!(ines.prg, prg);
+        assert_eq!(ines.chr, Some(vec![3; 8192]));
+    }
+
+    #[test]
+    fn test_unif_errors() {
+        let bytes = unif(&[chunk(b"PRG0", &[1; 16384])]);
+        match Unif::from_bytes(&bytes) {
+            Err(InesError::MissingChunk(id)) => assert_eq!(&id, b"MAPR"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut bytes = unif(&[chunk(b"MAPR", b"NES-NROM\0"), chunk(b"PRG0", &[1; 16])]);
+        bytes.truncate(bytes.len() - 4);
+        match Unif::from_bytes(&bytes) {
+            Err(InesError::Truncated {
+                section, actual, ..
+            }) => {
+                assert_eq!(section, Section::Chunk(*b"PRG0"));
+                assert_eq!(actual, 12);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let bytes = unif(&[chunk(b"MAPR", b"UNL-MYSTERY\0"), chunk(b"PRG0", &[1; 16])]);
+        match Unif::from_bytes(&bytes).unwrap().to_ines() {
+            Err(InesError::UnknownBoard(board)) => assert_eq!(board, "UNL-MYSTERY"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        for &(byte, mirroring) in &[
+            (2, UnifMirroring::SingleScreenA),
+            (3, UnifMirroring::SingleScreenB),
+            (5, UnifMirroring::MapperControlled),
+        ] {
+            let bytes = unif(&[
+                chunk(b"MAPR", b"NES-NROM-256\0"),
+                chunk(b"PRG0", &[1; 32768]),
+                chunk(b"MIRR", &[byte]),
+            ]);
+            match Unif::from_bytes(&bytes).unwrap().to_ines() {
+                Err(InesError::UnsupportedMirroring(found)) => assert_eq!(found, mirroring),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_board_mapper() {
+        assert_eq!(board_mapper("NES-NROM-256"), Some((0, 0)));
+        assert_eq!(board_mapper("HVC-HKROM"), Some((4, 1)));
+        assert_eq!(board_mapper("SNROM"), Some((1, 0)));
+        assert_eq!(board_mapper("NES-XYZROM"), None);
+    }
+}
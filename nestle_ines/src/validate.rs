@@ -0,0 +1,445 @@
+use std::fmt;
+
+use crate::error::{InesError, Result, Section};
+use crate::header::CHR_ROM_UNIT;
+use crate::model::{
+    ConsoleType, HeaderFormat, Ines, InesHeader, InesHeaderBuilder, HEADER_SIZE, INST_ROM_SIZE,
+    PROM_SIZE, TRAINER_SIZE,
+};
+use crate::write::BinWrite;
+
+/// Mappers of the copier boards that actually loaded trainers.
+const TRAINER_MAPPERS: &[u16] = &[6, 8, 17];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The image loads, but something about it is unusual
+    Warning,
+    /// The image is likely to load or run incorrectly
+    Error,
+}
+
+/// A problem found in an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// Bytes 7-15 of an archaic header aren't zero, usually a dumper's
+    /// signature like `DiskDude!`. Flags 7 can't be trusted, which drops
+    /// the upper mapper nibble.
+    ReservedGarbage { bytes: [u8; 9] },
+    /// The header announces a trainer on a mapper that never used one.
+    UnexpectedTrainer { mapper: u16 },
+    /// PRG-ROM size is zero.
+    EmptyPrgRom,
+    /// CHR-ROM size is zero but the file carries whole CHR banks after
+    /// PRG-ROM.
+    UndeclaredChrRom { size: usize },
+    /// The file is longer than the header accounts for.
+    TrailingData { expected: usize, actual: usize },
+    /// The file is shorter than the header claims.
+    MissingData { expected: usize, actual: usize },
+    /// A section's data doesn't match the size in the header.
+    SectionSize {
+        section: Section,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Diagnostic::UnexpectedTrainer { .. } | Diagnostic::TrailingData { .. } => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        }
+    }
+
+    /// Whether [`repair`] can fix this without guessing.
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            Diagnostic::ReservedGarbage { .. }
+                | Diagnostic::UndeclaredChrRom { .. }
+                | Diagnostic::TrailingData { .. }
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::ReservedGarbage { bytes } => {
+                let text: String = bytes
+                    .iter()
+                    .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                    .collect();
+                write!(f, "garbage in header bytes 7-15: \"{}\"", text)
+            }
+            Diagnostic::UnexpectedTrainer { mapper } => {
+                write!(
+                    f,
+                    "trainer present on mapper {}, which never used one",
+                    mapper
+                )
+            }
+            Diagnostic::EmptyPrgRom => f.write_str("PRG-ROM size is zero"),
+            Diagnostic::UndeclaredChrRom { size } => {
+                write!(
+                    f,
+                    "header declares no CHR-ROM but {} bytes of it follow PRG-ROM",
+                    size
+                )
+            }
+            Diagnostic::TrailingData { expected, actual } => write!(
+                f,
+                "file is {} bytes, {} longer than the {} the header accounts for",
+                actual,
+                actual - expected,
+                expected
+            ),
+            Diagnostic::MissingData { expected, actual } => write!(
+                f,
+                "file is {} bytes, {} shorter than the {} the header claims",
+                actual,
+                expected - actual,
+                expected
+            ),
+            Diagnostic::SectionSize {
+                section,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} is {} bytes but the header declares {}",
+                section, actual, expected
+            ),
+        }
+    }
+}
+
+/// Outcome of [`repair`].
+#[derive(Debug)]
+pub struct Repair {
+    pub ines: Ines,
+    /// Problems that were fixed in `ines`
+    pub fixed: Vec<Diagnostic>,
+    /// Problems that are still present
+    pub remaining: Vec<Diagnostic>,
+}
+
+/// Checks that only need the header.
+fn check_header(header: &InesHeader, diagnostics: &mut Vec<Diagnostic>) {
+    if header.format() == HeaderFormat::Archaic {
+        let bytes = [
+            header.flags7,
+            header.flags8,
+            header.flags9,
+            header.flags10,
+            header.flags11,
+            header.flags12,
+            header.flags13,
+            header.flags14,
+            header.flags15,
+        ];
+        diagnostics.push(Diagnostic::ReservedGarbage { bytes });
+    }
+
+    let mapper = header.mapper_number();
+    if header.has_trainer() && !TRAINER_MAPPERS.contains(&mapper) {
+        diagnostics.push(Diagnostic::UnexpectedTrainer { mapper });
+    }
+
+    if header.prg_rom_size() == Some(0) {
+        diagnostics.push(Diagnostic::EmptyPrgRom);
+    }
+}
+
+/// Header without the garbage an archaic header carries in bytes 7-15.
+fn clean_header(header: &InesHeader) -> InesHeader {
+    if header.format() != HeaderFormat::Archaic {
+        return header.clone();
+    }
+
+    InesHeader {
+        prg_size: header.prg_size,
+        chr_size: header.chr_size,
+        mapper: header.mapper,
+        ..Default::default()
+    }
+}
+
+/// Checks a raw image, including how its length compares to the header.
+/// Returns an error only when there's no iNES header to check.
+pub fn check(bytes: &[u8]) -> Result<Vec<Diagnostic>> {
+    let mut raw = [0u8; HEADER_SIZE];
+    let header = bytes
+        .get(..HEADER_SIZE)
+        .and_then(|header| {
+            raw.copy_from_slice(header);
+            InesHeader::from_bytes(&raw)
+        })
+        .ok_or_else(|| {
+            let mut found = [0u8; 4];
+            let len = bytes.len().min(4);
+            found[..len].copy_from_slice(&bytes[..len]);
+            InesError::BadMagic { offset: 0, found }
+        })?;
+
+    let mut diagnostics = Vec::new();
+    check_header(&header, &mut diagnostics);
+
+    // sizes from a cleaned header, so garbage doesn't turn it into NES 2.0
+    let clean = clean_header(&header);
+    let (prg, chr) = match (clean.prg_rom_size(), clean.chr_rom_size()) {
+        (Some(prg), Some(chr)) => (prg, chr),
+        _ => return Ok(diagnostics),
+    };
+    let trainer = if header.has_trainer() {
+        TRAINER_SIZE
+    } else {
+        0
+    };
+    let expected = HEADER_SIZE + trainer + prg + chr;
+    let actual = bytes.len();
+
+    // optional sections that may legitimately follow
+    let mut allowed = 0;
+    if clean.console_type() == ConsoleType::PlayChoice10 {
+        allowed = INST_ROM_SIZE + PROM_SIZE;
+    }
+    if clean.nes2().is_some_and(|nes2| nes2.misc_roms > 0) {
+        allowed = usize::MAX;
+    }
+
+    if actual < expected {
+        diagnostics.push(Diagnostic::MissingData { expected, actual });
+    } else if actual - expected > allowed {
+        // only what's past the optional sections can be undeclared CHR-ROM
+        let extra = actual - expected - allowed;
+        if chr == 0 && extra.is_multiple_of(CHR_ROM_UNIT) {
+            diagnostics.push(Diagnostic::UndeclaredChrRom { size: extra });
+        } else {
+            diagnostics.push(Diagnostic::TrailingData {
+                expected: expected + allowed,
+                actual,
+            });
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+impl Ines {
+    /// Checks the header, and that each section matches the size the header
+    /// declares for it.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        check_header(&self.header, &mut diagnostics);
+
+        let clean = clean_header(&self.header);
+        let trainer = if self.header.has_trainer() {
+            TRAINER_SIZE
+        } else {
+            0
+        };
+        let sections = [
+            (
+                Section::Trainer,
+                Some(trainer),
+                self.trainer.as_ref().map_or(0, Vec::len),
+            ),
+            (Section::PrgRom, clean.prg_rom_size(), self.prg.len()),
+            (
+                Section::ChrRom,
+                clean.chr_rom_size(),
+                self.chr.as_ref().map_or(0, Vec::len),
+            ),
+        ];
+        for &(section, expected, actual) in &sections {
+            match expected {
+                Some(expected) if expected != actual => diagnostics.push(Diagnostic::SectionSize {
+                    section,
+                    expected,
+                    actual,
+                }),
+                _ => {}
+            }
+        }
+
+        diagnostics
+    }
+}
+
+/// Checks a raw image and loads it with every repairable problem fixed:
+/// garbage in bytes 7-15 is cleared, undeclared CHR-ROM is declared, and
+/// trailing data is dropped.
+pub fn repair(bytes: &[u8]) -> Result<Repair> {
+    let diagnostics = check(bytes)?;
+    let mut raw = [0u8; HEADER_SIZE];
+    raw.copy_from_slice(&bytes[..HEADER_SIZE]);
+    let header = InesHeader::from_bytes(&raw).expect("check validated the magic");
+
+    let mut builder = InesHeaderBuilder::from_header(&clean_header(&header));
+    let mut len = bytes.len();
+    for diagnostic in &diagnostics {
+        match diagnostic {
+            Diagnostic::UndeclaredChrRom { size } => {
+                builder.chr_rom_size(*size)?;
+            }
+            Diagnostic::TrailingData { expected, .. } => len = *expected,
+            _ => {}
+        }
+    }
+
+    let mut image = builder.build().to_bytes()?;
+    image.extend_from_slice(&bytes[HEADER_SIZE..len]);
+    let ines = Ines::from_bytes(&image)?;
+
+    let (fixed, remaining) = diagnostics.into_iter().partition(Diagnostic::is_repairable);
+    Ok(Repair {
+        ines,
+        fixed,
+        remaining,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(header: &InesHeader, len: usize) -> Vec<u8> {
+        let mut bytes = header.to_bytes().unwrap();
+        bytes.resize(HEADER_SIZE + len, 0xEA);
+        bytes
+    }
+
+    #[test]
+    fn test_check_clean() {
+        let header = InesHeader::builder().prg_size(2).chr_size(1).build();
+        assert_eq!(check(&image(&header, 40960)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_check_diskdude() {
+        let mut raw = [0u8; HEADER_SIZE];
+        raw[..4].copy_from_slice(b"NES\x1a");
+        raw[4] = 1;
+        raw[6] = 0x10;
+        raw[7..].copy_from_slice(b"DiskDude!");
+        let header = InesHeader::from_bytes(&raw).unwrap();
+        let bytes = image(&header, 16384);
+
+        let diagnostics = check(&bytes).unwrap();
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::ReservedGarbage {
+                bytes: *b"DiskDude!"
+            }]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "garbage in header bytes 7-15: \"DiskDude!\""
+        );
+
+        let repaired = repair(&bytes).unwrap();
+        assert_eq!(repaired.fixed.len(), 1);
+        assert!(repaired.remaining.is_empty());
+        assert_eq!(repaired.ines.header.format(), HeaderFormat::Ines);
+        assert_eq!(repaired.ines.header.mapper_number(), 1);
+        assert_eq!(repaired.ines.validate(), vec![]);
+    }
+
+    #[test]
+    fn test_check_sizes() {
+        let header = InesHeader::builder().prg_size(1).build();
+        assert_eq!(
+            check(&image(&header, 16384 + 16384)).unwrap(),
+            vec![Diagnostic::UndeclaredChrRom { size: 16384 }]
+        );
+        assert_eq!(
+            check(&image(&header, 16384 + 100)).unwrap(),
+            vec![Diagnostic::TrailingData {
+                expected: 16400,
+                actual: 16500
+            }]
+        );
+        assert_eq!(
+            check(&image(&header, 8192)).unwrap(),
+            vec![Diagnostic::MissingData {
+                expected: 16400,
+                actual: 8208
+            }]
+        );
+
+        let repaired = repair(&image(&header, 16384 + 8192)).unwrap();
+        assert_eq!(repaired.ines.chr.as_ref().map(Vec::len), Some(8192));
+        let repaired = repair(&image(&header, 16384 + 100)).unwrap();
+        assert_eq!(repaired.ines.prg.len(), 16384);
+        assert!(repair(&image(&header, 8192)).is_err());
+    }
+
+    #[test]
+    fn test_check_optional_sections() {
+        // a PlayChoice-10 image with its INST-ROM but no PROM
+        let header = InesHeader::builder()
+            .prg_size(1)
+            .console_type(ConsoleType::PlayChoice10)
+            .build();
+        let bytes = image(&header, 16384 + INST_ROM_SIZE);
+        assert_eq!(check(&bytes).unwrap(), vec![]);
+        let repaired = repair(&bytes).unwrap();
+        assert_eq!(repaired.ines.chr, None);
+        assert_eq!(repaired.ines.to_bytes().unwrap(), bytes);
+
+        // NES 2.0 misc ROMs that happen to fill whole CHR banks
+        let header = InesHeader::builder().prg_size(1).misc_roms(1).build();
+        let bytes = image(&header, 16384 + 2 * CHR_ROM_UNIT);
+        assert_eq!(check(&bytes).unwrap(), vec![]);
+        let repaired = repair(&bytes).unwrap();
+        assert_eq!(repaired.ines.chr, None);
+        assert_eq!(repaired.ines.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_check_trainer() {
+        let header = InesHeader::builder().trainer(true).mapper(4).build();
+        let diagnostics = check(&image(&header, TRAINER_SIZE + 16384)).unwrap();
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::UnexpectedTrainer { mapper: 4 }]
+        );
+        assert_eq!(diagnostics[0].severity(), Severity::Warning);
+
+        let header = InesHeader::builder().trainer(true).mapper(6).build();
+        assert_eq!(
+            check(&image(&header, TRAINER_SIZE + 16384)).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_ines_validate() {
+        let ines = Ines {
+            header: InesHeader::builder().prg_size(0).chr_size(1).build(),
+            prg: vec![0; 100],
+            ..Default::default()
+        };
+        assert_eq!(
+            ines.validate(),
+            vec![
+                Diagnostic::EmptyPrgRom,
+                Diagnostic::SectionSize {
+                    section: Section::PrgRom,
+                    expected: 0,
+                    actual: 100
+                },
+                Diagnostic::SectionSize {
+                    section: Section::ChrRom,
+                    expected: 8192,
+                    actual: 0
+                },
+            ]
+        );
+    }
+}
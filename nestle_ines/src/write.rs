@@ -0,0 +1,20 @@
+use std::io::{self, Write};
+
+/// Serializes a ROM model back into its file representation. Parsing a
+/// well-formed file and writing it back produces identical bytes.
+pub trait BinWrite {
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+
+    /// The file's bytes. Fails like [`BinWrite::write_to`] when the model
+    /// can't be written, such as a section whose length doesn't match the
+    /// header.
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
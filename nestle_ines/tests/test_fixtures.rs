@@ -1,27 +1,93 @@
-use anyhow::Result;
-use binread::BinReaderExt;
 use nestle_ines::model::Ines;
-use std::fs::File;
+use nestle_ines::{BinWrite, InesRef, LoadOptions, Result, RomDb};
+use std::fs;
 use std::path::{Path, PathBuf};
 
 fn execute_bin(path: &Path) -> Result<()> {
     dbg!(path);
-    let mut reader = File::open(path)?;
-    let ines: Ines = reader.read_le().unwrap();
+    let ines = Ines::from_path(path)?;
     println!("prg: {}", ines.prg.len());
+
+    let bytes = fs::read(path)?;
+    assert_eq!(ines.to_bytes().unwrap(), bytes);
+    assert_eq!(InesRef::parse(&bytes)?, ines.view());
+
+    let db = RomDb::bundled();
+    let correction = db.correct(&ines.view())?.unwrap();
+    assert!(!correction.is_needed(), "{:?}", correction.differences);
+    assert_eq!(correction.header, ines.header);
     Ok(())
 }
 
 #[test]
 fn test_main() -> Result<()> {
     let dir = env!("CARGO_MANIFEST_DIR");
-    let fixtures = PathBuf::from(dir).join("fixtures");
+    let fixtures = PathBuf::from(dir).join("../fixtures");
 
-    for bin in fixtures.read_dir().unwrap() {
-        if let Ok(bin) = bin {
-            execute_bin(&bin.path())?;
-        }
+    for bin in fixtures.read_dir().unwrap().flatten() {
+        execute_bin(&bin.path())?;
     }
 
     Ok(())
 }
+
+#[test]
+fn test_include_bytes() -> Result<()> {
+    let ines = Ines::from_bytes(include_bytes!("../../fixtures/1.Branch_Basics.nes"))?;
+    assert_eq!(ines.prg.len(), 16384);
+    Ok(())
+}
+
+#[test]
+fn test_auto_patch() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("nestle_ines_patch_{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let rom = dir.join("branch.nes");
+    fs::copy("../fixtures/1.Branch_Basics.nes", &rom)?;
+
+    // replace the first two PRG bytes, which sit right after the header
+    let mut ips = b"PATCH".to_vec();
+    ips.extend(&[0x00, 0x00, 0x10, 0x00, 0x02, 0xEA, 0xEA]);
+    ips.extend(b"EOF");
+    fs::write(rom.with_extension("ips"), ips)?;
+
+    let plain = LoadOptions::new().load(&rom)?;
+    let patched = LoadOptions::new().auto_patch(true).load(&rom)?;
+    fs::remove_dir_all(&dir)?;
+
+    assert_ne!(&plain.prg[..2], &[0xEA, 0xEA]);
+    assert_eq!(&patched.prg[..2], &[0xEA, 0xEA]);
+    assert_eq!(&patched.prg[2..], &plain.prg[2..]);
+    Ok(())
+}
+
+#[test]
+fn test_archive() -> Result<()> {
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    let dir = std::env::temp_dir().join(format!("nestle_ines_archive_{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let rom = fs::read("../fixtures/1.Branch_Basics.nes")?;
+
+    let mut writer = ZipWriter::new(fs::File::create(dir.join("roms.zip"))?);
+    let options = FileOptions::default();
+    writer.start_file("branch.nes", options).unwrap();
+    writer.write_all(&rom)?;
+    writer.start_file("broken.fds", options).unwrap();
+    writer.write_all(b"garbage")?;
+    writer.finish().unwrap();
+
+    let path = dir.join("roms.zip");
+    let loaded = LoadOptions::new().member("branch.nes").load(&path);
+    let err = Ines::from_path(&path).unwrap_err();
+    let broken = LoadOptions::new().member("broken.fds").load(&path);
+    fs::remove_dir_all(&dir)?;
+
+    assert_eq!(loaded?.to_bytes().unwrap(), rom);
+    assert!(err.to_string().contains("branch.nes"), "{}", err);
+    let broken = broken.unwrap_err();
+    assert_eq!(broken.member(), Some("broken.fds"));
+    assert_eq!(broken.path(), Some(path.as_path()));
+    Ok(())
+}
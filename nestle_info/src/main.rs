@@ -0,0 +1,320 @@
+//! Prints everything nestle_ines knows about ROM files.
+//!
+//! ```text
+//! nestle-info [--json] [--member NAME] FILE...
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use nestle_ines::model::{ConsoleType, HeaderFormat, Timing};
+use nestle_ines::validate::{check, Severity};
+use nestle_ines::{Format, LoadOptions, PrgBankSize, Rom, RomDb, RomHashes};
+
+const USAGE: &str = "usage: nestle-info [--json] [--member NAME] FILE...";
+
+struct Args {
+    json: bool,
+    member: Option<String>,
+    paths: Vec<PathBuf>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        json: false,
+        member: None,
+        paths: Vec::new(),
+    };
+
+    let mut iter = env::args_os().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.to_str() {
+            Some("--json") => args.json = true,
+            Some("--member") => {
+                let member = iter
+                    .next()
+                    .context("--member needs an archive member name")?;
+                args.member = Some(member.to_string_lossy().into_owned());
+            }
+            Some("-h") | Some("--help") => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            Some(flag) if flag.starts_with("--") => bail!("unknown option {}\n{}", flag, USAGE),
+            _ => args.paths.push(arg.into()),
+        }
+    }
+
+    if args.paths.is_empty() {
+        bail!(USAGE);
+    }
+    Ok(args)
+}
+
+/// CPU vectors, read from the end of the last 8 KiB PRG bank which nearly
+/// every mapper keeps at $E000-$FFFF.
+struct Vectors {
+    nmi: u16,
+    reset: u16,
+    irq: u16,
+}
+
+fn vectors(prg: &[u8]) -> Option<Vectors> {
+    let bank = nestle_ines::Banks::new(prg, PrgBankSize::Size8K.bytes()).last()?;
+    let word = |at: usize| u16::from_le_bytes([bank[at], bank[at + 1]]);
+    Some(Vectors {
+        nmi: word(0x1FFA),
+        reset: word(0x1FFC),
+        irq: word(0x1FFE),
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Describes a ROM as JSON, which the text output is rendered from too.
+fn inspect(path: &Path, options: &LoadOptions, db: &RomDb) -> Result<Value> {
+    let bytes = options.read(path)?;
+    let rom = Rom::from_bytes(&bytes)?;
+    let format = rom.format();
+    let unif_board = match &rom {
+        Rom::Unif(unif) => Some(unif.board.clone()),
+        _ => None,
+    };
+    let ines = rom.into_ines()?;
+    let header = &ines.header;
+    let nes2 = header.nes2();
+    let hashes = RomHashes::of(&ines.view());
+    let entry = db.lookup(&hashes);
+
+    // flags 9 of an archaic header is as likely to be a dumper's signature
+    let timing = match (&nes2, header.format()) {
+        (Some(nes2), _) => Some(nes2.timing),
+        (None, HeaderFormat::Ines) if header.flags9 & 1 != 0 => Some(Timing::Pal),
+        (None, HeaderFormat::Ines) => Some(Timing::Ntsc),
+        (None, _) => None,
+    };
+    let console = match header.console_type() {
+        ConsoleType::Extended(kind) => format!("Extended({})", kind),
+        console => format!("{:?}", console),
+    };
+    let board = unif_board.or_else(|| entry.map(|entry| entry.board.clone()));
+    let checksum = |checksum: nestle_ines::db::Checksum| {
+        json!({
+            "crc32": format!("{:08x}", checksum.crc32),
+            "sha1": hex(&checksum.sha1),
+        })
+    };
+    // the file's length is only known from its bytes, and other formats
+    // have no iNES header to check
+    let mut diagnostics = match format {
+        Format::Ines | Format::Nes2 => check(&bytes)?,
+        _ => Vec::new(),
+    };
+    for diagnostic in ines.validate() {
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    let diagnostics: Vec<Value> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            json!({
+                "severity": match diagnostic.severity() {
+                    Severity::Warning => "warning",
+                    Severity::Error => "error",
+                },
+                "message": diagnostic.to_string(),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "path": path.display().to_string(),
+        "format": format.to_string(),
+        "mapper": header.mapper_number(),
+        "submapper": nes2.as_ref().map(|nes2| nes2.submapper),
+        "board": board,
+        "name": entry.map(|entry| entry.name.clone()),
+        "console": console,
+        "prg_rom_size": ines.prg.len(),
+        "chr_rom_size": ines.chr.as_ref().map_or(0, Vec::len),
+        "prg_ram_size": nes2.as_ref().map(|nes2| nes2.prg_ram_size),
+        "prg_nvram_size": nes2.as_ref().map(|nes2| nes2.prg_nvram_size),
+        "chr_ram_size": nes2.as_ref().map(|nes2| nes2.chr_ram_size),
+        "chr_nvram_size": nes2.as_ref().map(|nes2| nes2.chr_nvram_size),
+        "mirroring": format!("{:?}", header.mirroring()),
+        "battery": header.has_battery(),
+        "trainer": header.has_trainer(),
+        "region": timing.map(|timing| format!("{:?}", timing)),
+        "hashes": {
+            "prg": checksum(hashes.prg),
+            "chr": checksum(hashes.chr),
+            "rom": checksum(hashes.rom),
+        },
+        "vectors": vectors(&ines.prg).map(|vectors| json!({
+            "nmi": vectors.nmi,
+            "reset": vectors.reset,
+            "irq": vectors.irq,
+        })),
+        "diagnostics": diagnostics,
+    }))
+}
+
+fn print_text(info: &Value) {
+    let field = |name: &str| match &info[name] {
+        Value::Null => "unknown".to_string(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    let size = |name: &str| match info[name].as_u64() {
+        Some(bytes) if bytes >= 1024 && bytes % 1024 == 0 => format!("{} KiB", bytes / 1024),
+        Some(bytes) => format!("{} bytes", bytes),
+        None => "unknown".to_string(),
+    };
+    let flag = |name: &str| if info[name] == true { "yes" } else { "no" };
+
+    println!("{}", field("path"));
+    println!("  format:     {}", field("format"));
+    println!("  name:       {}", field("name"));
+    println!(
+        "  mapper:     {} (submapper {})",
+        field("mapper"),
+        field("submapper")
+    );
+    println!("  board:      {}", field("board"));
+    println!("  console:    {}", field("console"));
+    println!("  region:     {}", field("region"));
+    println!("  PRG-ROM:    {}", size("prg_rom_size"));
+    println!("  CHR-ROM:    {}", size("chr_rom_size"));
+    println!(
+        "  PRG-RAM:    {} + {} battery-backed",
+        size("prg_ram_size"),
+        size("prg_nvram_size")
+    );
+    println!(
+        "  CHR-RAM:    {} + {} battery-backed",
+        size("chr_ram_size"),
+        size("chr_nvram_size")
+    );
+    println!("  mirroring:  {}", field("mirroring"));
+    println!("  battery:    {}", flag("battery"));
+    println!("  trainer:    {}", flag("trainer"));
+
+    for section in &["prg", "chr", "rom"] {
+        let hashes = &info["hashes"][section];
+        println!(
+            "  {} hash:   {} {}",
+            section.to_uppercase(),
+            hashes["crc32"].as_str().unwrap_or_default(),
+            hashes["sha1"].as_str().unwrap_or_default()
+        );
+    }
+
+    match info["vectors"].as_object() {
+        Some(vectors) => {
+            let vector = |name: &str| vectors[name].as_u64().unwrap_or_default();
+            println!(
+                "  vectors:    NMI ${:04X}  RESET ${:04X}  IRQ ${:04X}",
+                vector("nmi"),
+                vector("reset"),
+                vector("irq")
+            );
+        }
+        None => println!("  vectors:    PRG-ROM too small"),
+    }
+
+    for diagnostic in info["diagnostics"].as_array().into_iter().flatten() {
+        println!(
+            "  {}: {}",
+            diagnostic["severity"].as_str().unwrap_or_default(),
+            diagnostic["message"].as_str().unwrap_or_default()
+        );
+    }
+}
+
+fn run(args: &Args) -> Result<bool> {
+    let mut options = LoadOptions::new();
+    if let Some(member) = &args.member {
+        options.member(member);
+    }
+    let db = RomDb::bundled();
+
+    let mut ok = true;
+    let mut infos = Vec::new();
+    for path in &args.paths {
+        match inspect(path, &options, &db) {
+            Ok(info) if args.json => infos.push(info),
+            Ok(info) => print_text(&info),
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                ok = false;
+            }
+        }
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&infos)?);
+    }
+    Ok(ok)
+}
+
+fn main() {
+    let result = parse_args().and_then(|args| run(&args));
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nestle_ines::CartridgeBuilder;
+    use std::fs;
+
+    #[test]
+    fn test_inspect() {
+        let dir = std::env::temp_dir().join(format!("nestle_info_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut bytes = CartridgeBuilder::new()
+            .reset_vector(0x8000)
+            .to_bytes()
+            .unwrap();
+        bytes.extend_from_slice(&[0; 100]);
+        let path = dir.join("trailing.nes");
+        fs::write(&path, &bytes).unwrap();
+
+        let info = inspect(&path, &LoadOptions::new(), &RomDb::bundled()).unwrap();
+        assert_eq!(info["format"], "iNES");
+        assert_eq!(info["mapper"], 0);
+        assert_eq!(info["region"], "Ntsc");
+        assert_eq!(info["vectors"]["reset"], 0x8000);
+        let diagnostics = info["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["severity"], "warning");
+        assert!(diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("100 longer"));
+
+        // byte 9 of the signature is 's', which mustn't read as PAL
+        bytes[7..16].copy_from_slice(b"DiskDude!");
+        let path = dir.join("archaic.nes");
+        fs::write(&path, &bytes).unwrap();
+        let info = inspect(&path, &LoadOptions::new(), &RomDb::bundled()).unwrap();
+        assert_eq!(info["region"], Value::Null);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}